@@ -0,0 +1,604 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+use super::dag::{Node, Store, Term};
+use super::eval::{double_factorial, eval_with, gamma, to_f64};
+use super::expr::{Const, Expr, Func};
+use super::function::Registry;
+
+// How many points `CompiledFn::call_batch` runs each instruction over.
+const LANES: usize = 64;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum Unary {
+    Sin,
+    Cos,
+    Tan,
+    ASin,
+    ACos,
+    ATan,
+    Ln,
+    Sqrt,
+    Cbrt,
+    Factorial,
+    DoubleFactorial,
+    Gamma,
+    Abs,
+    Floor,
+    Ceil,
+    Round,
+    Sign,
+    Frac,
+}
+
+impl Unary {
+    fn apply(self, x: f64) -> f64 {
+        match self {
+            Unary::Sin => x.sin(),
+            Unary::Cos => x.cos(),
+            Unary::Tan => x.tan(),
+            Unary::ASin => x.asin(),
+            Unary::ACos => x.acos(),
+            Unary::ATan => x.atan(),
+            Unary::Ln => x.ln(),
+            Unary::Sqrt => x.sqrt(),
+            Unary::Cbrt => x.cbrt(),
+            Unary::Factorial => gamma(x + 1.0),
+            Unary::DoubleFactorial => double_factorial(x),
+            Unary::Gamma => gamma(x),
+            Unary::Abs => x.abs(),
+            Unary::Floor => x.floor(),
+            Unary::Ceil => x.ceil(),
+            Unary::Round => x.round(),
+            Unary::Sign if x == 0.0 || x.is_nan() => x,
+            Unary::Sign => x.signum(),
+            Unary::Frac => x - x.floor(),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Unary::Sin => "sin",
+            Unary::Cos => "cos",
+            Unary::Tan => "tan",
+            Unary::ASin => "asin",
+            Unary::ACos => "acos",
+            Unary::ATan => "atan",
+            Unary::Ln => "log",
+            Unary::Sqrt => "sqrt",
+            Unary::Cbrt => "cbrt",
+            Unary::Factorial => "factorial",
+            Unary::DoubleFactorial => "factorial2",
+            Unary::Gamma => "gamma",
+            Unary::Abs => "abs",
+            Unary::Floor => "floor",
+            Unary::Ceil => "ceil",
+            Unary::Round => "round",
+            Unary::Sign => "sign",
+            Unary::Frac => "frac",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum Binary {
+    // `log(b, x)`, the logarithm of `x` to base `b`.
+    Log,
+    // `root(x, n)`
+    Root,
+    // `mod(x, m)`, which has the sign of `m`.
+    Mod,
+}
+
+impl Binary {
+    fn apply(self, x: f64, y: f64) -> f64 {
+        match self {
+            Binary::Log => y.log(x),
+            Binary::Root => x.powf(1.0 / y),
+            // As in `eval`, a finite `x` is its own remainder by an infinity
+            // of the same sign, and by one of the other sign it is that
+            // infinity.
+            Binary::Mod if x.is_finite() && y.is_infinite() => {
+                if x == 0.0 || (x < 0.0) == (y < 0.0) { x } else { y }
+            },
+            Binary::Mod => x - y * (x / y).floor(),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Binary::Log => "log",
+            Binary::Root => "root",
+            Binary::Mod => "mod",
+        }
+    }
+}
+
+// An instruction. The operands are values while compiling and registers
+// once the registers are allocated.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum Op {
+    Add(usize, usize),
+    Sub(usize, usize),
+    Mul(usize, usize),
+    Div(usize, usize),
+    Pow(usize, usize),
+    Powi(usize, i32),
+    Neg(usize),
+    Recip(usize),
+    Unary(Unary, usize),
+    Binary(Binary, usize, usize),
+}
+
+impl Op {
+    fn map_operands<F: FnMut(usize) -> usize>(self, mut f: F) -> Op {
+        match self {
+            Op::Add(a, b) => Op::Add(f(a), f(b)),
+            Op::Sub(a, b) => Op::Sub(f(a), f(b)),
+            Op::Mul(a, b) => Op::Mul(f(a), f(b)),
+            Op::Div(a, b) => Op::Div(f(a), f(b)),
+            Op::Pow(a, b) => Op::Pow(f(a), f(b)),
+            Op::Powi(a, n) => Op::Powi(f(a), n),
+            Op::Neg(a) => Op::Neg(f(a)),
+            Op::Recip(a) => Op::Recip(f(a)),
+            Op::Unary(fun, a) => Op::Unary(fun, f(a)),
+            Op::Binary(fun, a, b) => Op::Binary(fun, f(a), f(b)),
+        }
+    }
+
+    fn operands(self) -> Vec<usize> {
+        let mut operands = Vec::with_capacity(2);
+        self.map_operands(|a| {
+            operands.push(a);
+            a
+        });
+        operands
+    }
+
+    // The result, reading the operands with `get`.
+    fn apply<G: Fn(usize) -> f64>(self, get: G) -> f64 {
+        match self {
+            Op::Add(a, b) => get(a) + get(b),
+            Op::Sub(a, b) => get(a) - get(b),
+            Op::Mul(a, b) => get(a) * get(b),
+            Op::Div(a, b) => get(a) / get(b),
+            Op::Pow(a, b) => get(a).powf(get(b)),
+            Op::Powi(a, n) => get(a).powi(n),
+            Op::Neg(a) => -get(a),
+            Op::Recip(a) => 1.0 / get(a),
+            Op::Unary(f, a) => f.apply(get(a)),
+            Op::Binary(f, a, b) => f.apply(get(a), get(b)),
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Op::Add(a, b) => write!(f, "add r{} r{}", a, b),
+            Op::Sub(a, b) => write!(f, "sub r{} r{}", a, b),
+            Op::Mul(a, b) => write!(f, "mul r{} r{}", a, b),
+            Op::Div(a, b) => write!(f, "div r{} r{}", a, b),
+            Op::Pow(a, b) => write!(f, "pow r{} r{}", a, b),
+            Op::Powi(a, n) => write!(f, "powi r{} {}", a, n),
+            Op::Neg(a) => write!(f, "neg r{}", a),
+            Op::Recip(a) => write!(f, "recip r{}", a),
+            Op::Unary(fun, a) => write!(f, "{} r{}", fun.name(), a),
+            Op::Binary(fun, a, b) => write!(f, "{} r{} r{}", fun.name(), a, b),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Instr {
+    op: Op,
+    dst: usize,
+}
+
+/// An expression compiled by `compile` into instructions on `f64`
+/// registers, for evaluating it at many points.
+///
+/// The first registers hold the variables, in the order given to
+/// `compile`, and the next ones the constants. `Display` lists the
+/// instructions.
+#[derive(Debug, Clone)]
+pub struct CompiledFn {
+    vars: Vec<String>,
+    // How many registers after the variables hold constants.
+    consts: usize,
+    code: Vec<Instr>,
+    // The registers before a run, with the constants in place.
+    init: Vec<f64>,
+    result: usize,
+}
+
+impl CompiledFn {
+    /// The variables, in the order their values are passed.
+    pub fn vars(&self) -> &[String] {
+        &self.vars
+    }
+
+    /// The number of instructions.
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+
+    /// The value at `args`, which has one value for each variable.
+    ///
+    /// # Panics
+    ///
+    /// If `args` does not have one value for each variable.
+    pub fn call(&self, args: &[f64]) -> f64 {
+        assert_eq!(args.len(), self.vars.len(), "Expected {} arguments", self.vars.len());
+        let mut regs = self.init.clone();
+        regs[..args.len()].copy_from_slice(args);
+        for instr in &self.code {
+            regs[instr.dst] = instr.op.apply(|r| regs[r]);
+        }
+        regs[self.result]
+    }
+
+    /// The values at `points` points. `columns` has the values of each
+    /// variable in turn, each of length `points`.
+    ///
+    /// # Panics
+    ///
+    /// If there is not one column for each variable, or a column is not of
+    /// length `points`.
+    pub fn call_batch(&self, columns: &[&[f64]], points: usize) -> Vec<f64> {
+        let mut out = vec![0.0; points];
+        self.call_batch_into(columns, &mut out);
+        out
+    }
+
+    /// Like `call_batch`, writing the values to `out`, which has one value
+    /// for each point.
+    pub fn call_batch_into(&self, columns: &[&[f64]], out: &mut [f64]) {
+        assert_eq!(columns.len(), self.vars.len(), "Expected {} columns", self.vars.len());
+        assert!(columns.iter().all(|c| c.len() == out.len()), "Expected columns of length {}", out.len());
+        let mut regs: Vec<[f64; LANES]> = self.init.iter().map(|&x| [x; LANES]).collect();
+        for (start, out) in (0..).step_by(LANES).zip(out.chunks_mut(LANES)) {
+            let n = out.len();
+            for (reg, column) in regs.iter_mut().zip(columns) {
+                reg[..n].copy_from_slice(&column[start..start + n]);
+            }
+            for instr in &self.code {
+                run(instr, &mut regs);
+            }
+            out.copy_from_slice(&regs[self.result][..n]);
+        }
+    }
+}
+
+// Runs `instr` on every lane. The operands are copied first, since the
+// destination can be one of them.
+fn run(instr: &Instr, regs: &mut [[f64; LANES]]) {
+    fn each<F: Fn(f64) -> f64>(dst: &mut [f64; LANES], x: [f64; LANES], f: F) {
+        for (d, x) in dst.iter_mut().zip(x) {
+            *d = f(x);
+        }
+    }
+    fn each2<F: Fn(f64, f64) -> f64>(dst: &mut [f64; LANES], x: [f64; LANES], y: [f64; LANES], f: F) {
+        for ((d, x), y) in dst.iter_mut().zip(x).zip(y) {
+            *d = f(x, y);
+        }
+    }
+
+    let operands = |a: usize, b: usize| (regs[a], regs[b]);
+    match instr.op {
+        Op::Add(a, b) => {
+            let (x, y) = operands(a, b);
+            each2(&mut regs[instr.dst], x, y, |x, y| x + y)
+        },
+        Op::Sub(a, b) => {
+            let (x, y) = operands(a, b);
+            each2(&mut regs[instr.dst], x, y, |x, y| x - y)
+        },
+        Op::Mul(a, b) => {
+            let (x, y) = operands(a, b);
+            each2(&mut regs[instr.dst], x, y, |x, y| x * y)
+        },
+        Op::Div(a, b) => {
+            let (x, y) = operands(a, b);
+            each2(&mut regs[instr.dst], x, y, |x, y| x / y)
+        },
+        Op::Pow(a, b) => {
+            let (x, y) = operands(a, b);
+            each2(&mut regs[instr.dst], x, y, f64::powf)
+        },
+        Op::Binary(f, a, b) => {
+            let (x, y) = operands(a, b);
+            each2(&mut regs[instr.dst], x, y, |x, y| f.apply(x, y))
+        },
+        Op::Powi(a, n) => {
+            let x = regs[a];
+            each(&mut regs[instr.dst], x, |x| x.powi(n))
+        },
+        Op::Neg(a) => {
+            let x = regs[a];
+            each(&mut regs[instr.dst], x, |x| -x)
+        },
+        Op::Recip(a) => {
+            let x = regs[a];
+            each(&mut regs[instr.dst], x, |x| 1.0 / x)
+        },
+        Op::Unary(f, a) => {
+            let x = regs[a];
+            each(&mut regs[instr.dst], x, |x| f.apply(x))
+        },
+    }
+}
+
+impl fmt::Display for CompiledFn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, var) in self.vars.iter().enumerate() {
+            writeln!(f, "r{} = {}", i, var)?;
+        }
+        for (i, x) in self.init.iter().enumerate().skip(self.vars.len()).take(self.consts) {
+            writeln!(f, "r{} = {}", i, x)?;
+        }
+        for instr in &self.code {
+            writeln!(f, "r{} = {}", instr.dst, instr.op)?;
+        }
+        write!(f, "return r{}", self.result)
+    }
+}
+
+// What a value computed while compiling is.
+#[derive(Debug, Copy, Clone)]
+enum Value {
+    Var(usize),
+    Const(f64),
+    Op(Op),
+}
+
+// Lowers shared terms to values, folding constants and reusing the value
+// of any operation that was made before.
+struct Compiler<'a> {
+    vars: &'a [&'a str],
+    values: Vec<Value>,
+    terms: HashMap<Term, usize>,
+    ops: HashMap<Op, usize>,
+    consts: HashMap<u64, usize>,
+}
+
+impl Compiler<'_> {
+    fn constant(&mut self, x: f64) -> usize {
+        let values = &mut self.values;
+        *self.consts.entry(x.to_bits()).or_insert_with(|| {
+            values.push(Value::Const(x));
+            values.len() - 1
+        })
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let op = match op {
+            Op::Add(a, b) if b < a => Op::Add(b, a),
+            Op::Mul(a, b) if b < a => Op::Mul(b, a),
+            op => op,
+        };
+        let values = &self.values;
+        if op.operands().iter().all(|&a| matches!(values[a], Value::Const(_))) {
+            let x = op.apply(|a| match values[a] {
+                Value::Const(x) => x,
+                _ => unreachable!(),
+            });
+            return self.constant(x);
+        }
+        let values = &mut self.values;
+        *self.ops.entry(op).or_insert_with(|| {
+            values.push(Value::Op(op));
+            values.len() - 1
+        })
+    }
+
+    fn term(&mut self, term: &Term) -> Result<usize, String> {
+        if let Some(&value) = self.terms.get(term) {
+            return Ok(value);
+        }
+        let value = match term.node() {
+            Node::Sum(v) => self.fold(v, true)?,
+            Node::Prod(v) => self.fold(v, false)?,
+            Node::Pow(base, exp) => {
+                let base = self.term(base)?;
+                match int_exponent(exp) {
+                    Some(-1) => self.emit(Op::Recip(base)),
+                    Some(n) => self.emit(Op::Powi(base, n)),
+                    None => {
+                        let exp = self.term(exp)?;
+                        self.emit(Op::Pow(base, exp))
+                    },
+                }
+            },
+            Node::Neg(e) => {
+                let e = self.term(e)?;
+                self.emit(Op::Neg(e))
+            },
+            Node::Recipr(e) => {
+                let e = self.term(e)?;
+                self.emit(Op::Recip(e))
+            },
+            Node::Call(fun, args) => self.call(fun, args)?,
+            Node::Const(c) => self.constant(to_f64(*c)),
+            Node::Var(name) => match self.vars.iter().position(|v| **v == **name) {
+                Some(i) => i,
+                None => return Err(format!("Unknown variable {}", name)),
+            },
+            Node::Error => return Err(String::from("Cannot compile an expression with errors")),
+        };
+        self.terms.insert(term.clone(), value);
+        Ok(value)
+    }
+
+    // A sum, where a negated term is subtracted, or a product, where a
+    // reciprocal factor divides.
+    fn fold(&mut self, v: &[Term], sum: bool) -> Result<usize, String> {
+        let mut acc = None;
+        for t in v {
+            let (inverted, t) = match (t.node(), sum) {
+                (Node::Neg(e), true) | (Node::Recipr(e), false) => (true, e),
+                _ => (false, t),
+            };
+            let x = self.term(t)?;
+            let op = match (acc, inverted, sum) {
+                (None, false, _) => {
+                    acc = Some(x);
+                    continue;
+                },
+                (None, true, true) => Op::Neg(x),
+                (None, true, false) => Op::Recip(x),
+                (Some(acc), false, true) => Op::Add(acc, x),
+                (Some(acc), true, true) => Op::Sub(acc, x),
+                (Some(acc), false, false) => Op::Mul(acc, x),
+                (Some(acc), true, false) => Op::Div(acc, x),
+            };
+            acc = Some(self.emit(op));
+        }
+        Ok(match acc {
+            Some(acc) => acc,
+            None => self.constant(if sum { 0.0 } else { 1.0 }),
+        })
+    }
+
+    fn call(&mut self, fun: &Func, args: &[Term]) -> Result<usize, String> {
+        let unary = match fun {
+            Func::Sin => Some(Unary::Sin),
+            Func::Cos => Some(Unary::Cos),
+            Func::Tan => Some(Unary::Tan),
+            Func::ASin => Some(Unary::ASin),
+            Func::ACos => Some(Unary::ACos),
+            Func::ATan => Some(Unary::ATan),
+            Func::Log => Some(Unary::Ln),
+            Func::Sqrt => Some(Unary::Sqrt),
+            Func::Cbrt => Some(Unary::Cbrt),
+            Func::Factorial => Some(Unary::Factorial),
+            Func::DoubleFactorial => Some(Unary::DoubleFactorial),
+            Func::Gamma => Some(Unary::Gamma),
+            Func::Abs => Some(Unary::Abs),
+            Func::Floor => Some(Unary::Floor),
+            Func::Ceil => Some(Unary::Ceil),
+            Func::Round => Some(Unary::Round),
+            Func::Sign => Some(Unary::Sign),
+            Func::Frac => Some(Unary::Frac),
+            _ => None,
+        };
+        let binary = match fun {
+            Func::Log => Some(Binary::Log),
+            Func::Root => Some(Binary::Root),
+            Func::Mod => Some(Binary::Mod),
+            _ => None,
+        };
+        match (unary, binary, args) {
+            (Some(f), _, [x]) => {
+                let x = self.term(x)?;
+                Ok(self.emit(Op::Unary(f, x)))
+            },
+            (_, Some(f), [x, y]) => {
+                let x = self.term(x)?;
+                let y = self.term(y)?;
+                Ok(self.emit(Op::Binary(f, x, y)))
+            },
+            (_, _, args) => Err(format!("Cannot compile a call of {} with {} arguments", fun, args.len())),
+        }
+    }
+}
+
+// The exponent as an `i32` if it is an integer, which the parser gives as
+// `Neg(Int)` when negative.
+fn int_exponent(exp: &Term) -> Option<i32> {
+    match exp.node() {
+        Node::Const(Const::Int(n)) => i32::try_from(*n).ok(),
+        Node::Neg(e) => match e.node() {
+            Node::Const(Const::Int(n)) => n.checked_neg().and_then(|n| i32::try_from(n).ok()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Compiles `expr` for evaluating it numerically at many points, with
+/// `vars` as its parameters. Every variable of `expr` must be one of
+/// `vars`, and its calls must be of builtins.
+///
+/// Constants are folded and an expression that appears several times is
+/// computed once, and the result can be called with `f64`s far faster than
+/// `eval::approx` walks the tree. Undefined results are NaN.
+///
+/// ```
+/// let expr = betadog::parse_str("sin(x)^2 + sin(x)*y + 2*3").unwrap();
+/// let f = betadog::compile(&expr, &["x", "y"]).unwrap();
+/// assert_eq!(f.call(&[0.0, 1.0]), 6.0);
+/// assert_eq!(f.call_batch(&[&[0.0, 0.0], &[1.0, 2.0]], 2), vec![6.0, 6.0]);
+/// ```
+pub fn compile(expr: &Expr, vars: &[&str]) -> Result<CompiledFn, String> {
+    let mut compiler = Compiler{vars, values: Vec::new(), terms: HashMap::new(), ops: HashMap::new(), consts: HashMap::new()};
+    compiler.values.extend((0..vars.len()).map(Value::Var));
+    let result = compiler.term(&Store::new().import(expr))?;
+    Ok(allocate(vars, &compiler.values, result))
+}
+
+/// Like `compile`, after expanding the symbolic functions in `functions`
+/// with `eval::eval_with`.
+pub fn compile_with(expr: &Expr, vars: &[&str], functions: &Registry) -> Result<CompiledFn, String> {
+    compile(&eval_with(expr, functions), vars)
+}
+
+// Gives the values that `result` needs registers, reusing the register of
+// an operation once nothing later reads it. Variables and constants keep
+// theirs.
+fn allocate(vars: &[&str], values: &[Value], result: usize) -> CompiledFn {
+    let mut live = vec![false; values.len()];
+    live[result] = true;
+    for (i, value) in values.iter().enumerate().rev() {
+        if let (true, Value::Op(op)) = (live[i], value) {
+            op.operands().into_iter().for_each(|a| live[a] = true);
+        }
+    }
+    let mut last_use = vec![0; values.len()];
+    for (i, value) in values.iter().enumerate() {
+        if let (true, Value::Op(op)) = (live[i], value) {
+            op.operands().into_iter().for_each(|a| last_use[a] = i);
+        }
+    }
+    last_use[result] = values.len();
+
+    let mut init = vec![0.0; vars.len()];
+    let mut regs = vec![usize::MAX; values.len()];
+    for (i, value) in values.iter().enumerate() {
+        match (live[i], value) {
+            (_, Value::Var(v)) => regs[i] = *v,
+            (true, Value::Const(x)) => {
+                regs[i] = init.len();
+                init.push(*x);
+            },
+            _ => (),
+        }
+    }
+
+    let consts = init.len() - vars.len();
+    let mut code = Vec::new();
+    let mut free = Vec::new();
+    for (i, value) in values.iter().enumerate() {
+        let op = match (live[i], value) {
+            (true, Value::Op(op)) => *op,
+            _ => continue,
+        };
+        for a in op.operands() {
+            if last_use[a] == i && matches!(values[a], Value::Op(_)) && !free.contains(&regs[a]) {
+                free.push(regs[a]);
+            }
+        }
+        regs[i] = free.pop().unwrap_or_else(|| {
+            init.push(0.0);
+            init.len() - 1
+        });
+        code.push(Instr{op: op.map_operands(|a| regs[a]), dst: regs[i]});
+    }
+
+    CompiledFn{vars: vars.iter().map(|v| String::from(*v)).collect(), consts, code, init, result: regs[result]}
+}
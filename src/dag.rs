@@ -0,0 +1,225 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use super::expr::{Const, Expr, Func};
+
+/// A node of a shared expression graph, made by a `Store`. Children are
+/// `Term`s, so a subexpression that appears several times is one node.
+#[derive(Debug)]
+pub enum Node {
+    Sum(Vec<Term>),
+    Prod(Vec<Term>),
+    Pow(Term, Term),
+    Neg(Term),
+    Recipr(Term),
+    Call(Func, Vec<Term>),
+    Const(Const),
+    /// The name is interned: equal names from one store share the string.
+    Var(Rc<str>),
+    Error,
+}
+
+/// A shared, immutable expression whose equal subtrees are stored once.
+///
+/// Terms made by the same `Store` are equal exactly when they are the same
+/// node, so comparing them is a pointer comparison, and each node keeps its
+/// hash. Terms from different stores are never equal.
+///
+/// ```
+/// use betadog::dag::Store;
+///
+/// let mut store = Store::new();
+/// let expr = betadog::parse_str("(x + 1)^2 + sin(x + 1)").unwrap();
+/// let term = store.import(&expr);
+/// // x, 1, x + 1, 2, the power, the call and the sum.
+/// assert_eq!(store.len(), 7);
+/// assert_eq!(betadog::Expr::from(&term), expr);
+/// ```
+#[derive(Clone)]
+pub struct Term(Rc<Shared>);
+
+#[derive(Debug)]
+struct Shared {
+    node: Node,
+    hash: u64,
+}
+
+impl Term {
+    pub fn node(&self) -> &Node {
+        &self.0.node
+    }
+
+    /// The hash of the structure of the term, computed once when it was
+    /// made.
+    pub fn structural_hash(&self) -> u64 {
+        self.0.hash
+    }
+}
+
+impl PartialEq for Term {
+    fn eq(&self, other: &Term) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Term {}
+
+impl Hash for Term {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.0.hash);
+    }
+}
+
+impl fmt::Debug for Term {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.0.node, f)
+    }
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", Expr::from(self))
+    }
+}
+
+/// Makes `Term`s, keeping one node for every distinct subexpression and
+/// one string for every name. Nodes live as long as the store.
+#[derive(Default)]
+pub struct Store {
+    // Nodes by their hash, which rarely collides.
+    nodes: HashMap<u64, Vec<Term>>,
+    names: HashSet<Rc<str>>,
+    len: usize,
+}
+
+impl Store {
+    pub fn new() -> Store {
+        Store::default()
+    }
+
+    /// The number of distinct nodes made so far.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The shared copy of `name`.
+    pub fn intern(&mut self, name: &str) -> Rc<str> {
+        if let Some(name) = self.names.get(name) {
+            return name.clone();
+        }
+        let name: Rc<str> = Rc::from(name);
+        self.names.insert(name.clone());
+        name
+    }
+
+    /// The term for `node`: an existing one if the store has made the same
+    /// node before. Names in `node` need not be interned.
+    pub fn make(&mut self, node: Node) -> Term {
+        let node = match node {
+            Node::Var(name) => Node::Var(self.intern(&name)),
+            node => node,
+        };
+        let hash = hash_node(&node);
+        let bucket = self.nodes.entry(hash).or_default();
+        if let Some(term) = bucket.iter().find(|term| same_node(&term.0.node, &node)) {
+            return term.clone();
+        }
+        let term = Term(Rc::new(Shared{node, hash}));
+        bucket.push(term.clone());
+        self.len += 1;
+        term
+    }
+
+    pub fn var(&mut self, name: &str) -> Term {
+        let name = self.intern(name);
+        self.make(Node::Var(name))
+    }
+
+    pub fn constant(&mut self, c: Const) -> Term {
+        self.make(Node::Const(c))
+    }
+
+    /// The term for `expr`.
+    pub fn import(&mut self, expr: &Expr) -> Term {
+        let node = match expr {
+            Expr::Sum(v) => Node::Sum(self.import_all(v)),
+            Expr::Prod(v) => Node::Prod(self.import_all(v)),
+            Expr::Call(fun, v) => Node::Call(fun.clone(), self.import_all(v)),
+            Expr::Pow(lhs, rhs) => Node::Pow(self.import(lhs), self.import(rhs)),
+            Expr::Neg(e) => Node::Neg(self.import(e)),
+            Expr::Recipr(e) => Node::Recipr(self.import(e)),
+            Expr::Const(c) => Node::Const(*c),
+            Expr::Var(s) => return self.var(s),
+            Expr::Error => Node::Error,
+        };
+        self.make(node)
+    }
+
+    fn import_all(&mut self, v: &[Box<Expr>]) -> Vec<Term> {
+        v.iter().map(|e| self.import(e)).collect()
+    }
+}
+
+/// Copies `term` into a tree, once for every place a shared node is used.
+impl From<&Term> for Expr {
+    fn from(term: &Term) -> Expr {
+        let each = |v: &[Term]| v.iter().map(|t| Box::new(Expr::from(t))).collect();
+        match term.node() {
+            Node::Sum(v) => Expr::Sum(each(v)),
+            Node::Prod(v) => Expr::Prod(each(v)),
+            Node::Call(fun, v) => Expr::Call(fun.clone(), each(v)),
+            Node::Pow(lhs, rhs) => Expr::Pow(Box::new(Expr::from(lhs)), Box::new(Expr::from(rhs))),
+            Node::Neg(e) => Expr::Neg(Box::new(Expr::from(e))),
+            Node::Recipr(e) => Expr::Recipr(Box::new(Expr::from(e))),
+            Node::Const(c) => Expr::Const(*c),
+            Node::Var(s) => Expr::Var(String::from(&**s)),
+            Node::Error => Expr::Error,
+        }
+    }
+}
+
+// Hashes a node from its kind, its own data and the cached hashes of its
+// children.
+fn hash_node(node: &Node) -> u64 {
+    let mut state = DefaultHasher::new();
+    std::mem::discriminant(node).hash(&mut state);
+    match node {
+        Node::Sum(v) | Node::Prod(v) => v.hash(&mut state),
+        Node::Call(fun, v) => {
+            fun.hash(&mut state);
+            v.hash(&mut state);
+        },
+        Node::Pow(lhs, rhs) => {
+            lhs.hash(&mut state);
+            rhs.hash(&mut state);
+        },
+        Node::Neg(e) | Node::Recipr(e) => e.hash(&mut state),
+        Node::Const(c) => c.hash(&mut state),
+        Node::Var(s) => s.hash(&mut state),
+        Node::Error => (),
+    }
+    state.finish()
+}
+
+// Whether two nodes are the same given that their children are shared, so
+// children compare by pointer. Constants compare as `Const` does: `0.0`
+// and `-0.0` are different nodes and a NaN is the same as itself.
+fn same_node(a: &Node, b: &Node) -> bool {
+    match (a, b) {
+        (Node::Sum(a), Node::Sum(b)) | (Node::Prod(a), Node::Prod(b)) => a == b,
+        (Node::Call(f, a), Node::Call(g, b)) => f == g && a == b,
+        (Node::Pow(a, b), Node::Pow(c, d)) => a == c && b == d,
+        (Node::Neg(a), Node::Neg(b)) | (Node::Recipr(a), Node::Recipr(b)) => a == b,
+        (Node::Const(a), Node::Const(b)) => a == b,
+        (Node::Var(a), Node::Var(b)) => a == b,
+        (Node::Error, Node::Error) => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,91 @@
+use super::lexer::{self, Span};
+use super::parser;
+
+/// An error message attached to a span of the input, which can be shown to
+/// the user with `render`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String,
+    pub hint: Option<String>,
+}
+
+impl Diagnostic {
+    /// Formats the diagnostic with the offending line of `src` and a caret
+    /// underline:
+    ///
+    /// ```text
+    /// error: Unexpected token
+    ///   |
+    /// 1 | 1 + * 2
+    ///   |     ^
+    ///   = hint: expected a number, a variable, a function call or '('
+    /// ```
+    pub fn render(&self, src: &str) -> String {
+        self.render_from(src, 1)
+    }
+
+    /// Like `render` for a `src` that starts on line `first_line` of a
+    /// longer input, so that the line numbers shown are those of the input.
+    pub fn render_from(&self, src: &str, first_line: usize) -> String {
+        let start = floor_char_boundary(src, self.span.start);
+        let end = floor_char_boundary(src, self.span.end.max(start));
+        let line_start = src[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = src[start..].find('\n').map_or(src.len(), |i| start + i);
+        let line_no = src[..start].matches('\n').count() + first_line;
+
+        let line = &src[line_start..line_end];
+        let offset = src[line_start..start].chars().count();
+        let width = src[start..end.min(line_end)].chars().count().max(1);
+
+        let number = line_no.to_string();
+        let pad = " ".repeat(number.len());
+        let mut out = format!("error: {}\n", self.message);
+        out += &format!("{} |\n", pad);
+        out += &format!("{} | {}\n", number, line);
+        out += &format!("{} | {}{}\n", pad, " ".repeat(offset), "^".repeat(width));
+        if let Some(hint) = &self.hint {
+            out += &format!("{} = hint: {}\n", pad, hint);
+        }
+        out
+    }
+}
+
+fn floor_char_boundary(s: &str, mut i: usize) -> usize {
+    if i >= s.len() {
+        return s.len();
+    }
+    while !s.is_char_boundary(i) {
+        i -= 1;
+    }
+    i
+}
+
+impl From<&lexer::Error> for Diagnostic {
+    fn from(err: &lexer::Error) -> Self {
+        Diagnostic{
+            span: err.span(),
+            message: String::from(err.message()),
+            hint: err.hint().map(String::from),
+        }
+    }
+}
+
+impl From<&parser::Error> for Diagnostic {
+    fn from(err: &parser::Error) -> Self {
+        Diagnostic{
+            span: err.span(),
+            message: String::from(err.message()),
+            hint: err.hint().map(String::from),
+        }
+    }
+}
+
+impl From<&super::Error> for Diagnostic {
+    fn from(err: &super::Error) -> Self {
+        match err {
+            super::Error::Lex(err) => Diagnostic::from(err),
+            super::Error::Parse(err) => Diagnostic::from(err),
+        }
+    }
+}
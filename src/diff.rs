@@ -0,0 +1,123 @@
+use super::eval::eval_with;
+use super::expr::{Const, Expr, Func};
+use super::function::{Definition, Registry};
+
+/// The derivative of `expr` with respect to `var`, folded with `eval`.
+///
+/// Builtins are differentiated with the chain rule; `floor` and the other
+/// step functions have derivative 0 wherever they are differentiable.
+/// Derivatives without a closed form here, such as those of `gamma` and of
+/// unknown functions, are left as a call `(Diff expr var)` of `Func::Diff`.
+///
+/// ```
+/// let expr = betadog::parse_str("x^3 + sin(y)").unwrap();
+/// assert_eq!(betadog::diff(&expr, "x").to_string(), "(* 3 (^ x 2))");
+/// ```
+pub fn diff(expr: &Expr, var: &str) -> Expr {
+    diff_with(expr, var, &Registry::new())
+}
+
+/// Like `diff`, with the user functions in `functions`. Symbolic ones are
+/// expanded and native ones use `Function::derivative`.
+pub fn diff_with(expr: &Expr, var: &str, functions: &Registry) -> Expr {
+    eval_with(&derivative(expr, var, functions), functions)
+}
+
+fn derivative(expr: &Expr, var: &str, functions: &Registry) -> Expr {
+    if !depends(expr, var, functions) {
+        return Expr::int(0);
+    }
+    let d = |e: &Expr| derivative(e, var, functions);
+    match expr {
+        Expr::Var(_) => Expr::int(1),
+        Expr::Sum(v) => Expr::Sum(v.iter().map(|e| Box::new(d(e))).collect()),
+        Expr::Prod(v) => Expr::Sum((0..v.len()).map(|i| {
+            let mut factors = v.clone();
+            *factors[i] = d(&v[i]);
+            Box::new(Expr::Prod(factors))
+        }).collect()),
+        Expr::Neg(e) => -d(e),
+        Expr::Recipr(e) => -(d(e) / (**e).clone().pow(2)),
+        Expr::Pow(base, exp) if !depends(exp, var, functions) => {
+            let power = match eval_with(&((**exp).clone() - 1), functions) {
+                Expr::Const(Const::Int(1)) => (**base).clone(),
+                e => (**base).clone().pow(e),
+            };
+            (**exp).clone() * power * d(base)
+        },
+        Expr::Pow(base, exp) =>
+            expr.clone() * (d(exp) * (**base).clone().log() + (**exp).clone() * d(base) / (**base).clone()),
+        Expr::Call(fun, args) => call(expr, fun, args, var, functions),
+        Expr::Const(_) | Expr::Error => Expr::int(0),
+    }
+}
+
+fn call(expr: &Expr, fun: &Func, args: &[Box<Expr>], var: &str, functions: &Registry) -> Expr {
+    let d = |e: &Expr| derivative(e, var, functions);
+    let args: Vec<Expr> = args.iter().map(|e| (**e).clone()).collect();
+    let unevaluated = || Expr::call(Func::Diff, vec![expr.clone(), Expr::var(var)]);
+
+    if let [u] = &args[..] {
+        let u = u.clone();
+        let outer = match fun {
+            Func::Sin => u.clone().cos(),
+            Func::Cos => -u.clone().sin(),
+            Func::Tan => Expr::int(1) + u.clone().tan().pow(2),
+            Func::ASin => (Expr::int(1) - u.clone().pow(2)).sqrt().recipr(),
+            Func::ACos => -(Expr::int(1) - u.clone().pow(2)).sqrt().recipr(),
+            Func::ATan => (Expr::int(1) + u.clone().pow(2)).recipr(),
+            Func::Log => u.clone().recipr(),
+            Func::Sqrt => (Expr::int(2) * u.clone().sqrt()).recipr(),
+            Func::Cbrt => (Expr::int(3) * u.clone().cbrt().pow(2)).recipr(),
+            Func::Abs => u.clone().sign(),
+            Func::Floor | Func::Ceil | Func::Round | Func::Sign => Expr::int(0),
+            Func::Frac => Expr::int(1),
+            _ => return user(fun, &args, var, functions).unwrap_or_else(unevaluated),
+        };
+        return outer * d(&u);
+    }
+    match (fun, &args[..]) {
+        (Func::Log, [base, x]) => d(&(x.clone().log() / base.clone().log())),
+        (Func::Root, [x, n]) => d(&x.clone().pow(n.clone().recipr())),
+        (Func::Mod, [x, m]) => d(x) - (x.clone() / m.clone()).floor() * d(m),
+        _ => user(fun, &args, var, functions).unwrap_or_else(unevaluated),
+    }
+}
+
+// The derivative of a call of a registered user function.
+fn user(fun: &Func, args: &[Expr], var: &str, functions: &Registry) -> Option<Expr> {
+    let def = functions.get(fun.name()?)?;
+    if let Some(body) = def.expand(args) {
+        return Some(derivative(&body, var, functions));
+    }
+    let f = match def {
+        Definition::Native(f) if f.arity() == args.len() => f,
+        _ => return None,
+    };
+    let mut terms = Vec::new();
+    for (i, arg) in args.iter().enumerate() {
+        if depends(arg, var, functions) {
+            terms.push(Box::new(f.derivative(args, i)? * derivative(arg, var, functions)));
+        }
+    }
+    Some(Expr::Sum(terms))
+}
+
+// Whether `expr` mentions `var`, including in the bodies of the symbolic
+// functions it calls.
+fn depends(expr: &Expr, var: &str, functions: &Registry) -> bool {
+    match expr {
+        Expr::Var(s) => s == var,
+        Expr::Sum(v) | Expr::Prod(v) => v.iter().any(|e| depends(e, var, functions)),
+        Expr::Call(fun, v) => {
+            let args: Vec<Expr> = v.iter().map(|e| (**e).clone()).collect();
+            match fun.name().and_then(|name| functions.get(name)).and_then(|def| def.expand(&args)) {
+                Some(body) => depends(&body, var, functions),
+                None => args.iter().any(|e| depends(e, var, functions)),
+            }
+        },
+        Expr::Pow(lhs, rhs) => depends(lhs, var, functions) || depends(rhs, var, functions),
+        Expr::Neg(e) | Expr::Recipr(e) => depends(e, var, functions),
+        Expr::Const(_) | Expr::Error => false,
+    }
+}
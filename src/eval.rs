@@ -0,0 +1,535 @@
+use std::cmp::Ordering;
+use std::convert::{Infallible, TryFrom};
+use super::expr::{Const, Expr, Func};
+use super::function::{map_children, Definition, Function, Registry};
+use super::rat;
+
+/// Evaluates `expr` as far as possible without losing exactness.
+///
+/// Constant subterms are folded (`2 + 3*x + 1` becomes `3*x + 3`), exact
+/// arithmetic on `Int` and `Rat` stays exact, and functions are only
+/// computed numerically when one of their arguments is a `Float`. The
+/// rounding functions `abs`, `floor`, `ceil`, `round`, `sign`, `frac` and
+/// `mod` are exact on `Int` and `Rat`. Variables are left untouched.
+pub fn eval(expr: &Expr) -> Expr {
+    eval_with(expr, &Registry::new())
+}
+
+/// Evaluates `expr` numerically: every `Int` and `Rat` is turned into a
+/// `Float` first, so `sqrt(2)/3` becomes `0.47140452079103173`.
+pub fn approx(expr: &Expr) -> Expr {
+    approx_with(expr, &Registry::new())
+}
+
+/// Like `approx`, with the user functions in `functions`.
+pub fn approx_with(expr: &Expr, functions: &Registry) -> Expr {
+    eval_with(&to_float(&eval_with(expr, functions)), functions)
+}
+
+fn to_float(expr: &Expr) -> Expr {
+    match expr {
+        Expr::Const(Const::Int(i)) => Expr::Const(Const::Float(*i as f64)),
+        Expr::Const(Const::Rat(r)) => Expr::Const(Const::Float(f64::from(*r))),
+        e => match map_children(e, |e| Ok::<_, Infallible>(to_float(e))) {
+            Ok(e) => e,
+            Err(never) => match never {},
+        },
+    }
+}
+
+/// Like `eval`, and also expands the symbolic functions in `functions` and
+/// evaluates the native ones.
+pub fn eval_with(expr: &Expr, functions: &Registry) -> Expr {
+    match expr {
+        Expr::Sum(v) => {
+            let mut acc = Const::Int(0);
+            let mut terms = Vec::new();
+            for e in v {
+                match eval_with(e, functions) {
+                    Expr::Const(c) => acc = add_const(acc, c),
+                    e => terms.push(Box::new(e)),
+                }
+            }
+            if terms.is_empty() || acc == Const::Undef {
+                return Expr::Const(acc);
+            }
+            if acc != Const::Int(0) {
+                terms.push(Box::new(Expr::Const(acc)));
+            }
+            if terms.len() == 1 {
+                *terms.pop().unwrap()
+            } else {
+                Expr::Sum(terms)
+            }
+        },
+        Expr::Prod(v) => {
+            let mut acc = Const::Int(1);
+            let mut factors = Vec::new();
+            for e in v {
+                match eval_with(e, functions) {
+                    Expr::Const(c) => acc = mul_const(acc, c),
+                    e => factors.push(Box::new(e)),
+                }
+            }
+            if factors.is_empty() || acc == Const::Undef || acc == Const::Int(0) {
+                return Expr::Const(acc);
+            }
+            if acc != Const::Int(1) {
+                factors.insert(0, Box::new(Expr::Const(acc)));
+            }
+            if factors.len() == 1 {
+                *factors.pop().unwrap()
+            } else {
+                Expr::Prod(factors)
+            }
+        },
+        Expr::Pow(lhs, rhs) => match (eval_with(lhs, functions), eval_with(rhs, functions)) {
+            (Expr::Const(base), Expr::Const(exp)) => match pow_const(base, exp) {
+                Some(c) => Expr::Const(c),
+                None => Expr::Pow(Box::new(Expr::Const(base)), Box::new(Expr::Const(exp))),
+            },
+            (lhs, rhs) => Expr::Pow(Box::new(lhs), Box::new(rhs)),
+        },
+        Expr::Neg(e) => match eval_with(e, functions) {
+            Expr::Const(c) => Expr::Const(negate_const(c)),
+            Expr::Neg(e) => *e,
+            e => Expr::Neg(Box::new(e)),
+        },
+        Expr::Recipr(e) => match eval_with(e, functions) {
+            Expr::Const(c) => Expr::Const(reciprocal_const(c)),
+            Expr::Recipr(e) => *e,
+            e => Expr::Recipr(Box::new(e)),
+        },
+        Expr::Call(fun, v) => {
+            let args: Vec<Expr> = v.iter().map(|e| match (fun, eval_with(e, functions)) {
+                // |-x| = |x|
+                (Func::Abs, Expr::Neg(e)) => *e,
+                (_, e) => e,
+            }).collect();
+            if let (Func::Abs, [abs @ Expr::Call(Func::Abs, _)]) = (fun, &args[..]) {
+                return abs.clone();
+            }
+            if let Some(def) = fun.name().and_then(|name| functions.get(name)) {
+                if let Some(body) = def.expand(&args) {
+                    return eval_with(&body, functions);
+                }
+                if let Definition::Native(f) = def {
+                    if let Some(c) = call_native(&**f, &args) {
+                        return Expr::Const(c);
+                    }
+                }
+            }
+            match call_const(fun, &args) {
+                Some(c) => Expr::Const(c),
+                None => Expr::Call(fun.clone(), args.into_iter().map(Box::new).collect()),
+            }
+        },
+        Expr::Const(c) => Expr::Const(*c),
+        Expr::Var(s) => Expr::Var(s.clone()),
+        Expr::Error => Expr::Error,
+    }
+}
+
+pub(crate) fn to_f64(c: Const) -> f64 {
+    match c {
+        Const::Int(i) => i as f64,
+        Const::Float(f) => f,
+        Const::Rat(r) => f64::from(r),
+        Const::Inf => f64::INFINITY,
+        Const::NegInf => f64::NEG_INFINITY,
+        Const::Undef => f64::NAN,
+    }
+}
+
+fn from_f64(f: f64) -> Const {
+    if f.is_nan() {
+        Const::Undef
+    } else if f == f64::INFINITY {
+        Const::Inf
+    } else if f == f64::NEG_INFINITY {
+        Const::NegInf
+    } else {
+        Const::Float(f)
+    }
+}
+
+fn pow_int(base: rat::Rat, exp: i128) -> Option<Const> {
+    let e = u32::try_from(exp.checked_abs()?).ok()?;
+    let num = base.num().checked_pow(e)?;
+    let den = base.den().checked_pow(e)?;
+    if exp < 0 {
+        if num == 0 {
+            return Some(Const::Inf);
+        }
+        rat::Rat::checked_new(den, num).map(eval_rat)
+    } else {
+        Some(eval_rat(rat::Rat::new(num, den)))
+    }
+}
+
+fn pow_const(base: Const, exp: Const) -> Option<Const> {
+    use Const::*;
+    match (base, exp) {
+        (Undef, _) | (_, Undef) => Some(Undef),
+        (_, Int(0)) => Some(Int(1)),
+        (Int(b), Int(e)) => pow_int(rat::Rat::from(b), e)
+            .or_else(|| Some(from_f64((b as f64).powf(e as f64)))),
+        (Rat(b), Int(e)) => pow_int(b, e)
+            .or_else(|| Some(from_f64(f64::from(b).powf(e as f64)))),
+        (Float(_), _) | (_, Float(_)) => Some(from_f64(to_f64(base).powf(to_f64(exp)))),
+        _ => None,
+    }
+}
+
+fn call_const(fun: &Func, args: &[Expr]) -> Option<Const> {
+    let mut consts = Vec::with_capacity(args.len());
+    for arg in args {
+        match arg {
+            Expr::Const(c) => consts.push(*c),
+            _ => return None,
+        }
+    }
+    if consts.contains(&Const::Undef) {
+        return Some(Const::Undef);
+    }
+    match (fun, &consts[..]) {
+        (Func::Factorial, [Const::Int(n)]) => return Some(factorial_int(*n, 1)),
+        (Func::DoubleFactorial, [Const::Int(n)]) => return Some(factorial_int(*n, 2)),
+        // Gamma has poles at zero and the negative integers, like `factorial_int`.
+        (Func::Gamma, [Const::Int(n)]) => return Some(n.checked_sub(1).map_or(Const::Undef, |n| factorial_int(n, 1))),
+        (Func::Factorial, [Const::Inf]) | (Func::DoubleFactorial, [Const::Inf]) | (Func::Gamma, [Const::Inf]) =>
+            return Some(Const::Inf),
+        (Func::Abs, [c]) => return Some(abs_const(*c)),
+        (Func::Floor, [c]) | (Func::Ceil, [c]) | (Func::Round, [c]) => return Some(round_const(fun, *c)),
+        (Func::Sign, [c]) => return Some(sign_const(*c)),
+        (Func::Frac, [c]) => return Some(frac_const(*c)),
+        (Func::Mod, [x, m]) => return Some(mod_const(*x, *m)),
+        _ => (),
+    }
+    if !consts.iter().any(|c| matches!(c, Const::Float(_))) {
+        return None;
+    }
+
+    let x: Vec<f64> = consts.into_iter().map(to_f64).collect();
+    let f = match (fun, &x[..]) {
+        (Func::Sin, [x]) => x.sin(),
+        (Func::Cos, [x]) => x.cos(),
+        (Func::Tan, [x]) => x.tan(),
+        (Func::ASin, [x]) => x.asin(),
+        (Func::ACos, [x]) => x.acos(),
+        (Func::ATan, [x]) => x.atan(),
+        (Func::Log, [x]) => x.ln(),
+        (Func::Log, [b, x]) => x.log(*b),
+        (Func::Sqrt, [x]) => x.sqrt(),
+        (Func::Cbrt, [x]) => x.cbrt(),
+        (Func::Root, [x, n]) => x.powf(1.0 / n),
+        (Func::Factorial, [x]) => gamma(x + 1.0),
+        (Func::DoubleFactorial, [x]) => double_factorial(*x),
+        (Func::Gamma, [x]) => gamma(*x),
+        _ => return None,
+    };
+    Some(from_f64(f))
+}
+
+fn call_native(f: &dyn Function, args: &[Expr]) -> Option<Const> {
+    if f.arity() != args.len() {
+        return None;
+    }
+    let mut consts = Vec::with_capacity(args.len());
+    for arg in args {
+        match arg {
+            Expr::Const(c) => consts.push(*c),
+            _ => return None,
+        }
+    }
+    if consts.contains(&Const::Undef) {
+        return Some(Const::Undef);
+    }
+    if let Some(c) = f.eval_exact(&consts) {
+        return Some(c);
+    }
+    if !consts.iter().any(|c| matches!(c, Const::Float(_))) {
+        return None;
+    }
+    let x: Vec<f64> = consts.into_iter().map(to_f64).collect();
+    f.eval_f64(&x).map(from_f64)
+}
+
+// `n!` for `step == 1` and `n!!` for `step == 2`. Falls back to the gamma
+// function once the product no longer fits in an `Int`.
+fn factorial_int(n: i128, step: i128) -> Const {
+    if n < 0 {
+        return if step == 2 && n == -1 { Const::Int(1) } else { Const::Undef };
+    }
+    let mut acc: i128 = 1;
+    let mut i = n;
+    while i > 1 {
+        acc = match acc.checked_mul(i) {
+            Some(acc) => acc,
+            None if step == 1 => return from_f64(gamma(n as f64 + 1.0)),
+            None => return from_f64(double_factorial(n as f64)),
+        };
+        i -= step;
+    }
+    Const::Int(acc)
+}
+
+// Lanczos approximation, accurate to about 15 significant digits.
+pub(crate) fn gamma(x: f64) -> f64 {
+    use std::f64::consts::PI;
+    const G: f64 = 7.0;
+    const COEFFS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+
+    if x <= 0.0 && x == x.floor() {
+        f64::NAN
+    } else if x < 0.5 {
+        PI / ((PI * x).sin() * gamma(1.0 - x))
+    } else {
+        let x = x - 1.0;
+        let t = x + G + 0.5;
+        let sum = COEFFS[1..].iter().enumerate()
+            .fold(COEFFS[0], |acc, (i, c)| acc + c / (x + i as f64 + 1.0));
+        (2.0 * PI).sqrt() * t.powf(x + 0.5) * (-t).exp() * sum
+    }
+}
+
+// The usual continuation of `n!!` to real numbers, exact at the integers.
+pub(crate) fn double_factorial(x: f64) -> f64 {
+    use std::f64::consts::PI;
+    let scale = (2.0 / PI).powf((1.0 - (PI * x).cos()) / 4.0);
+    2f64.powf(x / 2.0) * scale * gamma(x / 2.0 + 1.0)
+}
+
+// The largest integer not above `r`.
+fn floor_rat(r: rat::Rat) -> i128 {
+    let (num, den) = if r.den() < 0 { (-r.num(), -r.den()) } else { (r.num(), r.den()) };
+    num.div_euclid(den)
+}
+
+// The smallest integer not below `r`.
+fn ceil_rat(r: rat::Rat) -> i128 {
+    let floor = floor_rat(r);
+    if r.num() % r.den() == 0 { floor } else { floor + 1 }
+}
+
+// The nearest integer to `r`, with halves rounded away from zero. The
+// fraction above the floor is compared with a half without overflowing.
+fn round_rat(r: rat::Rat) -> i128 {
+    let floor = floor_rat(r);
+    let (above, below) = (r.num().rem_euclid(r.den()), r.den() - r.num().rem_euclid(r.den()));
+    if above > below || (above == below && r.num() > 0) { floor + 1 } else { floor }
+}
+
+fn to_rat(c: Const) -> Option<rat::Rat> {
+    match c {
+        Const::Int(i) => Some(rat::Rat::from(i)),
+        Const::Rat(r) => Some(r),
+        _ => None,
+    }
+}
+
+fn abs_const(c: Const) -> Const {
+    use Const::*;
+    match c {
+        Int(i) => i.checked_abs().map_or(Float((i as f64).abs()), Int),
+        Float(f) => Float(f.abs()),
+        Rat(r) if r < rat::Rat::from(0) => exact_or(r.checked_neg(), || -f64::from(r)),
+        Rat(r) => Rat(r),
+        Inf | NegInf => Inf,
+        Undef => Undef,
+    }
+}
+
+// `floor`, `ceil` or `round`, which rounds halves away from zero.
+fn round_const(fun: &Func, c: Const) -> Const {
+    use Const::*;
+    match (fun, c) {
+        (Func::Floor, Rat(r)) => Int(floor_rat(r)),
+        (Func::Ceil, Rat(r)) => Int(ceil_rat(r)),
+        (_, Rat(r)) => Int(round_rat(r)),
+        (Func::Floor, Float(f)) => Float(f.floor()),
+        (Func::Ceil, Float(f)) => Float(f.ceil()),
+        (_, Float(f)) => Float(f.round()),
+        (_, c) => c,
+    }
+}
+
+fn sign_const(c: Const) -> Const {
+    use Const::*;
+    match c {
+        Int(i) => Int(i.signum()),
+        Rat(r) => Int(r.num().signum() * r.den().signum()),
+        Float(f) if f == 0.0 || f.is_nan() => Float(f),
+        Float(f) => Float(f.signum()),
+        Inf => Int(1),
+        NegInf => Int(-1),
+        Undef => Undef,
+    }
+}
+
+// The fractional part `x - floor(x)`, which is in `[0, 1)`.
+fn frac_const(c: Const) -> Const {
+    use Const::*;
+    match c {
+        Int(_) => Int(0),
+        Rat(r) => eval_rat(rat::Rat::new(r.num().rem_euclid(r.den()), r.den())),
+        Float(f) => Float(f - f.floor()),
+        Inf | NegInf | Undef => Undef,
+    }
+}
+
+// `x - m*floor(x/m)`, which has the sign of `m`.
+fn mod_const(x: Const, m: Const) -> Const {
+    use Const::*;
+    match (x, m) {
+        (Undef, _) | (_, Undef) | (Inf, _) | (NegInf, _) | (_, Int(0)) => Undef,
+        (Float(f), _) | (_, Float(f)) if f.is_nan() => Undef,
+        (Int(x), Int(m)) => match x.checked_rem(m) {
+            Some(r) if r != 0 && (r < 0) != (m < 0) => Int(r + m),
+            Some(r) => Int(r),
+            None => Int(0),
+        },
+        (x, Inf) if x.numeric_cmp(&Int(0)) != Some(Ordering::Less) => x,
+        (_, Inf) => Inf,
+        (x, NegInf) if x.numeric_cmp(&Int(0)) != Some(Ordering::Greater) => x,
+        (_, NegInf) => NegInf,
+        (Float(_), _) | (_, Float(_)) => {
+            let (x, m) = (to_f64(x), to_f64(m));
+            from_f64(x - m * (x / m).floor())
+        },
+        (x, m) => match (to_rat(x), to_rat(m)) {
+            (Some(_), Some(m)) if m.num() == 0 => Undef,
+            (Some(x), Some(m)) => exact_or(
+                x.checked_div(m).and_then(|q| m.checked_mul(rat::Rat::from(floor_rat(q)))).and_then(|y| x.checked_sub(y)),
+                || {
+                    let (x, m) = (f64::from(x), f64::from(m));
+                    x - m * (x / m).floor()
+                },
+            ),
+            _ => Undef,
+        },
+    }
+}
+
+// An exact result, or the float one when it does not fit in a `Rat`.
+fn exact_or<F: FnOnce() -> f64>(r: Option<rat::Rat>, approx: F) -> Const {
+    r.map_or_else(|| from_f64(approx()), eval_rat)
+}
+
+fn eval_rat(r: rat::Rat) -> Const {
+    match r.den() {
+        1 => Const::Int(r.num()),
+        -1 => Const::Int(-r.num()),
+        _ => Const::Rat(r),
+    }
+}
+
+pub(crate) fn add_const(lhs: Const, rhs: Const) -> Const {
+    use Const::*;
+    match (lhs, rhs) {
+        (Undef, _) => Undef,
+        (_, Undef) => Undef,
+
+        (Int(i), Int(j)) => i.checked_add(j).map_or(Float(i as f64 + j as f64), Int),
+        (Int(i), Float(j)) => Float(i as f64 + j),
+        (Float(i), Int(j)) => Float(i + j as f64),
+        (Float(i), Float(j)) => Float(i + j),
+        (Rat(i), Int(j)) => exact_or(i.checked_add(rat::Rat::from(j)), || f64::from(i) + j as f64),
+        (Rat(i), Float(j)) => Float(f64::from(i) + j),
+        (Int(i), Rat(j)) => exact_or(rat::Rat::from(i).checked_add(j), || i as f64 + f64::from(j)),
+        (Float(i), Rat(j)) => Float(i + f64::from(j)),
+        (Rat(i), Rat(j)) => exact_or(i.checked_add(j), || f64::from(i) + f64::from(j)),
+        
+        (Inf, NegInf) => Undef,
+        (NegInf, Inf) => Undef,
+        (_, Inf) => Inf,
+        (Inf, _) => Inf,
+        (_, NegInf) => NegInf,
+        (NegInf, _) => NegInf,
+
+    }
+}
+
+pub(crate) fn mul_const(lhs: Const, rhs: Const) -> Const {
+    use Const::*;
+    match (lhs, rhs) {
+        (Undef, _) => Undef,
+        (_, Undef) => Undef,
+
+        (Int(i), Int(j)) => i.checked_mul(j).map_or(Float(i as f64 * j as f64), Int),
+        (Int(i), Float(j)) => Float(i as f64 * j),
+        (Float(i), Int(j)) => Float(i * j as f64),
+        (Float(i), Float(j)) => Float(i * j),
+        (Rat(i), Int(j)) => exact_or(i.checked_mul(rat::Rat::from(j)), || f64::from(i) * j as f64),
+        (Rat(i), Float(j)) => Float(f64::from(i) * j),
+        (Int(i), Rat(j)) => exact_or(rat::Rat::from(i).checked_mul(j), || i as f64 * f64::from(j)),
+        (Float(i), Rat(j)) => Float(i * f64::from(j)),
+        (Rat(i), Rat(j)) => exact_or(i.checked_mul(j), || f64::from(i) * f64::from(j)),
+
+        (Inf, x) => mul_infinity_const(x),
+        (x, Inf) => mul_infinity_const(x),
+
+        (NegInf, x) => mul_infinity_const(negate_const(x)),
+        (x, NegInf) => mul_infinity_const(negate_const(x)),
+    }
+}
+
+pub(crate) fn negate_const(expr: Const) -> Const {
+    use Const::*;
+    match expr {
+        Int(i) => i.checked_neg().map_or(Float(-(i as f64)), Int),
+        Float(i) => Float(-i),
+        Rat(r) => exact_or(r.checked_neg(), || -f64::from(r)),
+        Inf => NegInf,
+        NegInf => Inf,
+        Undef => Undef,
+    }
+}
+
+fn reciprocal_const(expr: Const) -> Const {
+    use Const::*;
+    match expr {
+        Int(0) => Inf,
+        Int(i) => exact_or(rat::Rat::checked_new(1, i), || 1.0 / i as f64),
+        Float(0.0) => Inf,
+        Float(i) =>  Float(1.0 / i),
+        Rat(i) => exact_or(rat::Rat::checked_new(i.den(), i.num()), || 1.0 / f64::from(i)),
+        Inf => Int(0),
+        NegInf => Int(0),
+        Undef => Undef
+    }
+}
+
+fn mul_infinity_const(expr: Const) -> Const {
+    use Const::*;
+    const INT_MAX: i128 = i128::MAX;
+    const INT_MIN: i128 = i128::MIN;
+
+    match expr {
+        Int(INT_MIN..=-1) => NegInf,
+        Int(0) => Undef,
+        Int(1..=INT_MAX) => Inf,
+
+        Float(x) if x < 0.0 => NegInf,
+        Float(x) if x > 0.0 => Inf,
+        Float(_) => Undef,
+
+        Rat(x) if x.num() < 0 => NegInf,
+        Rat(x) if x.num() > 0 => Inf,
+        Rat(_) => Undef,
+
+        Inf => Inf,
+        NegInf => NegInf,
+        Undef => Undef,
+    }
+}
@@ -0,0 +1,608 @@
+use std::fmt;
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+use super::rat;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A numeric constant.
+///
+/// With the `serde` feature a constant is serialised as
+/// `{"type": "int", "value": "42"}`, `{"type": "float", "value": 0.5}`,
+/// `{"type": "rat", "value": {"num": "1", "den": "2"}}` or one of
+/// `{"type": "inf"}`, `{"type": "neginf"}`, `{"type": "undef"}`.
+/// Integers are strings so they survive 64-bit JSON readers, and floats
+/// that JSON has no number for are the strings `"NaN"`, `"inf"` and
+/// `"-inf"`.
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", content = "value", rename_all = "lowercase"))]
+pub enum Const {
+    Int(#[cfg_attr(feature = "serde", serde(with = "rat::serde_i128"))] i128),
+    Float(#[cfg_attr(feature = "serde", serde(with = "serde_f64"))] f64),
+    Rat(rat::Rat),
+    Inf,
+    NegInf,
+    Undef
+}
+
+// Floats are numbers, except that NaN and the infinities are strings, see
+// `Const`.
+#[cfg(feature = "serde")]
+mod serde_f64 {
+    use std::fmt;
+    use serde::{de, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(x: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        match *x {
+            x if x.is_nan() => serializer.serialize_str("NaN"),
+            f64::INFINITY => serializer.serialize_str("inf"),
+            f64::NEG_INFINITY => serializer.serialize_str("-inf"),
+            x => serializer.serialize_f64(x),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        struct Visitor;
+
+        impl de::Visitor<'_> for Visitor {
+            type Value = f64;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a number, \"NaN\", \"inf\" or \"-inf\"")
+            }
+
+            fn visit_f64<E: de::Error>(self, x: f64) -> Result<f64, E> {
+                Ok(x)
+            }
+
+            fn visit_i64<E: de::Error>(self, x: i64) -> Result<f64, E> {
+                Ok(x as f64)
+            }
+
+            fn visit_u64<E: de::Error>(self, x: u64) -> Result<f64, E> {
+                Ok(x as f64)
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<f64, E> {
+                match s {
+                    "NaN" => Ok(f64::NAN),
+                    "inf" => Ok(f64::INFINITY),
+                    "-inf" => Ok(f64::NEG_INFINITY),
+                    s => Err(E::invalid_value(de::Unexpected::Str(s), &self)),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+impl Const {
+    /// Compares by value, as `<` and `==` do in scripts: `1 == 1.0` and
+    /// `1/2 < 0.6`. Nothing compares with `undef` or a NaN.
+    pub fn numeric_cmp(&self, other: &Const) -> Option<Ordering> {
+        use Const::*;
+        use Ordering::*;
+        match (self, other) {
+            (Undef, _) | (_, Undef) => None,
+
+            (Inf, Inf) => Some(Equal),
+            (NegInf, NegInf) => Some(Equal),
+
+            (Inf, _) => Some(Greater),
+            (_, Inf) => Some(Less),
+            (NegInf, _) => Some(Less),
+            (_, NegInf) => Some(Greater),
+
+            (Int(x), Int(y)) => Some(x.cmp(y)),
+            (Int(x), Rat(y)) => Some(rat::Rat::from(*x).cmp(y)),
+            (Int(x), Float(y)) => int_float_cmp(*x, *y),
+            (Rat(x), Int(y)) => Some(x.cmp(&rat::Rat::from(*y))),
+            (Float(x), Int(y)) => int_float_cmp(*y, *x).map(Ordering::reverse),
+
+            (Float(x), Float(y)) => x.partial_cmp(y),
+            (Float(x), Rat(y)) => rat_float_cmp(*y, *x).map(Ordering::reverse),
+            (Rat(x), Float(y)) => rat_float_cmp(*x, *y),
+
+            (Rat(x), Rat(y)) => Some(x.cmp(y)),
+        }
+    }
+
+    // Breaks ties between numerically equal constants.
+    fn kind(&self) -> u8 {
+        match self {
+            Const::Int(_) => 0,
+            Const::Rat(_) => 1,
+            Const::Float(_) => 2,
+            Const::Inf | Const::NegInf => 3,
+            Const::Undef => 4,
+        }
+    }
+}
+
+// Compares exactly, where `x as f64` would round large integers.
+fn int_float_cmp(x: i128, y: f64) -> Option<Ordering> {
+    // 2^127, the first float above every i128.
+    const LIMIT: f64 = 170141183460469231731687303715884105728.0;
+    if y.is_nan() {
+        None
+    } else if y >= LIMIT {
+        Some(Ordering::Less)
+    } else if y < -LIMIT {
+        Some(Ordering::Greater)
+    } else {
+        let whole = y.trunc();
+        Some(x.cmp(&(whole as i128)).then_with(|| whole.partial_cmp(&y).unwrap()))
+    }
+}
+
+// Compares exactly, where `f64::from(x)` would round: the integer parts
+// first, then the binary digits of the fractions, which are finite for
+// the float.
+fn rat_float_cmp(x: rat::Rat, y: f64) -> Option<Ordering> {
+    // 2^128, the first float above every integer part of a `Rat`.
+    const LIMIT: f64 = 340282366920938463463374607431768211456.0;
+    if y.is_nan() {
+        return None;
+    }
+    let x_sign = x.num().signum() * x.den().signum();
+    let y_sign = if y > 0.0 { 1 } else if y < 0.0 { -1 } else { 0 };
+    if x_sign != y_sign || x_sign == 0 {
+        return Some(x_sign.cmp(&y_sign));
+    }
+    let (num, den) = (x.num().unsigned_abs(), x.den().unsigned_abs());
+    let y = y.abs();
+    let whole = y.trunc();
+    let ord = if whole >= LIMIT {
+        Ordering::Less
+    } else {
+        (num / den).cmp(&(whole as u128)).then_with(|| {
+            // Doubling and subtracting 1 are exact on the float.
+            let (mut rem, mut frac) = (num % den, y - whole);
+            while frac != 0.0 {
+                rem *= 2;
+                frac *= 2.0;
+                let (x_bit, y_bit) = (rem >= den, frac >= 1.0);
+                if x_bit != y_bit {
+                    return x_bit.cmp(&y_bit);
+                }
+                if x_bit {
+                    rem -= den;
+                    frac -= 1.0;
+                }
+            }
+            if rem == 0 { Ordering::Equal } else { Ordering::Greater }
+        })
+    };
+    Some(if x_sign < 0 { ord.reverse() } else { ord })
+}
+
+/// The canonical order of constants, which is total: numbers by value,
+/// from `-inf` to `inf`, then NaN, then `undef`. Numerically equal
+/// constants are ordered `Int`, `Rat`, `Float`, so `1 < 1.0`, and `-0.0 <
+/// 0.0`. Every NaN is the same constant. Equality is this order's, so
+/// unlike `f64` a NaN equals itself, and `0.0 != -0.0`.
+impl Ord for Const {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let class = |c: &Const| match c {
+            Const::Float(f) if f.is_nan() => 1,
+            Const::Undef => 2,
+            _ => 0,
+        };
+        match (class(self), class(other)) {
+            (0, 0) => self.numeric_cmp(other).unwrap()
+                .then_with(|| self.kind().cmp(&other.kind()))
+                .then_with(|| match (self, other) {
+                    (Const::Float(x), Const::Float(y)) => x.total_cmp(y),
+                    _ => Ordering::Equal,
+                }),
+            (a, b) => a.cmp(&b),
+        }
+    }
+}
+
+impl PartialOrd for Const {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Const {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Const {}
+
+impl Hash for Const {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Const::Int(i) => i.hash(state),
+            Const::Rat(r) => r.hash(state),
+            Const::Float(f) if f.is_nan() => (),
+            Const::Float(f) => f.to_bits().hash(state),
+            Const::Inf | Const::NegInf | Const::Undef => (),
+        }
+    }
+}
+
+impl fmt::Display for Const {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Const::Int(i) => write!(f, "{}", i),
+            // The shortest text that reads back as the same `f64`, so
+            // `sexpr::read` reloads it exactly.
+            Const::Float(i) => write!(f, "{:?}", i),
+            Const::Rat(r) => write!(f, "{}", r),
+            Const::Inf => write!(f, "inf"),
+            Const::NegInf => write!(f, "-inf"),
+            Const::Undef => write!(f, "undef"),
+        }
+    }
+}
+
+/// With the `serde` feature a function is serialised as `{"type": "sin"}`,
+/// or `{"type": "func", "value": "f"}` for a named function.
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", content = "value", rename_all = "lowercase"))]
+pub enum Func {
+    Sin,
+    Cos,
+    Tan,
+
+    ASin,
+    ACos,
+    ATan,
+    
+    Log,
+    Sqrt,
+    Cbrt,
+    Root,
+
+    Factorial,
+    DoubleFactorial,
+    Gamma,
+
+    Abs,
+    Floor,
+    Ceil,
+    Round,
+    Sign,
+    Frac,
+    Mod,
+
+    /// `Diff(expr, var)` is the derivative of `expr` with respect to `var`
+    /// that `diff` could not work out. It has no name in the infix syntax,
+    /// so a user function called `diff` is never mistaken for it.
+    Diff,
+
+    Func(String),
+}
+
+impl Func {
+    /// The names `from_name` maps to builtins.
+    pub const BUILTIN_NAMES: &'static [&'static str] = &[
+        "sin", "cos", "tan", "asin", "acos", "atan", "log", "sqrt", "cbrt", "root",
+        "factorial", "gamma", "abs", "floor", "ceil", "round", "sign", "frac", "mod",
+    ];
+
+    /// Looks up a builtin by the name it is written with in the infix
+    /// syntax, e.g. `sin` or `sqrt`. Any other name is a `Func::Func`.
+    pub fn from_name(name: &str) -> Func {
+        match name {
+            "sin" => Func::Sin,
+            "cos" => Func::Cos,
+            "tan" => Func::Tan,
+            "asin" => Func::ASin,
+            "acos" => Func::ACos,
+            "atan" => Func::ATan,
+            "log" => Func::Log,
+            "sqrt" => Func::Sqrt,
+            "cbrt" => Func::Cbrt,
+            "root" => Func::Root,
+            "factorial" => Func::Factorial,
+            "gamma" => Func::Gamma,
+            "abs" => Func::Abs,
+            "floor" => Func::Floor,
+            "ceil" => Func::Ceil,
+            "round" => Func::Round,
+            "sign" => Func::Sign,
+            "frac" => Func::Frac,
+            "mod" => Func::Mod,
+            s => Func::Func(String::from(s)),
+        }
+    }
+
+    /// The name of a user function; `None` for the builtins.
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Func::Func(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn is_builtin(&self) -> bool {
+        !matches!(self, Func::Func(_))
+    }
+}
+
+impl fmt::Display for Func {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Func::Func(s) => write!(f, "{}", s),
+            x => write!(f, "{:?}", x),
+        }
+    }
+}
+
+fn print_sep_vec<T: fmt::Display>(v: &[T], sep: &str, f: &mut fmt::Formatter) -> fmt::Result {
+    let mut iter = v.iter();
+    if let Some(ex) = iter.next() {
+        write!(f, "{}", *ex)?;
+        for ex in iter {
+            write!(f, "{}{}", sep, *ex)?;
+        }
+    }
+    Ok(())
+}
+
+
+/// An expression tree, as produced by `parser::parse`. Subtraction is a
+/// `Sum` with a `Neg` term and division a `Prod` with a `Recipr` factor.
+///
+/// With the `serde` feature every node is serialised as
+/// `{"type": <node>, "value": <payload>}` where the payload is
+///
+/// - `sum`, `prod`: an array of nodes
+/// - `pow`: `[base, exponent]`
+/// - `neg`, `recipr`: a single node
+/// - `call`: `[function, [arguments...]]`, see `Func`
+/// - `const`: a constant, see `Const`
+/// - `var`: the variable name as a string
+/// - `error`: no payload
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", content = "value", rename_all = "lowercase"))]
+pub enum Expr {
+    Sum(Vec<Box<Expr>>),
+    Prod(Vec<Box<Expr>>),
+    Pow(Box<Expr>, Box<Expr>),
+    Neg(Box<Expr>),
+    Recipr(Box<Expr>),
+    Call(Func, Vec<Box<Expr>>),
+    Const(Const),
+    Var(String),
+    // A part of the input that could not be parsed, see
+    // `parser::parse_recovering`.
+    Error,
+}
+
+impl Expr {
+    // The order of the kinds of node in the canonical order.
+    fn rank(&self) -> u8 {
+        match self {
+            Expr::Const(_) => 0,
+            Expr::Var(_) => 1,
+            Expr::Pow(..) => 2,
+            Expr::Call(..) => 3,
+            Expr::Prod(_) => 4,
+            Expr::Sum(_) => 5,
+            Expr::Recipr(_) => 6,
+            Expr::Error => 7,
+            Expr::Neg(e) => e.rank(),
+        }
+    }
+}
+
+/// The canonical order of terms, which `simplify` sorts the factors of
+/// products and (after their coefficients) the terms of sums by.
+///
+/// Constants come first, in their own order, then variables by name,
+/// powers, calls, products, sums and reciprocals, with `-e` right after
+/// `e`. Nodes of the same kind compare their children from the left, so
+/// `x < -x < y`, `x^2 < x^3 < y^2` and `x*y < x*y*z`.
+impl Ord for Expr {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // A negation sorts right after what it negates.
+        fn unneg(mut e: &Expr) -> (&Expr, usize) {
+            let mut depth = 0;
+            while let Expr::Neg(inner) = e {
+                e = inner;
+                depth += 1;
+            }
+            (e, depth)
+        }
+        let ((a, i), (b, j)) = (unneg(self), unneg(other));
+        if i > 0 || j > 0 {
+            return a.cmp(b).then(i.cmp(&j));
+        }
+        match (self, other) {
+            (Expr::Const(a), Expr::Const(b)) => a.cmp(b),
+            (Expr::Var(a), Expr::Var(b)) => a.cmp(b),
+            (Expr::Pow(a, b), Expr::Pow(c, d)) => a.cmp(c).then_with(|| b.cmp(d)),
+            (Expr::Call(f, a), Expr::Call(g, b)) => f.cmp(g).then_with(|| a.cmp(b)),
+            (Expr::Prod(a), Expr::Prod(b)) | (Expr::Sum(a), Expr::Sum(b)) => a.cmp(b),
+            (Expr::Recipr(a), Expr::Recipr(b)) => a.cmp(b),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for Expr {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        
+        match self {
+            Expr::Sum(v) => { 
+                write!(f, "(+ ")?;
+                print_sep_vec(v, " ", f)?;
+                write!(f, ")")
+            },
+            Expr::Prod(v) => { 
+                write!(f, "(* ")?;
+                print_sep_vec(v, " ", f)?;
+                write!(f, ")")
+            },
+            Expr::Pow(lhs, rhs) => write!(f, "(^ {} {})", lhs, rhs),
+            Expr::Neg(expr) => write!(f, "(- {})", expr),
+            Expr::Recipr(expr) => write!(f, "(/ 1 {})", expr),
+            Expr::Call(fun, v) => {
+                write!(f, "({} ", fun)?;
+                print_sep_vec(v, " ", f)?;
+                write!(f, ")")
+            },
+            Expr::Const(c) => write!(f, "{}", c),
+            Expr::Var(s) => write!(f, "{}", s),
+            Expr::Error => write!(f, "?"),
+        }
+    }
+}
+
+impl Expr {
+    pub fn new_unary(op: &str, expr: Expr) -> Option<Expr> {
+        match op {
+            "+" => Some(Expr::Sum(vec![Box::new(expr)])),
+            "-" => Some(Expr::Neg(Box::new(expr))),
+            "√" => Some(Expr::Call(Func::Sqrt, vec![Box::new(expr)])),
+            "∛" => Some(Expr::Call(Func::Cbrt, vec![Box::new(expr)])),
+            _ => None,
+        }
+    }
+
+    /// Gives `expr` back if `op` is not a postfix operator.
+    pub fn new_postfix(op: &str, expr: Expr) -> Result<Expr, Expr> {
+        match op {
+            "!" => Ok(Expr::Call(Func::Factorial, vec![Box::new(expr)])),
+            "!!" => Ok(Expr::Call(Func::DoubleFactorial, vec![Box::new(expr)])),
+            "%" => Ok(Expr::Prod(vec![
+                Box::new(expr),
+                Box::new(Expr::Recipr(Box::new(Expr::Const(Const::Int(100))))),
+            ])),
+            _ => Err(expr),
+        }
+    }
+
+    pub fn new_binary(op: &str, lhs: Expr, rhs: Expr) -> Option<Expr> {
+        match op {
+            "^" => Some(Expr::Pow(Box::new(lhs), Box::new(rhs))),
+            _ => None,
+        }
+    }
+}
+
+// Builders for constructing trees from Rust. They produce the same shapes as
+// `parser::parse`; the operator impls live in `ops.rs`.
+impl Expr {
+    pub fn var(name: &str) -> Expr {
+        Expr::Var(String::from(name))
+    }
+
+    pub fn int(i: i128) -> Expr {
+        Expr::Const(Const::Int(i))
+    }
+
+    pub fn float(f: f64) -> Expr {
+        Expr::Const(Const::Float(f))
+    }
+
+    /// Panics if `den` is zero, like `Rat::new`.
+    pub fn rat(num: i128, den: i128) -> Expr {
+        let r = rat::Rat::new(num, den);
+        if r.den() == 1 {
+            Expr::int(r.num())
+        } else {
+            Expr::Const(Const::Rat(r))
+        }
+    }
+
+    pub fn call(fun: Func, args: Vec<Expr>) -> Expr {
+        Expr::Call(fun, args.into_iter().map(Box::new).collect())
+    }
+
+    pub fn pow<T: Into<Expr>>(self, exp: T) -> Expr {
+        Expr::Pow(Box::new(self), Box::new(exp.into()))
+    }
+
+    pub fn recipr(self) -> Expr {
+        Expr::Recipr(Box::new(self))
+    }
+
+    pub fn root<T: Into<Expr>>(self, n: T) -> Expr {
+        Expr::call(Func::Root, vec![self, n.into()])
+    }
+
+    pub fn sin(self) -> Expr { Expr::call(Func::Sin, vec![self]) }
+    pub fn cos(self) -> Expr { Expr::call(Func::Cos, vec![self]) }
+    pub fn tan(self) -> Expr { Expr::call(Func::Tan, vec![self]) }
+    pub fn asin(self) -> Expr { Expr::call(Func::ASin, vec![self]) }
+    pub fn acos(self) -> Expr { Expr::call(Func::ACos, vec![self]) }
+    pub fn atan(self) -> Expr { Expr::call(Func::ATan, vec![self]) }
+    pub fn log(self) -> Expr { Expr::call(Func::Log, vec![self]) }
+    pub fn sqrt(self) -> Expr { Expr::call(Func::Sqrt, vec![self]) }
+    pub fn cbrt(self) -> Expr { Expr::call(Func::Cbrt, vec![self]) }
+    pub fn factorial(self) -> Expr { Expr::call(Func::Factorial, vec![self]) }
+    pub fn gamma(self) -> Expr { Expr::call(Func::Gamma, vec![self]) }
+    pub fn abs(self) -> Expr { Expr::call(Func::Abs, vec![self]) }
+    pub fn floor(self) -> Expr { Expr::call(Func::Floor, vec![self]) }
+    pub fn ceil(self) -> Expr { Expr::call(Func::Ceil, vec![self]) }
+    pub fn round(self) -> Expr { Expr::call(Func::Round, vec![self]) }
+    pub fn sign(self) -> Expr { Expr::call(Func::Sign, vec![self]) }
+    pub fn frac(self) -> Expr { Expr::call(Func::Frac, vec![self]) }
+
+    /// `self mod m`, with the sign of `m`.
+    pub fn modulo<T: Into<Expr>>(self, m: T) -> Expr {
+        Expr::call(Func::Mod, vec![self, m.into()])
+    }
+}
+
+impl From<Const> for Expr {
+    fn from(c: Const) -> Self {
+        Expr::Const(c)
+    }
+}
+
+impl From<rat::Rat> for Expr {
+    fn from(r: rat::Rat) -> Self {
+        Expr::rat(r.num(), r.den())
+    }
+}
+
+impl From<i32> for Expr {
+    fn from(i: i32) -> Self {
+        Expr::int(i.into())
+    }
+}
+
+impl From<i64> for Expr {
+    fn from(i: i64) -> Self {
+        Expr::int(i.into())
+    }
+}
+
+impl From<i128> for Expr {
+    fn from(i: i128) -> Self {
+        Expr::int(i)
+    }
+}
+
+impl From<f64> for Expr {
+    fn from(f: f64) -> Self {
+        Expr::float(f)
+    }
+}
+
+impl From<&Expr> for Expr {
+    fn from(expr: &Expr) -> Self {
+        expr.clone()
+    }
+}
@@ -0,0 +1,449 @@
+use std::str::FromStr;
+
+use super::expr::{Const, Expr, Func};
+
+/// The notations `render` can write.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Style {
+    /// `(+ (* 2 x) 1)`, as printed by `Display` and read by `sexpr::read`.
+    Sexpr,
+    /// `2*x + 1`, which `parse_str` reads back.
+    Infix,
+    /// `2 \cdot x + 1`
+    Latex,
+}
+
+impl FromStr for Style {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sexpr" => Ok(Style::Sexpr),
+            "infix" => Ok(Style::Infix),
+            "latex" => Ok(Style::Latex),
+            s => Err(format!("Unknown format {}; the formats are sexpr, infix and latex", s)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Options {
+    pub style: Style,
+    /// Digits after the decimal point of a `Float`. `None` writes the
+    /// shortest form that reads back as the same number.
+    pub precision: Option<usize>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options{style: Style::Sexpr, precision: None}
+    }
+}
+
+/// Writes `expr` in the notation and with the precision in `options`.
+///
+/// ```
+/// use betadog::format::{render, Options, Style};
+///
+/// let expr = betadog::parse_str("x^2/2 - 1.25").unwrap();
+/// let infix = Options{style: Style::Infix, precision: Some(1)};
+/// assert_eq!(render(&expr, infix), "x^2/2 - 1.2");
+/// assert_eq!(render(&expr, Options{style: Style::Latex, precision: None}), "\\frac{x^{2}}{2} - 1.25");
+/// ```
+pub fn render(expr: &Expr, options: Options) -> String {
+    let mut out = String::new();
+    let mut printer = Printer{precision: options.precision, out: &mut out};
+    match options.style {
+        Style::Sexpr => printer.write_sexpr(expr),
+        Style::Infix => printer.write_infix(expr),
+        Style::Latex => printer.write_latex(expr),
+    }
+    out
+}
+
+/// `expr` in infix notation, e.g. `2*x + 1`.
+pub fn infix(expr: &Expr) -> String {
+    render(expr, Options{style: Style::Infix, precision: None})
+}
+
+/// `expr` as LaTeX math, e.g. `\frac{1}{2} \cdot x`.
+pub fn latex(expr: &Expr) -> String {
+    render(expr, Options{style: Style::Latex, precision: None})
+}
+
+/// `expr` drawn as a tree with one node per line:
+///
+/// ```text
+/// Sum
+/// ├─ Prod
+/// │  ├─ 2
+/// │  └─ x
+/// └─ 1
+/// ```
+pub fn tree(expr: &Expr) -> String {
+    let mut out = String::new();
+    draw(expr, "", "", &mut out);
+    out
+}
+
+fn draw(expr: &Expr, first: &str, rest: &str, out: &mut String) {
+    let (label, children): (String, &[Box<Expr>]) = match expr {
+        Expr::Sum(v) => (String::from("Sum"), v),
+        Expr::Prod(v) => (String::from("Prod"), v),
+        Expr::Pow(lhs, rhs) => {
+            out.push_str(&format!("{}Pow\n", first));
+            draw(lhs, &format!("{}├─ ", rest), &format!("{}│  ", rest), out);
+            draw(rhs, &format!("{}└─ ", rest), &format!("{}   ", rest), out);
+            return;
+        },
+        Expr::Neg(e) | Expr::Recipr(e) => {
+            let label = if let Expr::Neg(_) = expr { "Neg" } else { "Recipr" };
+            out.push_str(&format!("{}{}\n", first, label));
+            draw(e, &format!("{}└─ ", rest), &format!("{}   ", rest), out);
+            return;
+        },
+        Expr::Call(fun, v) => (fun.to_string(), v),
+        e => (e.to_string(), &[]),
+    };
+    out.push_str(&format!("{}{}\n", first, label));
+    for (i, child) in children.iter().enumerate() {
+        if i + 1 < children.len() {
+            draw(child, &format!("{}├─ ", rest), &format!("{}│  ", rest), out);
+        } else {
+            draw(child, &format!("{}└─ ", rest), &format!("{}   ", rest), out);
+        }
+    }
+}
+
+// How tightly an expression holds together in infix: an operand is put in
+// parentheses when its precedence is below what its position needs.
+fn prec(expr: &Expr) -> u8 {
+    match expr {
+        Expr::Sum(_) => 1,
+        Expr::Prod(_) | Expr::Recipr(_) | Expr::Const(Const::Rat(_)) => 2,
+        Expr::Pow(..) => 3,
+        // The parser applies a unary minus before `^`, so `-x^2` is `(-x)^2`.
+        Expr::Neg(_) | Expr::Const(Const::NegInf) => 4,
+        Expr::Const(Const::Int(i)) if *i < 0 => 4,
+        Expr::Const(Const::Float(f)) if *f < 0.0 => 4,
+        _ => 5,
+    }
+}
+
+// `-term` if `term` is written with a leading minus, so that a sum can
+// show `a - 2*b` rather than `a + -2*b`.
+fn negated(term: &Expr) -> Option<Expr> {
+    match term {
+        Expr::Neg(e) => Some((**e).clone()),
+        Expr::Const(Const::Int(i)) if *i < 0 => i.checked_neg().map(Expr::int),
+        Expr::Const(Const::Float(f)) if *f < 0.0 => Some(Expr::Const(Const::Float(-f))),
+        Expr::Const(Const::Rat(r)) if r.num() < 0 => r.num().checked_neg().map(|num| Expr::rat(num, r.den())),
+        Expr::Const(Const::NegInf) => Some(Expr::Const(Const::Inf)),
+        Expr::Prod(v) => {
+            // The first factor with a sign, as in `2*-x`.
+            let i = v.iter().position(|e| negated(e).is_some())?;
+            let mut factors = v.clone();
+            match negated(&v[i]).unwrap() {
+                Expr::Const(Const::Int(1)) => {
+                    factors.remove(i);
+                },
+                e => *factors[i] = e,
+            }
+            Some(match factors.len() {
+                0 => Expr::int(1),
+                1 => *factors.pop().unwrap(),
+                _ => Expr::Prod(factors),
+            })
+        },
+        _ => None,
+    }
+}
+
+struct Printer<'a> {
+    precision: Option<usize>,
+    out: &'a mut String,
+}
+
+impl Printer<'_> {
+    fn push(&mut self, s: &str) {
+        self.out.push_str(s);
+    }
+
+    fn float(&self, f: f64) -> String {
+        if f.is_nan() {
+            String::from("undef")
+        } else if f.is_infinite() {
+            String::from(if f > 0.0 { "inf" } else { "-inf" })
+        } else {
+            match self.precision {
+                Some(p) => format!("{:.*}", p, f),
+                None => format!("{:?}", f),
+            }
+        }
+    }
+
+    fn write_sexpr(&mut self, expr: &Expr) {
+        let (head, args): (String, &[Box<Expr>]) = match expr {
+            Expr::Sum(v) => (String::from("+"), v),
+            Expr::Prod(v) => (String::from("*"), v),
+            Expr::Call(fun, v) => (fun.to_string(), v),
+            Expr::Pow(lhs, rhs) => {
+                self.push("(^ ");
+                self.write_sexpr(lhs);
+                self.push(" ");
+                self.write_sexpr(rhs);
+                return self.push(")");
+            },
+            Expr::Neg(e) | Expr::Recipr(e) => {
+                self.push(if let Expr::Neg(_) = expr { "(- " } else { "(/ 1 " });
+                self.write_sexpr(e);
+                return self.push(")");
+            },
+            Expr::Const(Const::Float(f)) => {
+                let f = if f.is_nan() { String::from("NaN") } else { self.float(*f) };
+                return self.push(&f);
+            },
+            e => return self.push(&e.to_string()),
+        };
+        self.push(&format!("({}", head));
+        for arg in args {
+            self.push(" ");
+            self.write_sexpr(arg);
+        }
+        self.push(")");
+    }
+
+    fn wrap_infix(&mut self, expr: &Expr, min: u8) {
+        if prec(expr) < min {
+            self.push("(");
+            self.write_infix(expr);
+            self.push(")");
+        } else {
+            self.write_infix(expr);
+        }
+    }
+
+    fn write_infix(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Sum(v) => for (i, term) in v.iter().enumerate() {
+                match (i, &**term) {
+                    (0, term) => self.wrap_infix(term, 2),
+                    (_, term) => match negated(term) {
+                        Some(e) => {
+                            self.push(" - ");
+                            self.wrap_infix(&e, 2);
+                        },
+                        None => {
+                            self.push(" + ");
+                            self.wrap_infix(term, 2);
+                        },
+                    },
+                }
+            },
+            Expr::Prod(v) => for (i, factor) in v.iter().enumerate() {
+                match (i, &**factor) {
+                    (0, Expr::Recipr(e)) => {
+                        self.push("1/");
+                        self.wrap_infix(e, 3);
+                    },
+                    (_, Expr::Recipr(e)) => {
+                        self.push("/");
+                        self.wrap_infix(e, 3);
+                    },
+                    (0, factor) => self.wrap_infix(factor, 2),
+                    (_, factor) => {
+                        self.push("*");
+                        self.wrap_infix(factor, 3);
+                    },
+                }
+            },
+            Expr::Pow(base, exp) => {
+                self.wrap_infix(base, 5);
+                self.push("^");
+                self.wrap_infix(exp, 4);
+            },
+            Expr::Neg(e) => {
+                self.push("-");
+                self.wrap_infix(e, 4);
+            },
+            Expr::Recipr(e) => {
+                self.push("1/");
+                self.wrap_infix(e, 3);
+            },
+            Expr::Call(Func::Factorial, v) | Expr::Call(Func::DoubleFactorial, v) if v.len() == 1 => {
+                self.wrap_infix(&v[0], 5);
+                self.push(if let Expr::Call(Func::Factorial, _) = expr { "!" } else { "!!" });
+            },
+            Expr::Call(fun, v) => {
+                self.push(&match fun {
+                    Func::Func(s) => s.clone(),
+                    fun => format!("{:?}", fun).to_lowercase(),
+                });
+                self.push("(");
+                for (i, arg) in v.iter().enumerate() {
+                    if i > 0 {
+                        self.push(", ");
+                    }
+                    self.write_infix(arg);
+                }
+                self.push(")");
+            },
+            Expr::Const(Const::Float(f)) => {
+                let f = self.float(*f);
+                self.push(&f);
+            },
+            e => self.push(&e.to_string()),
+        }
+    }
+
+    fn wrap_latex(&mut self, expr: &Expr, min: u8) {
+        if prec(expr) < min {
+            self.push("\\left(");
+            self.write_latex(expr);
+            self.push("\\right)");
+        } else {
+            self.write_latex(expr);
+        }
+    }
+
+    fn product_latex(&mut self, factors: &[&Expr]) {
+        if let [factor] = factors {
+            return self.write_latex(factor);
+        }
+        for (i, factor) in factors.iter().enumerate() {
+            if i > 0 {
+                self.push(" \\cdot ");
+            }
+            self.wrap_latex(factor, 2);
+        }
+    }
+
+    fn call_latex(&mut self, name: &str, args: &[Box<Expr>]) {
+        self.push(name);
+        self.push("\\left(");
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                self.push(", ");
+            }
+            self.write_latex(arg);
+        }
+        self.push("\\right)");
+    }
+
+    fn write_latex(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Sum(v) => for (i, term) in v.iter().enumerate() {
+                match (i, &**term) {
+                    (0, term) => self.wrap_latex(term, 2),
+                    (_, term) => match negated(term) {
+                        Some(e) => {
+                            self.push(" - ");
+                            self.wrap_latex(&e, 2);
+                        },
+                        None => {
+                            self.push(" + ");
+                            self.wrap_latex(term, 2);
+                        },
+                    },
+                }
+            },
+            Expr::Prod(v) => {
+                let num: Vec<&Expr> = v.iter().filter(|e| !matches!(***e, Expr::Recipr(_))).map(|e| &**e).collect();
+                let den: Vec<&Expr> = v.iter().filter_map(|e| match &**e {
+                    Expr::Recipr(e) => Some(&**e),
+                    _ => None,
+                }).collect();
+                if den.is_empty() {
+                    return self.product_latex(&num);
+                }
+                self.push("\\frac{");
+                if num.is_empty() {
+                    self.push("1");
+                }
+                self.product_latex(&num);
+                self.push("}{");
+                self.product_latex(&den);
+                self.push("}");
+            },
+            Expr::Pow(base, exp) => {
+                self.wrap_latex(base, 5);
+                self.push("^{");
+                self.write_latex(exp);
+                self.push("}");
+            },
+            Expr::Neg(e) => {
+                self.push("-");
+                self.wrap_latex(e, 4);
+            },
+            Expr::Recipr(e) => {
+                self.push("\\frac{1}{");
+                self.write_latex(e);
+                self.push("}");
+            },
+            Expr::Call(fun, v) => match (fun, &v[..]) {
+                (Func::Sqrt, [x]) | (Func::Cbrt, [x]) | (Func::Root, [x, _]) => {
+                    self.push("\\sqrt");
+                    match (fun, &v[..]) {
+                        (Func::Cbrt, _) => self.push("[3]"),
+                        (Func::Root, [_, n]) => {
+                            self.push("[");
+                            self.write_latex(n);
+                            self.push("]");
+                        },
+                        _ => (),
+                    }
+                    self.push("{");
+                    self.write_latex(x);
+                    self.push("}");
+                },
+                (Func::Abs, [x]) | (Func::Floor, [x]) | (Func::Ceil, [x]) => {
+                    let (open, close) = match fun {
+                        Func::Abs => ("\\left|", "\\right|"),
+                        Func::Floor => ("\\left\\lfloor ", "\\right\\rfloor"),
+                        _ => ("\\left\\lceil ", "\\right\\rceil"),
+                    };
+                    self.push(open);
+                    self.write_latex(x);
+                    self.push(close);
+                },
+                (Func::Factorial, [x]) | (Func::DoubleFactorial, [x]) => {
+                    self.wrap_latex(x, 5);
+                    self.push(if let Func::Factorial = fun { "!" } else { "!!" });
+                },
+                (Func::Log, [_]) => self.call_latex("\\ln", v),
+                (Func::Log, [base, _]) => {
+                    self.push("\\log_{");
+                    self.write_latex(base);
+                    self.push("}");
+                    self.call_latex("", &v[1..]);
+                },
+                (Func::Sin, _) => self.call_latex("\\sin", v),
+                (Func::Cos, _) => self.call_latex("\\cos", v),
+                (Func::Tan, _) => self.call_latex("\\tan", v),
+                (Func::ASin, _) => self.call_latex("\\arcsin", v),
+                (Func::ACos, _) => self.call_latex("\\arccos", v),
+                (Func::ATan, _) => self.call_latex("\\arctan", v),
+                (Func::Gamma, _) => self.call_latex("\\Gamma", v),
+                (Func::Func(s), _) if s.chars().count() == 1 => self.call_latex(s, v),
+                (Func::Func(s), _) => self.call_latex(&format!("\\operatorname{{{}}}", s), v),
+                (fun, _) => self.call_latex(&format!("\\operatorname{{{}}}", format!("{:?}", fun).to_lowercase()), v),
+            },
+            Expr::Const(c) => {
+                let c = match c {
+                    Const::Float(f) => self.float(*f),
+                    Const::Rat(r) if r.num() < 0 => format!("-\\frac{{{}}}{{{}}}", r.num().unsigned_abs(), r.den()),
+                    Const::Rat(r) => format!("\\frac{{{}}}{{{}}}", r.num(), r.den()),
+                    Const::Inf => String::from("\\infty"),
+                    Const::NegInf => String::from("-\\infty"),
+                    Const::Undef => String::from("\\mathrm{undef}"),
+                    c => c.to_string(),
+                };
+                self.push(&c);
+            },
+            Expr::Var(s) if s == "pi" => self.push("\\pi"),
+            Expr::Var(s) if s.chars().count() == 1 => self.push(s),
+            Expr::Var(s) => self.push(&format!("\\mathrm{{{}}}", s)),
+            Expr::Error => self.push("?"),
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::expr::{Const, Expr, Func};
+use super::visit::{children, subs};
+
+/// A function implemented in Rust, which can be added to a `Registry` so
+/// that calls of `name` evaluate and differentiate.
+///
+/// ```
+/// use betadog::{Const, Expr, Func};
+/// use betadog::function::{Function, Registry};
+///
+/// struct Hypot;
+///
+/// impl Function for Hypot {
+///     fn name(&self) -> &str { "hypot" }
+///     fn arity(&self) -> usize { 2 }
+///     fn eval_f64(&self, args: &[f64]) -> Option<f64> { Some(args[0].hypot(args[1])) }
+///     fn derivative(&self, args: &[Expr], i: usize) -> Option<Expr> {
+///         Some(args[i].clone() / Expr::call(Func::Func(String::from("hypot")), args.to_vec()))
+///     }
+/// }
+///
+/// let mut registry = Registry::new();
+/// registry.register(Hypot).unwrap();
+/// let call = Expr::call(Func::Func(String::from("hypot")), vec![Expr::float(3.0), Expr::int(4)]);
+/// assert_eq!(betadog::eval::eval_with(&call, &registry), Expr::float(5.0));
+/// ```
+pub trait Function {
+    fn name(&self) -> &str;
+
+    fn arity(&self) -> usize;
+
+    /// The exact value at constant arguments, which are never `Undef`.
+    /// `None` leaves the call unevaluated unless `eval_f64` applies.
+    fn eval_exact(&self, _args: &[Const]) -> Option<Const> {
+        None
+    }
+
+    /// The value when at least one argument is a `Float`.
+    fn eval_f64(&self, _args: &[f64]) -> Option<f64> {
+        None
+    }
+
+    /// The partial derivative in the `i`th argument, as an expression of
+    /// `args`. `None` leaves the derivative unevaluated.
+    fn derivative(&self, _args: &[Expr], _i: usize) -> Option<Expr> {
+        None
+    }
+}
+
+/// The meaning of a user function in a `Registry`.
+pub enum Definition {
+    /// `f(x, y) := x*y + 1`, which is expanded where it is called.
+    Symbolic{params: Vec<String>, body: Expr},
+    Native(Box<dyn Function>),
+}
+
+impl Definition {
+    pub fn arity(&self) -> usize {
+        match self {
+            Definition::Symbolic{params, ..} => params.len(),
+            Definition::Native(f) => f.arity(),
+        }
+    }
+
+    /// The body with `args` in place of the parameters, for a symbolic
+    /// function called with the right number of arguments.
+    pub fn expand(&self, args: &[Expr]) -> Option<Expr> {
+        match self {
+            Definition::Symbolic{params, body} if params.len() == args.len() =>
+                Some(substitute(body, params, args)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for Definition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Definition::Symbolic{params, body} => f.debug_struct("Symbolic")
+                .field("params", params)
+                .field("body", body)
+                .finish(),
+            Definition::Native(fun) => write!(f, "Native({}/{})", fun.name(), fun.arity()),
+        }
+    }
+}
+
+/// The user functions that `Func::Func` calls refer to, by name. Used by
+/// `eval::eval_with` and `diff::diff_with`; calls of names that are not
+/// registered are left alone.
+#[derive(Debug, Default)]
+pub struct Registry {
+    functions: BTreeMap<String, Definition>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// Defines `name(params...) := body`, replacing any previous function of
+    /// that name. Fails if `name` is a builtin or the body calls `name`,
+    /// directly or through other registered functions.
+    pub fn define(&mut self, name: &str, params: Vec<String>, body: Expr) -> Result<(), String> {
+        check_name(name)?;
+        if let Some(path) = self.path_to(&body, name, &mut Vec::new()) {
+            return Err(format!("Recursive definition of {}: {} → {}", name, name, path.join(" → ")));
+        }
+        self.functions.insert(String::from(name), Definition::Symbolic{params, body});
+        Ok(())
+    }
+
+    /// Adds a native function under `f.name()`, replacing any previous
+    /// function of that name. Fails if the name is a builtin.
+    pub fn register<F: Function + 'static>(&mut self, f: F) -> Result<(), String> {
+        check_name(f.name())?;
+        self.functions.insert(String::from(f.name()), Definition::Native(Box::new(f)));
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Definition> {
+        self.functions.get(name)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Definition> {
+        self.functions.remove(name)
+    }
+
+    /// Removes the symbolic functions and keeps the native ones.
+    pub fn clear_symbolic(&mut self) {
+        self.functions.retain(|_, def| matches!(def, Definition::Native(_)));
+    }
+
+    /// The registered names in order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.functions.keys().map(|name| &name[..])
+    }
+
+    // The chain of functions through which `expr` calls `target`, if any.
+    fn path_to(&self, expr: &Expr, target: &str, visited: &mut Vec<String>) -> Option<Vec<String>> {
+        let mut calls = Vec::new();
+        called(expr, &mut calls);
+        for name in calls {
+            if name == target {
+                return Some(vec![name]);
+            }
+            if visited.contains(&name) {
+                continue;
+            }
+            visited.push(name.clone());
+            if let Some(Definition::Symbolic{body, ..}) = self.functions.get(&name) {
+                if let Some(mut path) = self.path_to(body, target, visited) {
+                    path.insert(0, name);
+                    return Some(path);
+                }
+            }
+        }
+        None
+    }
+}
+
+fn check_name(name: &str) -> Result<(), String> {
+    if Func::from_name(name).is_builtin() {
+        return Err(format!("{} is a builtin function and cannot be defined", name));
+    }
+    Ok(())
+}
+
+// The user functions called in `expr`.
+fn called(expr: &Expr, names: &mut Vec<String>) {
+    if let Expr::Call(Func::Func(name), _) = expr {
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+    children(expr).into_iter().for_each(|e| called(e, names));
+}
+
+// Replaces each variable in `params` with the matching `args`.
+pub(crate) fn substitute(expr: &Expr, params: &[String], args: &[Expr]) -> Expr {
+    let pairs: Vec<(Expr, Expr)> = params.iter().map(|p| Expr::var(p)).zip(args.iter().cloned()).collect();
+    subs(expr, &pairs)
+}
+
+// Rebuilds `expr` with `f` applied to each of its children.
+pub(crate) fn map_children<E, F: FnMut(&Expr) -> Result<Expr, E>>(expr: &Expr, mut f: F) -> Result<Expr, E> {
+    fn each<E, F: FnMut(&Expr) -> Result<Expr, E>>(v: &[Box<Expr>], f: &mut F) -> Result<Vec<Box<Expr>>, E> {
+        v.iter().map(|e| f(e).map(Box::new)).collect()
+    }
+    Ok(match expr {
+        Expr::Sum(v) => Expr::Sum(each(v, &mut f)?),
+        Expr::Prod(v) => Expr::Prod(each(v, &mut f)?),
+        Expr::Call(fun, v) => Expr::Call(fun.clone(), each(v, &mut f)?),
+        Expr::Pow(lhs, rhs) => Expr::Pow(Box::new(f(lhs)?), Box::new(f(rhs)?)),
+        Expr::Neg(e) => Expr::Neg(Box::new(f(e)?)),
+        Expr::Recipr(e) => Expr::Recipr(Box::new(f(e)?)),
+        e => e.clone(),
+    })
+}
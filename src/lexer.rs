@@ -0,0 +1,573 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::iter::{Peekable};
+use std::convert::TryFrom;
+use super::expr::Const;
+use super::rat;
+
+/// A range of bytes `start..end` in the source text.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span{start, end}
+    }
+
+    /// The smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span{start: self.start.min(other.start), end: self.end.max(other.end)}
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    InvalidLiteral,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    kind: ErrorKind,
+    span: Span,
+    message: String,
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn hint(&self) -> Option<&'static str> {
+        match self.kind {
+            ErrorKind::InvalidLiteral => Some("numbers look like 12, 0.5, .5, 1e-3, 1_000 or 0xff"),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.span.start)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A token. Names and operators borrow from the source where they can.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Tok<'src> {
+    Iden(&'src str),
+    Op(&'src str),
+    Lit(Const),
+    LParen,
+    RParen,
+    Comma,
+    // `|`, which both opens and closes an absolute value.
+    Bar,
+    LFloor,
+    RFloor,
+    LCeil,
+    RCeil,
+    Inf,
+    Undef,
+    // The statement syntax of `script`: `;`, `{`, `}`, `..` and `..=`.
+    Semi,
+    LBrace,
+    RBrace,
+    DotDot,
+    DotDotEq,
+    // Stands in for input that `lex_recovering` could not read.
+    Error,
+}
+
+/// A `Tok` together with the bytes of the input it was read from.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Token<'src> {
+    pub tok: Tok<'src>,
+    pub span: Span,
+}
+
+/// Every operator the lexer knows. A run of symbols is split into the
+/// longest operators in this table, so `*-` is `*` then `-`; a symbol that
+/// starts none of them becomes an `Op` on its own.
+pub const OPERATORS: &[&str] = &[
+    "+", "-", "*", "/", "^", "!", "!!", "%", "√", "∛", ":=", "=", "<", "<=", ">", ">=", "==", "!=", "->",
+];
+
+// Typographic operators that mean the same as an ASCII one.
+fn normalize_op(c: char) -> char {
+    match c {
+        '×' | '·' | '⋅' | '∙' | '∗' => '*',
+        '÷' | '∕' => '/',
+        '−' => '-',
+        c => c,
+    }
+}
+
+fn bracket(c: char) -> Option<Tok<'static>> {
+    match c {
+        '|' => Some(Tok::Bar),
+        '⌊' => Some(Tok::LFloor),
+        '⌋' => Some(Tok::RFloor),
+        '⌈' => Some(Tok::LCeil),
+        '⌉' => Some(Tok::RCeil),
+        _ => None,
+    }
+}
+
+// Single characters that stand for a whole token.
+fn symbol(c: char) -> Option<Tok<'static>> {
+    let frac = |n, d| Some(Tok::Lit(Const::Rat(rat::Rat::new(n, d))));
+    match c {
+        'π' => Some(Tok::Iden("pi")),
+        '∞' => Some(Tok::Inf),
+        '½' => frac(1, 2),
+        '⅓' => frac(1, 3),
+        '⅔' => frac(2, 3),
+        '¼' => frac(1, 4),
+        '¾' => frac(3, 4),
+        '⅕' => frac(1, 5),
+        '⅖' => frac(2, 5),
+        '⅗' => frac(3, 5),
+        '⅘' => frac(4, 5),
+        '⅙' => frac(1, 6),
+        '⅚' => frac(5, 6),
+        '⅐' => frac(1, 7),
+        '⅛' => frac(1, 8),
+        '⅜' => frac(3, 8),
+        '⅝' => frac(5, 8),
+        '⅞' => frac(7, 8),
+        '⅑' => frac(1, 9),
+        '⅒' => frac(1, 10),
+        _ => None,
+    }
+}
+
+// The ASCII character a superscript digit or sign stands for.
+fn superscript(c: char) -> Option<char> {
+    match c {
+        '⁰' => Some('0'),
+        '¹' => Some('1'),
+        '²' => Some('2'),
+        '³' => Some('3'),
+        '⁴'..='⁹' => std::char::from_digit(c as u32 - '⁴' as u32 + 4, 10),
+        '⁻' => Some('-'),
+        _ => None,
+    }
+}
+
+/// Syntax that is off by default.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// Reads decimal and scientific literals as exact `Int`s and `Rat`s, so
+    /// `0.1` is `1/10` and `1e-3` is `1/1000`, instead of as `Float`s.
+    pub exact_decimals: bool,
+    /// Reads a parenthesised group of digits after the decimal point as
+    /// repeating: `0.(3)` is `1/3` and `0.1(6)` is `1/6`. These are always
+    /// exact.
+    pub repeating_decimals: bool,
+    /// Skips `#` and the rest of its line, as in scripts and rule files.
+    pub comments: bool,
+}
+
+/// Reads tokens from a string one at a time. Names and operators in the
+/// tokens are slices of the string, so lexing allocates nothing for them.
+///
+/// A malformed literal is returned as an `Err` in place of its token, and
+/// lexing carries on after it.
+///
+/// ```
+/// use betadog::lexer::{Lexer, Tok};
+///
+/// let toks: Vec<_> = Lexer::new("sin(x) × 2").map(|t| t.unwrap().tok).collect();
+/// assert_eq!(toks[..2], [Tok::Iden("sin"), Tok::LParen]);
+/// assert_eq!(toks[4], Tok::Op("*"));
+/// ```
+#[derive(Clone)]
+pub struct Lexer<'src> {
+    src: &'src str,
+    current: Peekable<std::str::Chars<'src>>,
+    index: usize,
+    options: Options,
+    // Tokens already read that come before the next one, such as the `^`
+    // in front of a superscript.
+    pending: VecDeque<Token<'src>>,
+    // When set, errors are collected here and a `Tok::Error` is returned
+    // in their place.
+    errors: Option<Vec<Error>>,
+}
+
+impl<'src> Lexer<'src> {
+    pub fn new(src: &'src str) -> Lexer<'src> {
+        Lexer::with_options(src, Options::default())
+    }
+
+    pub fn with_options(src: &'src str, options: Options) -> Lexer<'src> {
+        Lexer {
+            src,
+            current: src.chars().peekable(),
+            index: 0,
+            options,
+            pending: VecDeque::new(),
+            errors: None,
+        }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.current.next()?;
+        self.index += c.len_utf8();
+        Some(c)
+    }
+
+    // The next token, or `None` at the end of the input.
+    fn token(&mut self) -> Option<Result<Token<'src>, Error>> {
+        while let Some(&current) = self.current.peek() {
+            let start = self.index;
+            let tok = if current == '(' {
+                self.bump();
+                Tok::LParen
+            } else if current == ')' {
+                self.bump();
+                Tok::RParen
+            } else if current == ',' {
+                self.bump();
+                Tok::Comma
+            } else if current == ';' {
+                self.bump();
+                Tok::Semi
+            } else if current == '{' {
+                self.bump();
+                Tok::LBrace
+            } else if current == '}' {
+                self.bump();
+                Tok::RBrace
+            } else if current == '.' && self.peek_nth(1) == Some('.') {
+                self.bump();
+                self.bump();
+                if self.current.peek() == Some(&'=') {
+                    self.bump();
+                    Tok::DotDotEq
+                } else {
+                    Tok::DotDot
+                }
+            } else if current == '#' && self.options.comments {
+                // A comment runs to the end of the line.
+                while self.current.peek().is_some_and(|c| *c != '\n') {
+                    self.bump();
+                }
+                continue;
+            } else if let Some(tok) = bracket(current) {
+                self.bump();
+                tok
+            } else if current.is_ascii_digit() || current == '.' || superscript(current).is_some() {
+                let lit = if current.is_ascii_digit() || current == '.' {
+                    self.literal()
+                } else {
+                    self.superscript()
+                };
+                match lit {
+                    Ok(tok) => tok,
+                    Err(err) => {
+                        // Skip the rest of the malformed literal.
+                        while let Some(c) = self.current.peek() {
+                            if !c.is_ascii_digit() && *c != '.' && *c != '_' {
+                                break;
+                            }
+                            self.bump();
+                        }
+                        match &mut self.errors {
+                            Some(errors) => {
+                                errors.push(err);
+                                Tok::Error
+                            },
+                            None => {
+                                self.pending.clear();
+                                return Some(Err(err));
+                            },
+                        }
+                    },
+                }
+            } else if current.is_whitespace() {
+                self.bump();
+                continue;
+            } else if let Some(tok) = symbol(current) {
+                self.bump();
+                tok
+            } else if current.is_alphabetic() {
+                self.iden()
+            } else {
+                self.operator()
+            };
+            return Some(Ok(Token{tok, span: Span::new(start, self.index)}));
+        }
+        None
+    }
+
+    fn invalid_literal<T>(&self, start: usize, message: &str) -> Result<T, Error> {
+        Err(Error{
+            kind: ErrorKind::InvalidLiteral,
+            span: Span::new(start, self.index),
+            message: String::from(message),
+        })
+    }
+
+    // The character `n` places ahead of the next one.
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        self.current.clone().nth(n)
+    }
+
+    // Reads digits in `radix`, allowing single `_` separators between them.
+    fn digits(&mut self, radix: u32) -> Digits {
+        let mut digits = Digits{len: 0, value: Some(0)};
+        while let Some(&c) = self.current.peek() {
+            if let Some(d) = c.to_digit(radix) {
+                digits.push(radix, d);
+                self.bump();
+            } else if c == '_' && digits.len > 0 && self.peek_nth(1).is_some_and(|c| c.is_digit(radix)) {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+        digits
+    }
+
+    fn literal(&mut self) -> Result<Tok<'src>, Error> {
+        let start = self.index;
+
+        // `0x`, `0o` and `0b` only start a prefixed literal when a digit
+        // follows, so that `0b` is still `0 b` with implicit multiplication.
+        let radix = match (self.peek_nth(0), self.peek_nth(1)) {
+            (Some('0'), Some('x')) | (Some('0'), Some('X')) => 16,
+            (Some('0'), Some('o')) | (Some('0'), Some('O')) => 8,
+            (Some('0'), Some('b')) | (Some('0'), Some('B')) => 2,
+            _ => 10,
+        };
+        if radix != 10 && self.peek_nth(2).is_some_and(|c| c.is_digit(radix)) {
+            self.bump();
+            self.bump();
+            return match self.digits(radix).value {
+                Some(i) => Ok(Tok::Lit(Const::Int(i))),
+                None => self.invalid_literal(start, "Integer literal is too large"),
+            };
+        }
+
+        let int = self.digits(10);
+        let mut frac = None;
+        // In `1..5` the dots are a range.
+        if self.current.peek() == Some(&'.') && self.peek_nth(1) != Some('.') {
+            self.bump();
+            frac = Some(self.digits(10));
+        }
+        if int.len == 0 && frac.is_none_or(|f| f.len == 0) {
+            return self.invalid_literal(start, "Invalid numeric literal");
+        }
+
+        let mut repeating = None;
+        if self.options.repeating_decimals && frac.is_some() && self.current.peek() == Some(&'(') {
+            let len = self.current.clone().skip(1).take_while(char::is_ascii_digit).count();
+            if len > 0 && self.peek_nth(len + 1) == Some(')') {
+                self.bump();
+                repeating = Some(self.digits(10));
+                self.bump();
+            }
+        }
+
+        let mut exp = None;
+        if let Some('e') | Some('E') = self.current.peek() {
+            let sign = match self.peek_nth(1) {
+                Some('+') | Some('-') => 1,
+                _ => 0,
+            };
+            if self.peek_nth(1 + sign).is_some_and(|c| c.is_ascii_digit()) {
+                self.bump();
+                let negative = sign == 1 && self.bump() == Some('-');
+                match self.digits(10).value.and_then(|e| i32::try_from(e).ok()) {
+                    Some(e) => exp = Some(if negative { -e } else { e }),
+                    None => return self.invalid_literal(start, "Exponent is too large"),
+                }
+            }
+        }
+
+        let text = &self.src[start..self.index];
+        if self.current.peek() == Some(&'.') && self.peek_nth(1) != Some('.') {
+            self.bump();
+            return self.invalid_literal(start, "Invalid numeric literal");
+        }
+
+        let is_float = frac.is_some() || exp.is_some();
+        if repeating.is_some() || (is_float && self.options.exact_decimals) {
+            let frac = frac.unwrap_or(Digits{len: 0, value: Some(0)});
+            return match exact_decimal(int, frac, repeating, exp.unwrap_or(0)) {
+                Some(c) => Ok(Tok::Lit(c)),
+                None => self.invalid_literal(start, "Numeric literal is too large to represent exactly"),
+            };
+        }
+
+        if is_float {
+            // Only a literal with separators needs a copy without them.
+            let f = if text.contains('_') { text.replace('_', "").parse() } else { text.parse() };
+            Ok(Tok::Lit(Const::Float(f.expect("Float literals are valid Rust floats"))))
+        } else {
+            match int.value {
+                Some(i) => Ok(Tok::Lit(Const::Int(i))),
+                None => self.invalid_literal(start, "Integer literal is too large"),
+            }
+        }
+    }
+
+    // `x²` and `x⁻¹` are read as `x ^ 2` and `x ^ -1`. The `^` is queued
+    // here with an empty span, and the exponent is returned.
+    fn superscript(&mut self) -> Result<Tok<'src>, Error> {
+        let start = self.index;
+        self.pending.push_back(Token{tok: Tok::Op("^"), span: Span::new(start, start)});
+        if let Some('⁻') = self.current.peek() {
+            self.bump();
+            self.pending.push_back(Token{tok: Tok::Op("-"), span: Span::new(start, self.index)});
+        }
+        let start = self.index;
+        let mut digits = Digits{len: 0, value: Some(0)};
+        while let Some(d) = self.current.peek().and_then(|&c| superscript(c)).and_then(|c| c.to_digit(10)) {
+            digits.push(10, d);
+            self.bump();
+        }
+        if digits.len == 0 {
+            return self.invalid_literal(start, "Expected a superscript digit");
+        }
+        match digits.value {
+            Some(i) => Ok(Tok::Lit(Const::Int(i))),
+            None => self.invalid_literal(start, "Integer literal is too large"),
+        }
+    }
+
+    fn operator(&mut self) -> Tok<'src> {
+        let start = self.index;
+        let starts_with = |op: &str| {
+            let mut ahead = self.current.clone().map(normalize_op);
+            op.chars().all(|c| ahead.next() == Some(c))
+        };
+        let longest = OPERATORS.iter()
+            .filter(|op| starts_with(op))
+            .max_by_key(|op| op.chars().count());
+        match longest {
+            Some(op) => {
+                for _ in op.chars() {
+                    self.bump();
+                }
+                Tok::Op(op)
+            },
+            None => {
+                self.bump();
+                Tok::Op(&self.src[start..self.index])
+            },
+        }
+    }
+
+    fn iden(&mut self) -> Tok<'src> {
+        let start = self.index;
+        while let Some(&c) = self.current.peek() {
+            if c.is_alphanumeric() && symbol(c).is_none() && superscript(c).is_none() {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+
+        match &self.src[start..self.index] {
+            "inf" => Tok::Inf,
+            "undef" => Tok::Undef,
+            s => Tok::Iden(s),
+        }
+    }
+}
+
+impl<'src> Iterator for Lexer<'src> {
+    type Item = Result<Token<'src>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(token) = self.pending.pop_front() {
+            return Some(Ok(token));
+        }
+        let token = self.token()?;
+        match (token, self.pending.pop_front()) {
+            (Ok(token), Some(first)) => {
+                self.pending.push_back(token);
+                Some(Ok(first))
+            },
+            (token, _) => Some(token),
+        }
+    }
+}
+
+// The exact value of `int.frac(repeating)e exp`, or `None` on overflow.
+fn exact_decimal(int: Digits, frac: Digits, repeating: Option<Digits>, exp: i32) -> Option<Const> {
+    let pow10 = |n: usize| 10i128.checked_pow(u32::try_from(n).ok()?);
+    let fixed = int.value?.checked_mul(pow10(frac.len)?)?.checked_add(frac.value?)?;
+    let (mut num, mut den) = match repeating {
+        None => (fixed, pow10(frac.len)?),
+        Some(repeating) => {
+            // x = fixed / 10^f + r / (10^f * (10^k - 1))
+            let shift = pow10(repeating.len)?;
+            let num = fixed.checked_mul(shift)?.checked_add(repeating.value?)?.checked_sub(fixed)?;
+            (num, pow10(frac.len)?.checked_mul(shift - 1)?)
+        },
+    };
+    if exp >= 0 {
+        num = num.checked_mul(pow10(exp as usize)?)?;
+    } else {
+        den = den.checked_mul(pow10(exp.unsigned_abs() as usize)?)?;
+    }
+    let r = rat::Rat::new(num, den);
+    Some(if r.den() == 1 { Const::Int(r.num()) } else { Const::Rat(r) })
+}
+
+// A run of digits in a numeric literal, read as it is lexed.
+#[derive(Copy, Clone)]
+struct Digits {
+    len: usize,
+    // `None` once the value overflows an `i128`.
+    value: Option<i128>,
+}
+
+impl Digits {
+    fn push(&mut self, radix: u32, digit: u32) {
+        self.len += 1;
+        self.value = self.value
+            .and_then(|v| v.checked_mul(i128::from(radix)))
+            .and_then(|v| v.checked_add(i128::from(digit)));
+    }
+}
+
+/// Splits `s` into tokens, stopping at the first error.
+pub fn lex(s: &str) -> Result<Vec<Token<'_>>, Error> {
+    Lexer::new(s).collect()
+}
+
+/// Like `lex`, with the syntax in `options`.
+pub fn lex_with(s: &str, options: Options) -> Result<Vec<Token<'_>>, Error> {
+    Lexer::with_options(s, options).collect()
+}
+
+/// Like `lex`, but carries on after an error. Input that could not be read
+/// becomes a `Tok::Error`, and all errors are returned alongside the tokens.
+pub fn lex_recovering(s: &str) -> (Vec<Token<'_>>, Vec<Error>) {
+    lex_recovering_with(s, Options::default())
+}
+
+/// Like `lex_recovering`, with the syntax in `options`.
+pub fn lex_recovering_with(s: &str, options: Options) -> (Vec<Token<'_>>, Vec<Error>) {
+    let mut lexer = Lexer::with_options(s, options);
+    lexer.errors = Some(Vec::new());
+    let toks = lexer.by_ref().collect::<Result<_, _>>().unwrap_or_default();
+    (toks, lexer.errors.unwrap_or_default())
+}
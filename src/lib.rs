@@ -0,0 +1,1148 @@
+//! A small computer algebra system.
+//!
+//! Input is turned into tokens by [`lexer::lex`], into an [`Expr`] tree by
+//! [`parser::parse`] and simplified by [`eval::eval`]. [`parse_str`] runs the
+//! first two steps with the default operator table:
+//!
+//! ```
+//! let expr = betadog::parse_str("1 + 2*x + 3").unwrap();
+//! assert_eq!(betadog::eval(&expr).to_string(), "(+ (* 2 x) 4)");
+//! ```
+//!
+//! `Display for Expr` prints s-expressions, which [`sexpr::read`] reads back;
+//! [`format`](mod@format) also writes infix and LaTeX.
+//! [`diff()`] differentiates, and user functions for both live in a
+//! [`function::Registry`]. [`simplify()`] goes further than `eval`, collecting
+//! like terms, and can record each rule it applies. [`rewrite`] applies
+//! rules written as text, such as `sin(a)^2 + cos(a)^2 -> 1`, and
+//! [`visit`] has substitution and traversals for writing more. [`dag`]
+//! shares equal subtrees of large expressions, and [`compile()`] turns one
+//! into bytecode for evaluating it at many points.
+//!
+//! [`session::Session`] keeps definitions between inputs, and [`script`]
+//! runs programs of statements in one.
+//!
+//! The library needs no features. `serde` makes [`Expr`] and its parts
+//! serialisable, and the `betadog` binary is built with `cli`.
+
+#![allow(clippy::vec_box)]
+
+use std::collections::HashMap;
+use std::fmt;
+
+pub mod compile;
+pub mod dag;
+pub mod diagnostic;
+pub mod diff;
+pub mod eval;
+pub mod expr;
+pub mod format;
+pub mod function;
+pub mod lexer;
+#[doc(hidden)]
+#[macro_use]
+pub mod macros;
+mod ops;
+pub mod parser;
+pub mod rat;
+pub mod rewrite;
+pub mod script;
+pub mod session;
+pub mod sexpr;
+pub mod simplify;
+pub mod visit;
+
+pub use compile::compile;
+pub use diagnostic::Diagnostic;
+pub use diff::diff;
+pub use eval::eval;
+pub use expr::{Const, Expr, Func};
+pub use lexer::lex;
+pub use parser::parse;
+pub use rat::Rat;
+pub use simplify::simplify;
+pub use visit::subs;
+
+/// An error from either stage of [`parse_str`].
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    Lex(lexer::Error),
+    Parse(parser::Error),
+}
+
+impl Error {
+    pub fn span(&self) -> lexer::Span {
+        match self {
+            Error::Lex(err) => err.span(),
+            Error::Parse(err) => err.span(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Lex(err) => write!(f, "{}", err),
+            Error::Parse(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<lexer::Error> for Error {
+    fn from(err: lexer::Error) -> Self {
+        Error::Lex(err)
+    }
+}
+
+impl From<parser::Error> for Error {
+    fn from(err: parser::Error) -> Self {
+        Error::Parse(err)
+    }
+}
+
+/// The binary operators understood by [`parse_str`] and their precedences.
+pub fn default_ops() -> HashMap<String, i8> {
+    let mut ops = HashMap::with_capacity(5);
+    ops.insert(String::from("^"), 60);
+    ops.insert(String::from("*"), 40);
+    ops.insert(String::from("/"), 40);
+    ops.insert(String::from("+"), 20);
+    ops.insert(String::from("-"), 20);
+    ops
+}
+
+/// Lexes and parses `s` using [`default_ops`]. The parser reads tokens
+/// as it needs them, so the error returned is the first one in `s`.
+pub fn parse_str(s: &str) -> Result<Expr, Error> {
+    // A lexer error ends the tokens, and the parser fails on what it got.
+    let mut lex_error = None;
+    let toks = lexer::Lexer::new(s).map_while(|t| t.map_err(|err| lex_error = Some(err)).ok());
+    let expr = parse(toks, default_ops());
+    match lex_error {
+        Some(err) => Err(err.into()),
+        None => Ok(expr?),
+    }
+}
+
+/// Lexes and parses `s` without stopping at the first error, returning the
+/// partial tree along with a diagnostic for every problem found. See
+/// [`parser::parse_recovering`].
+///
+/// ```
+/// let (expr, diagnostics) = betadog::parse_str_recovering("(1 +) * f(2 3");
+/// assert_eq!(expr.to_string(), "(* (+ 1 ?) (f 2))");
+/// assert_eq!(diagnostics.len(), 3);
+/// ```
+pub fn parse_str_recovering(s: &str) -> (Expr, Vec<Diagnostic>) {
+    let (toks, lex_errors) = lexer::lex_recovering(s);
+    let (expr, parse_errors) = parser::parse_recovering(toks, default_ops());
+    let mut diagnostics: Vec<Diagnostic> = lex_errors.iter().map(Diagnostic::from)
+        .chain(parse_errors.iter().map(Diagnostic::from))
+        .collect();
+    diagnostics.sort_by_key(|d| d.span.start);
+    (expr, diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap};
+    use super::eval::*;
+    use super::expr::*;
+    use super::lexer::*;
+    use super::parser::*;
+    use super::rat::Rat;
+    use super::sexpr::read;
+    use super::lexer::lex_recovering;
+    use super::parser::{parse_recovering, parse_with, ImplicitDiv, Options};
+    use super::diagnostic::Diagnostic;
+    #[test]
+    #[allow(clippy::approx_constant)]
+    fn test_lexer() {
+        let lex = |x| lex(x).map(|toks| toks.into_iter().map(|t| t.tok).collect::<Vec<_>>());
+        use Tok::*;
+        assert_eq!(lex(""), Ok(Vec::new()));
+        assert_eq!(lex("5"), Ok(vec![Lit(Const::Int(5))]));
+        assert_eq!(lex("3.14"), Ok(vec![Lit(Const::Float(3.14))]));
+        assert_eq!(lex("()"), Ok(vec![LParen, RParen]));
+        assert!(lex("3.3.3.3").is_err());
+        assert!(lex(".").is_err());
+        assert_eq!(lex(".0"), Ok(vec![Lit(Const::Float(0.0))]));
+        assert_eq!(lex("0."), Ok(vec![Lit(Const::Float(0.0))]));
+        assert_eq!(lex("+ - * / "), Ok(vec![
+            Op("+"), Op("-"), Op("*"), Op("/")
+        ]));
+        assert_eq!(lex("f(x,-y)"), Ok(vec![
+            Iden("f"), LParen, Iden("x"), Comma, Op("-"), Iden("y"), RParen
+        ]))
+    }
+
+    #[test]
+    fn test_spans() {
+        let spans = |x| super::lex(x).unwrap().into_iter().map(|t| t.span).collect::<Vec<_>>();
+        let span = Span::new;
+        assert_eq!(spans(" ab+ 12.5"), vec![span(1, 3), span(3, 4), span(5, 9)]);
+        assert_eq!(spans("π*x"), vec![span(0, 2), span(2, 3), span(3, 4)]);
+
+        let err = super::lex("1 + 3.3.3").unwrap_err();
+        assert_eq!((err.kind(), err.span()), (super::lexer::ErrorKind::InvalidLiteral, span(4, 8)));
+        assert!(super::lex("1000000000000000000000000000000000000000000").is_err());
+
+        let parse_err = |x| super::parse_str(x).unwrap_err();
+        assert_eq!(parse_err("1 + * 2").span(), span(4, 5));
+        assert_eq!(parse_err("1 +").span(), span(3, 3));
+        assert_eq!(parse_err("2 * (1 + x").span(), span(4, 5));
+        assert_eq!(parse_err("x y z").span(), span(2, 5));
+        assert_eq!(parse_err("1 & 2").span(), span(2, 3));
+        match parse_err("f(1 2)") {
+            super::Error::Parse(err) => assert_eq!(err.kind(), super::parser::ErrorKind::ExpectedDelimiter),
+            err => panic!("{:?}", err),
+        }
+
+        let src = "1 + 2\n  * (x + 1";
+        let err = super::parse_str(src).unwrap_err();
+        assert_eq!(Diagnostic::from(&err).render(src), concat!(
+            "error: Unclosed '('\n",
+            "  |\n",
+            "2 |   * (x + 1\n",
+            "  |     ^\n",
+            "  = hint: add a matching ')'\n",
+        ));
+        assert!(Diagnostic::from(&err).render_from(src, 9).starts_with("error: Unclosed '('\n   |\n10 |   * (x + 1\n"));
+    }
+
+    #[test]
+    fn test_token_stream() {
+        use Tok::*;
+        let src = "sin(x²) + 3.3.3 × yz";
+        let toks: Vec<_> = Lexer::new(src).map(|t| t.map(|t| t.tok)).collect();
+        assert_eq!(toks[..7], [Ok(Iden("sin")), Ok(LParen), Ok(Iden("x")), Ok(Op("^")), Ok(Lit(Const::Int(2))), Ok(RParen), Ok(Op("+"))]);
+        assert_eq!(toks[7].as_ref().unwrap_err().span(), Span::new(11, 15));
+        assert_eq!(toks[8..], [Ok(Op("*")), Ok(Iden("yz"))]);
+
+        // Names are slices of the source.
+        match Lexer::new(src).last() {
+            Some(Ok(Token{tok: Iden(name), span})) => assert!(std::ptr::eq(name, &src[span.start..span.end])),
+            t => panic!("{:?}", t),
+        }
+
+        let expr = parse(Lexer::new("2*(x + 1)").map(Result::unwrap), super::default_ops()).unwrap();
+        assert_eq!(expr, super::parse_str("2*(x + 1)").unwrap());
+        // The parser stops before the malformed literal, so its error is first.
+        assert!(matches!(super::parse_str("1 + ) 3.3.3"), Err(super::Error::Parse(_))));
+        assert!(matches!(super::parse_str("(1 + 3.3.3)"), Err(super::Error::Lex(_))));
+    }
+
+    #[test]
+    fn test_parser() {
+        use self::Const::*;
+        use Tok::*;
+        use Expr::*;
+
+        let ops = {
+            let mut ops = HashMap::new();
+            ops.insert(String::from("*"), 40);
+            ops.insert(String::from("/"), 40);
+            ops.insert(String::from("+"), 20);
+            ops.insert(String::from("-"), 20);
+            ops
+        };
+        let parse = |toks: Vec<Tok>| parse(toks.into_iter().map(|tok| Token{tok, span: Span::default()}), ops.clone());
+        assert!(parse(Vec::new()).is_err());
+        assert_eq!(parse(vec![Lit(Int(5))]), Ok(Const(Int(5))));
+
+        // The trace can be any closure, here one that keeps what it sees.
+        let partials = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let seen = partials.clone();
+        let options = Options{trace: Some(std::rc::Rc::new(move |e: &Expr| seen.borrow_mut().push(e.to_string()))), ..Options::default()};
+        parse_with(lex("a + 2*b*c").unwrap(), super::default_ops(), options).unwrap();
+        assert_eq!(*partials.borrow(), ["(+ a)", "(* 2)", "(* 2 b)", "(+ a (* 2 b c))"]);
+    }
+
+    #[test]
+    fn test_rat() {
+        let new_rat = |num, den| Rat::new(num, den);
+
+        assert_eq!(new_rat(3, 5) + new_rat(2, 5), new_rat(1, 1));
+        
+        assert_eq!(new_rat(4, 9) + new_rat(9, 4), new_rat(97, 36));
+        assert!(new_rat(4, 9) < new_rat(1, 2));
+        assert_eq!(new_rat(1, 2), new_rat(2, 4));
+        assert_eq!(new_rat(-1, 2).num(), -1);
+        assert_eq!(new_rat(3, -6).den(), 2);
+
+        assert_eq!(new_rat(1, 2).checked_sub(new_rat(1, 3)), Some(new_rat(1, 6)));
+        assert_eq!(new_rat(2, 3).checked_div(new_rat(4, 9)), Some(new_rat(3, 2)));
+        assert_eq!(new_rat(1, 2).checked_div(new_rat(0, 1)), None);
+        assert_eq!(new_rat(i128::MAX, 2).checked_add(new_rat(1, 3)), None);
+        assert_eq!(new_rat(i128::MIN, 3).checked_neg(), None);
+    }
+
+    #[test]
+    fn test_sexpr() {
+        use self::Const::*;
+        use Expr::*;
+        let var = |x| Box::new(Var(String::from(x)));
+
+        assert_eq!(read("x"), Ok(Var(String::from("x"))));
+        assert_eq!(read("-3"), Ok(Const(Int(-3))));
+        assert_eq!(read("1/2"), Ok(Const(Rat(super::rat::Rat::new(1, 2)))));
+        assert_eq!(read("(/ 1 x)"), Ok(Recipr(var("x"))));
+        assert_eq!(read("(+ a (* 2 b))"), Ok(Sum(vec![
+            var("a"), Box::new(Prod(vec![Box::new(Const(Int(2))), var("b")]))
+        ])));
+        assert!(read("(/ 2 x)").is_err());
+        assert!(read("(^ x)").is_err());
+        assert!(read("(+ x").is_err());
+        assert!(read("x)").is_err());
+        assert!(read("1/0").is_err());
+        assert!(read("-170141183460469231731687303715884105728/-1").is_err());
+
+        let golden = [
+            "(+ a (* 2 b))",
+            "(^ x (- 1/3))",
+            "(Sin (* 0.1 x))",
+            "(+ 0.25 1e-7 2.0)",
+            "(f (/ 1 y) inf -inf undef)",
+            "(+ )",
+        ];
+        for s in golden.iter() {
+            assert_eq!(read(s).map(|e| e.to_string()), Ok(s.to_string()));
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let expr = read("(+ (Sin x) (* 1/2 y) (- 170141183460469231731687303715884105727) 0.25 inf (f))").unwrap();
+        let json = serde_json::to_string(&expr).unwrap();
+        assert_eq!(serde_json::from_str::<Expr>(&json).unwrap(), expr);
+
+        assert_eq!(
+            serde_json::to_value(read("(* 1/2 x)").unwrap()).unwrap(),
+            serde_json::json!({"type": "prod", "value": [
+                {"type": "const", "value": {"type": "rat", "value": {"num": "1", "den": "2"}}},
+                {"type": "var", "value": "x"},
+            ]})
+        );
+        assert_eq!(
+            serde_json::to_value(read("(Sqrt (f 7))").unwrap()).unwrap(),
+            serde_json::json!({"type": "call", "value": [{"type": "sqrt"}, [
+                {"type": "call", "value": [{"type": "func", "value": "f"}, [
+                    {"type": "const", "value": {"type": "int", "value": "7"}},
+                ]]},
+            ]]})
+        );
+
+        assert_eq!(
+            serde_json::from_str::<Const>(r#"{"type": "rat", "value": {"num": "2", "den": "4"}}"#).unwrap(),
+            Const::Rat(Rat::new(1, 2))
+        );
+        assert!(serde_json::from_str::<Rat>(r#"{"num": "1", "den": "0"}"#).is_err());
+        assert!(serde_json::from_str::<Rat>(r#"{"num": "x", "den": "1"}"#).is_err());
+
+        // JSON has no numbers for NaN and the infinities.
+        for x in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, -0.0, 1e300] {
+            let json = serde_json::to_string(&Const::Float(x)).unwrap();
+            assert_eq!(serde_json::from_str::<Const>(&json).unwrap(), Const::Float(x), "{}", json);
+        }
+        assert_eq!(serde_json::to_string(&Const::Float(f64::NEG_INFINITY)).unwrap(), r#"{"type":"float","value":"-inf"}"#);
+        assert_eq!(serde_json::from_str::<Const>(r#"{"type": "float", "value": 2}"#).unwrap(), Const::Float(2.0));
+        assert!(serde_json::from_str::<Const>(r#"{"type": "float", "value": "infinity"}"#).is_err());
+        let min = i128::MIN;
+        assert!(serde_json::from_str::<Rat>(&format!(r#"{{"num": "{}", "den": "-1"}}"#, min)).is_err());
+        assert!(serde_json::from_str::<Rat>(&format!(r#"{{"num": "1", "den": "{}"}}"#, min)).is_err());
+        assert_eq!(serde_json::from_str::<Rat>(&format!(r#"{{"num": "{}", "den": "{}"}}"#, min, min)).unwrap(), Rat::from(1));
+        assert_eq!(serde_json::from_str::<Rat>(&format!(r#"{{"num": "{}", "den": "1"}}"#, min)).unwrap(), Rat::from(min));
+        assert_eq!(serde_json::from_str::<Rat>(r#"{"num": "3", "den": "-6"}"#).unwrap(), Rat::new(-1, 2));
+    }
+
+    #[test]
+    fn test_eval() {
+        let eval_str = |s| eval(&super::parse_str(s).unwrap()).to_string();
+        assert_eq!(eval_str("1 + 2 * 3"), "7");
+        assert_eq!(eval_str("1/2 + 1/3"), "5/6");
+        assert_eq!(eval_str("2^(-2)"), "1/4");
+        assert_eq!(eval_str("x + 1 + 2"), "(+ x 3)");
+        assert_eq!(eval_str("0 * x"), "0");
+        assert_eq!(eval_str("1 / 0"), "inf");
+        assert_eq!(eval(&Expr::Recipr(Box::new(Expr::Const(Const::Int(-1))))), Expr::Const(Const::Int(-1)));
+        assert_eq!(eval_str("2 / 1"), "2");
+        assert_eq!(eval(&Expr::rat(1, 2).recipr()), Expr::int(2));
+        assert_eq!(eval(&Expr::rat(-1, 3).recipr()), Expr::int(-3));
+        // Overflow falls back to floats rather than panicking.
+        let min = Expr::int(i128::MIN);
+        assert_eq!(eval(&-min.clone()), Expr::float(-(i128::MIN as f64)));
+        assert_eq!(eval(&Expr::Recipr(Box::new(min.clone()))), Expr::float(1.0 / i128::MIN as f64));
+        assert_eq!(eval(&min.clone().gamma()), Expr::Const(Const::Undef));
+        assert_eq!(eval(&(Expr::rat(i128::MAX, 2) + Expr::rat(i128::MAX, 3))), Expr::float(i128::MAX as f64 / 2.0 + i128::MAX as f64 / 3.0));
+        assert_eq!(eval(&(Expr::rat(i128::MIN + 1, 2) * 3)), Expr::float((i128::MIN + 1) as f64 * 1.5));
+        assert_eq!(eval(&Expr::rat(i128::MIN, 3).pow(-1)), Expr::float(3.0 / i128::MIN as f64));
+        assert_eq!(eval(&Expr::rat(i128::MIN + 1, 2).ceil()), Expr::int(i128::MIN / 2 + 1));
+        assert_eq!(eval(&Expr::rat(-5, 2).round()), Expr::int(-3));
+        assert_eq!(super::format::infix(&(Expr::var("x") + min)), "x + -170141183460469231731687303715884105728");
+        assert_eq!(eval_str("0.5 * 3"), "1.5");
+        assert_eq!(eval_str("170141183460469231731687303715884105727 + 1"), "1.7014118346046923e38");
+    }
+
+    #[test]
+    fn test_builder() {
+        let x = Expr::var("x");
+        let y = Expr::var("y");
+        let parse = |s| super::parse_str(s).unwrap();
+
+        assert_eq!(x.clone().pow(2) + Expr::int(3) * &y - 1, parse("x^2 + 3*y - 1"));
+        assert_eq!(&x / &y * 2, parse("x / y * 2"));
+        assert_eq!(-(&x + &y), parse("-(x + y)"));
+        assert_eq!((&x + 1) + (&y + 2), parse("x + 1 + y + 2"));
+        assert_eq!((&x + &y).sin() * x.clone().sqrt(), Expr::Prod(vec![
+            Box::new(Expr::call(Func::Sin, vec![parse("x + y")])),
+            Box::new(Expr::call(Func::Sqrt, vec![x.clone()])),
+        ]));
+        assert_eq!(Expr::rat(2, 4), Expr::Const(Const::Rat(Rat::new(1, 2))));
+        assert_eq!(Expr::rat(4, 2), Expr::int(2));
+    }
+
+    #[test]
+    fn test_macro() {
+        let parse = |s| super::parse_str(s).unwrap();
+        assert_eq!(crate::expr!(x^2 + 3*sin(y)), parse("x^2 + 3*sin(y)"));
+        assert_eq!(crate::expr!(root(x, 3) - 1/2), parse("root(x, 3) - 1/2"));
+        assert_eq!(crate::expr!(f() * inf), Expr::call(Func::Func(String::from("f")), vec![]) * Const::Inf);
+        assert_eq!(crate::expr!(-x^2 - 3*y/z^-1 + (a + b) + 2.5e3), parse("-x^2 - 3*y/z^-1 + (a + b) + 2.5e3"));
+        assert_eq!(crate::expr!(x^y^z * +w! - n!!% / |a - b| * mod(p, 7)), parse("x^y^z * +w! - n!!% / |a - b| * mod(p, 7)"));
+        assert_eq!(crate::expr!(undef), Expr::Const(Const::Undef));
+
+        let a = Expr::var("a") + 1;
+        let k = 2;
+        assert_eq!(crate::expr!(#a * x), Expr::Prod(vec![Box::new(a.clone()), Box::new(Expr::var("x"))]));
+        assert_eq!(crate::expr!(x^#k), Expr::var("x").pow(2));
+        assert_eq!(crate::expr!(#(k + 1) * #a), Expr::int(3) * &a);
+        // There is no limit on the number of captures.
+        let [c0, c1, c2, c3, c4, c5, c6, c7, c8, c9] = std::array::from_fn(|i| Expr::from(i as i32));
+        assert_eq!(
+            crate::expr!(#c0 + #c1 + #c2 + #c3 + #c4 + #c5 + #c6 + #c7 + #c8 + #c9 + #c0 + #c1 + #c2 + #c3 + #c4 + #c5 + #c6 + #c7),
+            Expr::Sum((0..10).chain(0..8).map(|i| Box::new(Expr::from(i))).collect()),
+        );
+    }
+
+    #[test]
+    fn test_recovery() {
+        let recover = |s| {
+            let (toks, lex_errors) = lex_recovering(s);
+            let (expr, errors) = parse_recovering(toks, super::default_ops());
+            let spans: Vec<_> = lex_errors.iter().map(|e| e.span())
+                .chain(errors.iter().map(|e| e.span()))
+                .map(|s| (s.start, s.end))
+                .collect();
+            (expr.to_string(), spans)
+        };
+
+        assert_eq!(recover("1 + 2"), (String::from("(+ 1 2)"), vec![]));
+        assert_eq!(recover("1 + * 2"), (String::from("(+ 1 ?)"), vec![(4, 5)]));
+        assert_eq!(recover("1 +"), (String::from("(+ 1 ?)"), vec![(3, 3)]));
+        assert_eq!(recover("(1 2) + x"), (String::from("(+ 1 x)"), vec![(3, 4)]));
+        assert_eq!(recover("(x + 1"), (String::from("(+ x 1)"), vec![(0, 1)]));
+        assert_eq!(recover("x ) + 1"), (String::from("(+ x 1)"), vec![(2, 3)]));
+        assert_eq!(recover("x y + 1"), (String::from("(+ x 1)"), vec![(2, 7)]));
+        assert_eq!(recover("f(1, , 3 4, 5)"), (String::from("(f 1 ? 3 5)"), vec![(5, 6), (9, 10)]));
+        assert_eq!(recover("3.3.3 * x & 2"), (String::from("(* ? ?)"), vec![(0, 4), (10, 11)]));
+        assert_eq!(recover(""), (String::from("?"), vec![(0, 0)]));
+
+        let (_, diagnostics) = super::parse_str_recovering("2 & x + (y");
+        let messages: Vec<_> = diagnostics.iter().map(|d| &d.message[..]).collect();
+        assert_eq!(messages, vec!["Unknown operator &", "Unclosed '('"]);
+    }
+
+    #[test]
+    fn test_implicit_mul() {
+        let implicit = |s, implicit_div| {
+            let options = Options{implicit_mul: true, implicit_div, ..Options::default()};
+            parse_with(lex(s).unwrap(), super::default_ops(), options).map(|e| e.to_string())
+        };
+        let loose = |s| implicit(s, ImplicitDiv::Loose);
+        let tight = |s| implicit(s, ImplicitDiv::Tight);
+        let ok = |s: &str| Ok(String::from(s));
+
+        assert_eq!(loose("2x"), ok("(* 2 x)"));
+        assert_eq!(loose("3(x+1)"), ok("(* 3 (+ x 1))"));
+        assert_eq!(loose("(a+b)(a-b)"), ok("(* (+ a b) (+ a (- b)))"));
+        assert_eq!(loose("2 sin(x)"), ok("(* 2 (Sin x))"));
+        assert_eq!(loose("2x^2"), ok("(* 2 (^ x 2))"));
+        assert_eq!(loose("x^2y + 1"), ok("(+ (* (^ x 2) y) 1)"));
+        assert_eq!(loose("1 + 2x - 3y z"), ok("(+ 1 (* 2 x) (* (- 3) y z))"));
+        assert_eq!(loose("f(x+1)"), ok("(* f (+ x 1))"));
+        assert_eq!(loose("2 - x"), ok("(+ 2 (- x))"));
+        assert_eq!(loose("1/2x"), ok("(* 1 (/ 1 2) x)"));
+        assert_eq!(tight("1/2x"), ok("(* 1 (/ 1 (* 2 x)))"));
+        assert_eq!(tight("1/2x^2y"), ok("(* 1 (/ 1 (* 2 (^ x 2) y)))"));
+        assert_eq!(tight("1/2 x"), ok("(* 1 (/ 1 (* 2 x)))"));
+
+        assert!(super::parse_str("2x").is_err());
+        assert_eq!(super::parse_str("f(x+1)").unwrap(), Expr::call(Func::Func(String::from("f")), vec![Expr::var("x") + 1]));
+    }
+
+    #[test]
+    fn test_postfix() {
+        let parse = |s| super::parse_str(s).unwrap().to_string();
+        let eval_str = |s| eval(&super::parse_str(s).unwrap()).to_string();
+
+        assert_eq!(parse("3!+1"), "(+ (Factorial 3) 1)");
+        assert_eq!(parse("-3!"), "(- (Factorial 3))");
+        assert_eq!(parse("2^3!"), "(^ 2 (Factorial 3))");
+        assert_eq!(parse("n!! * 50%"), "(* (DoubleFactorial n) (* 50 (/ 1 100)))");
+        assert_eq!(parse("(x+1)!"), "(Factorial (+ x 1))");
+        assert_eq!(parse("x!!!"), "(Factorial (DoubleFactorial x))");
+        // Operands are moved into the node built around them, so long chains
+        // parse in linear time.
+        let chain = super::parse_str(&format!("x{}", "%".repeat(3000))).unwrap();
+        assert_eq!(super::visit::depth(&chain), 3002);
+
+        assert_eq!(eval_str("5!"), "120");
+        assert_eq!(eval_str("0!"), "1");
+        assert_eq!(eval_str("6!!"), "48");
+        assert_eq!(eval_str("7!!"), "105");
+        assert_eq!(eval_str("gamma(5)"), "24");
+        assert_eq!(eval_str("33!"), "8683317618811886495518194401280000000");
+        assert_eq!(eval_str("(-1)!"), "undef");
+        assert_eq!(eval_str("gamma(0)"), "undef");
+        assert_eq!(eval_str("50%"), "1/2");
+        assert_eq!(eval_str("x!"), "(Factorial x)");
+        assert_eq!(eval_str("(1/2)!"), "(Factorial 1/2)");
+
+        let float = |s| match eval(&super::parse_str(s).unwrap()) {
+            Expr::Const(Const::Float(f)) => f,
+            e => panic!("{}", e),
+        };
+        assert!((float("0.5!") - 0.886_226_925_452_758).abs() < 1e-12);
+        assert!((float("4.0!") - 24.0).abs() < 1e-10);
+        assert!((float("5.0!!") - 15.0).abs() < 1e-10);
+        assert!((float("34!") / 2.952_327_990_396_041e38 - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_literals() {
+        use Tok::*;
+        let lit = |x: &'static str, options| match lex_with(x, options) {
+            Ok(toks) => toks.into_iter().map(|t| t.tok).collect::<Vec<_>>(),
+            Err(e) => panic!("{}", e),
+        };
+        let plain = |x| lit(x, super::lexer::Options::default());
+        assert_eq!(plain("6.02e23"), vec![Lit(Const::Float(6.02e23))]);
+        assert_eq!(plain("1E-9"), vec![Lit(Const::Float(1e-9))]);
+        assert_eq!(plain("2e+3"), vec![Lit(Const::Float(2000.0))]);
+        assert_eq!(plain("0xFF"), vec![Lit(Const::Int(255))]);
+        assert_eq!(plain("0b1010"), vec![Lit(Const::Int(10))]);
+        assert_eq!(plain("0o17"), vec![Lit(Const::Int(15))]);
+        assert_eq!(plain("1_000_000"), vec![Lit(Const::Int(1_000_000))]);
+        assert_eq!(plain("0.000_1"), vec![Lit(Const::Float(0.0001))]);
+        assert_eq!(plain("1_0.2_5e1_0"), vec![Lit(Const::Float(10.25e10))]);
+        assert_eq!(plain("1.e3"), vec![Lit(Const::Float(1000.0))]);
+        assert_eq!(plain("170141183460469231731687303715884105727"), vec![Lit(Const::Int(i128::MAX))]);
+        assert!(lex("170141183460469231731687303715884105728").is_err());
+        assert!(lex("1e2147483648").is_err());
+        assert_eq!(plain("x¹²"), vec![Iden("x"), Op("^"), Lit(Const::Int(12))]);
+        // Suffixes that do not continue a literal are left for the next token.
+        assert_eq!(plain("2e"), vec![Lit(Const::Int(2)), Iden("e")]);
+        assert_eq!(plain("0b"), vec![Lit(Const::Int(0)), Iden("b")]);
+        assert_eq!(plain("1_"), vec![Lit(Const::Int(1)), Op("_")]);
+        assert_eq!(plain("0.(3)"), vec![Lit(Const::Float(0.0)), LParen, Lit(Const::Int(3)), RParen]);
+        assert!(lex("0xFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF").is_err());
+        assert!(lex("1e5.5").is_err());
+
+        let rat = |n, d| vec![Lit(Const::Rat(super::rat::Rat::new(n, d)))];
+        let exact = |x| lit(x, super::lexer::Options{exact_decimals: true, ..super::lexer::Options::default()});
+        assert_eq!(exact("0.1"), rat(1, 10));
+        assert_eq!(exact("2.50"), rat(5, 2));
+        assert_eq!(exact("1e-3"), rat(1, 1000));
+        assert_eq!(exact("6.02e23"), vec![Lit(Const::Int(602_000_000_000_000_000_000_000))]);
+        assert_eq!(exact("7"), vec![Lit(Const::Int(7))]);
+        assert!(lex_with("1e40", super::lexer::Options{exact_decimals: true, ..super::lexer::Options::default()}).is_err());
+
+        let repeating = |x| lit(x, super::lexer::Options{repeating_decimals: true, ..super::lexer::Options::default()});
+        assert_eq!(repeating("0.(3)"), rat(1, 3));
+        assert_eq!(repeating("0.1(6)"), rat(1, 6));
+        assert_eq!(repeating("1.(142857)"), rat(8, 7));
+        assert_eq!(repeating("0.(9)"), vec![Lit(Const::Int(1))]);
+        assert_eq!(repeating("1_0.2_5(3)"), rat(769, 75));
+        assert_eq!(repeating("0.5"), vec![Lit(Const::Float(0.5))]);
+        assert_eq!(repeating("2(3)"), vec![Lit(Const::Int(2)), LParen, Lit(Const::Int(3)), RParen]);
+    }
+
+    #[test]
+    fn test_unicode() {
+        use Tok::*;
+        let ops = |x: &'static str| lex(x).unwrap().into_iter().map(|t| t.tok).collect::<Vec<_>>();
+        let op = |x: &'static str| Op(x);
+        assert_eq!(ops("×−"), vec![op("*"), op("-")]);
+        assert_eq!(ops("*-!!%"), vec![op("*"), op("-"), op("!!"), op("%")]);
+        assert_eq!(ops("÷·"), vec![op("/"), op("*")]);
+        assert_eq!(ops("2πr"), vec![Lit(Const::Int(2)), Iden("pi"), Iden("r")]);
+        assert_eq!(ops("−∞"), vec![op("-"), Inf]);
+        assert_eq!(ops("x⁻¹²"), vec![Iden("x"), op("^"), op("-"), Lit(Const::Int(12))]);
+        assert!(lex("x⁻").is_err());
+
+        let spans = |x| super::lex(x).unwrap().into_iter().map(|t| (t.span.start, t.span.end)).collect::<Vec<_>>();
+        assert_eq!(spans("x²×y"), vec![(0, 1), (1, 1), (1, 3), (3, 5), (5, 6)]);
+
+        let eval_str = |s| eval(&super::parse_str(s).unwrap()).to_string();
+        assert_eq!(eval_str("3 × 4 − 2 ÷ 2"), "11");
+        assert_eq!(eval_str("2³ + 3²"), "17");
+        assert_eq!(eval_str("2⁻¹"), "1/2");
+        assert_eq!(eval_str("½ + ¾"), "5/4");
+        assert_eq!(eval_str("√x"), "(Sqrt x)");
+        assert_eq!(eval_str("∛(x·y)"), "(Cbrt (* x y))");
+        assert_eq!(eval_str("2 * -3"), "-6");
+        assert_eq!(eval_str("x ^ ∞"), "(^ x inf)");
+    }
+
+    #[test]
+    fn test_rounding() {
+        let eval_str = |s| eval(&super::parse_str(s).unwrap()).to_string();
+        assert_eq!(eval_str("floor(7/2)"), "3");
+        assert_eq!(eval_str("floor(-7/2)"), "-4");
+        assert_eq!(eval_str("ceil(7/2)"), "4");
+        assert_eq!(eval_str("ceil(-7/2)"), "-3");
+        assert_eq!(eval_str("round(5/2)"), "3");
+        assert_eq!(eval_str("round(-5/2)"), "-3");
+        assert_eq!(eval_str("round(1/3)"), "0");
+        assert_eq!(eval_str("round(2.5)"), "3.0");
+        assert_eq!(eval_str("abs(-3/4)"), "3/4");
+        assert_eq!(eval_str("abs(-inf)"), "inf");
+        assert_eq!(eval_str("sign(-2/3)"), "-1");
+        assert_eq!(eval_str("sign(0)"), "0");
+        assert_eq!(eval_str("sign(-inf)"), "-1");
+        assert_eq!(eval_str("frac(7/2)"), "1/2");
+        assert_eq!(eval_str("frac(-7/2)"), "1/2");
+        assert_eq!(eval_str("frac(inf)"), "undef");
+        assert_eq!(eval_str("floor(inf)"), "inf");
+        assert_eq!(eval_str("floor(undef)"), "undef");
+        assert_eq!(eval_str("mod(7, 3)"), "1");
+        assert_eq!(eval_str("mod(-7, 3)"), "2");
+        assert_eq!(eval_str("mod(7, -3)"), "-2");
+        assert_eq!(eval_str("mod(7/2, 1/3)"), "1/6");
+        assert_eq!(eval_str("mod(5, 0)"), "undef");
+        assert_eq!(eval_str("mod(-5, inf)"), "inf");
+        assert_eq!(eval_str("mod(inf, 3)"), "undef");
+        assert_eq!(eval_str("mod(7.5, 2)"), "1.5");
+        assert_eq!(eval_str("mod(0.0, -inf)"), "0.0");
+        assert_eq!(eval_str("mod(-0.5, inf)"), "inf");
+        assert_eq!(eval(&Expr::call(Func::Mod, vec![Expr::float(f64::NAN), Expr::Const(Const::Inf)])), Expr::Const(Const::Undef));
+
+        assert_eq!(eval_str("abs(-x)"), "(Abs x)");
+        assert_eq!(eval_str("abs(abs(x))"), "(Abs x)");
+        assert_eq!(eval_str("|2 - 5|"), "3");
+        assert_eq!(eval_str("⌊7/2⌋ + ⌈7/2⌉"), "7");
+        assert_eq!(eval_str("|x - |y||"), "(Abs (+ x (- (Abs y))))");
+        assert_eq!(Expr::var("x").modulo(2).to_string(), "(Mod x 2)");
+
+        let implicit = |s| {
+            let options = Options{implicit_mul: true, ..Options::default()};
+            parse_with(lex(s).unwrap(), super::default_ops(), options).map(|e| e.to_string())
+        };
+        assert_eq!(implicit("2|x||y|"), Ok(String::from("(* 2 (Abs x) (Abs y))")));
+        assert_eq!(implicit("|2x|⌊y⌋"), Ok(String::from("(* (Abs (* 2 x)) (Floor y))")));
+        match super::parse_str("|x + 1") {
+            Err(super::Error::Parse(err)) => assert_eq!((err.message(), err.span()), ("Unclosed '|'", Span::new(0, 1))),
+            r => panic!("{:?}", r),
+        }
+        match super::parse_str("⌊x⌉") {
+            Err(super::Error::Parse(err)) => assert_eq!(err.message(), "Expected '⌋'"),
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_session() {
+        use super::session::{Outcome, Session};
+        let mut session = Session::new(super::default_ops(), Options{implicit_mul: true, ..Options::default()});
+        let mut run = |s| match session.run(s) {
+            Ok(Outcome::Value(e)) => e.to_string(),
+            Ok(Outcome::Defined(name)) => name,
+            Err(diagnostics) => diagnostics.into_iter().map(|d| d.message).collect::<Vec<_>>().join("; "),
+        };
+        assert_eq!(run("x := 3"), "x");
+        assert_eq!(run("y := 2x + 1"), "y");
+        assert_eq!(run("y"), "7");
+        assert_eq!(run("x := 4"), "x");
+        assert_eq!(run("y"), "9");
+        assert_eq!(run("f(t) := t^2 + 1"), "f");
+        assert_eq!(run("f(x) + 2f(1)"), "21");
+        assert_eq!(run("g(x, y) := x*y - y"), "g");
+        assert_eq!(run("g(y, z)"), "(* 8 z)");
+        assert_eq!(run("10"), "10");
+        assert_eq!(run("% * 2 + ans"), "30");
+        assert_eq!(run("50%"), "1/2");
+        assert_eq!(run("a := % + 1"), "a");
+        assert_eq!(run("4"), "4");
+        assert_eq!(run("a"), "3/2");
+
+        assert_eq!(run("x := x + 1"), "Recursive definition of x: x → x");
+        assert_eq!(run("z := y"), "z");
+        assert_eq!(run("x := z"), "Recursive definition of x: x → z → y → x");
+        assert_eq!(run("h(n) := n h(n - 1)"), "Recursive definition of h: h → h");
+        assert_eq!(run("f(1, 2)"), "f takes 1 argument but 2 were given");
+        assert_eq!(run("sin := 2"), "sin is a builtin function and cannot be defined");
+        assert_eq!(run("2 := 3"), "Expected a name or a function like f(x) before ':='");
+        assert_eq!(run("f(x, x) := x"), "Parameter x appears twice");
+        assert_eq!(run("x :="), "Expected an expression after ':='");
+
+        assert_eq!(session.value("y"), Some(&super::parse_str("2*x + 1").unwrap()));
+        assert!(session.remove("y"));
+        assert!(!session.remove("y"));
+        assert_eq!(session.describe("f").unwrap(), "f(t) := (+ (^ t 2) 1)");
+        assert_eq!(session.names(), vec!["a", "f", "g", "x", "z"]);
+        session.clear();
+        assert!(session.ans().is_none());
+        assert!(session.names().is_empty());
+    }
+
+    #[test]
+    fn test_functions() {
+        use super::diff::{diff, diff_with};
+        use super::function::{Function, Registry};
+
+        // gcd with derivative 0 wherever it is defined.
+        struct Gcd;
+        impl Function for Gcd {
+            fn name(&self) -> &str { "gcd" }
+            fn arity(&self) -> usize { 2 }
+            fn eval_exact(&self, args: &[Const]) -> Option<Const> {
+                match args {
+                    [Const::Int(a), Const::Int(b)] => {
+                        let (mut a, mut b) = (a.abs(), b.abs());
+                        while b != 0 {
+                            let t = a % b;
+                            a = b;
+                            b = t;
+                        }
+                        Some(Const::Int(a))
+                    },
+                    _ => None,
+                }
+            }
+            fn derivative(&self, _args: &[Expr], _i: usize) -> Option<Expr> { Some(Expr::int(0)) }
+        }
+
+        struct Sinc;
+        impl Function for Sinc {
+            fn name(&self) -> &str { "sinc" }
+            fn arity(&self) -> usize { 1 }
+            fn eval_f64(&self, args: &[f64]) -> Option<f64> { Some(args[0].sin() / args[0]) }
+        }
+
+        let mut registry = Registry::new();
+        registry.register(Gcd).unwrap();
+        registry.register(Sinc).unwrap();
+        registry.define("f", vec![String::from("x"), String::from("y")], super::parse_str("x*y + 1").unwrap()).unwrap();
+        assert!(registry.register(Sinc).is_ok());
+        assert_eq!(registry.define("sin", vec![], Expr::int(1)), Err(String::from("sin is a builtin function and cannot be defined")));
+        assert_eq!(
+            registry.define("g", vec![String::from("x")], super::parse_str("f(g(x), 1)").unwrap()),
+            Err(String::from("Recursive definition of g: g → g")),
+        );
+        assert_eq!(registry.names().collect::<Vec<_>>(), vec!["f", "gcd", "sinc"]);
+
+        let eval_str = |s| eval_with(&super::parse_str(s).unwrap(), &registry).to_string();
+        assert_eq!(eval_str("f(2, 3)"), "7");
+        assert_eq!(eval_str("f(a, 2)"), "(+ (* 2 a) 1)");
+        assert_eq!(eval_str("gcd(12, 18) + gcd(x, 2)"), "(+ (gcd x 2) 6)");
+        assert_eq!(eval_str("gcd(1, 2, 3)"), "(gcd 1 2 3)");
+        assert_eq!(eval_str("sinc(1/2)"), "(sinc 1/2)");
+        assert_eq!(eval_str("gcd(undef, 2)"), "undef");
+        assert!((match eval_with(&super::parse_str("sinc(0.5)").unwrap(), &registry) {
+            Expr::Const(Const::Float(f)) => f,
+            e => panic!("{}", e),
+        } - 0.958_851_077_208_406).abs() < 1e-12);
+
+        let d = |s, x| diff(&super::parse_str(s).unwrap(), x).to_string();
+        assert_eq!(d("x^3 + 2*x", "x"), "(+ (* 3 (^ x 2)) 2)");
+        assert_eq!(d("sin(x^2)", "x"), "(* 2 (Cos (^ x 2)) x)");
+        assert_eq!(d("y*log(x)", "x"), "(* y (/ 1 x))");
+        assert_eq!(d("abs(3*x)", "x"), "(* 3 (Sign (* 3 x)))");
+        assert_eq!(d("gamma(x)", "x"), "(Diff (Gamma x) x)");
+        assert_eq!(d("h(x)", "x"), "(Diff (h x) x)");
+        assert_eq!(d("h(y)", "x"), "0");
+
+        let d = |s, x| diff_with(&super::parse_str(s).unwrap(), x, &registry).to_string();
+        assert_eq!(d("f(x, x)", "x"), "(+ x x)");
+        assert_eq!(d("gcd(x^2, 4)", "x"), "0");
+        assert_eq!(d("sinc(2*x)", "x"), "(Diff (sinc (* 2 x)) x)");
+
+        let mut session = super::session::Session::default();
+        session.functions_mut().register(Gcd).unwrap();
+        assert_eq!(session.run("gcd(4, 6) + 1"), Ok(super::session::Outcome::Value(Expr::int(3))));
+        assert_eq!(session.describe("gcd").unwrap(), "gcd is a native function of 2 arguments");
+        session.run("f(x) := 2*x").unwrap();
+        session.clear();
+        assert_eq!(session.names(), vec!["gcd"]);
+
+        // A user function named `diff` does not capture unevaluated derivatives.
+        session.run("diff(u, v) := u + v").unwrap();
+        let derivative = diff_with(&super::parse_str("gamma(x)").unwrap(), "x", session.functions());
+        assert_eq!(session.evaluate(&derivative).unwrap().to_string(), "(Diff (Gamma x) x)");
+        assert_eq!(read(&derivative.to_string()).unwrap(), derivative);
+        assert_eq!(session.run("diff(1, 2)"), Ok(super::session::Outcome::Value(Expr::int(3))));
+    }
+
+    #[test]
+    fn test_format() {
+        use super::format::{infix, latex, render, tree, Options, Style};
+
+        let round_trip = |s| {
+            let expr = super::parse_str(s).unwrap();
+            let written = infix(&expr);
+            assert_eq!(super::parse_str(&written).unwrap(), expr, "{} was written as {}", s, written);
+            written
+        };
+        assert_eq!(round_trip("1 + 2*x - y"), "1 + 2*x - y");
+        assert_eq!(round_trip("(a + b)*(c - d)/(e*f)"), "(a + b)*(c - d)/(e*f)");
+        assert_eq!(round_trip("-x^2"), "(-x)^2");
+        assert_eq!(round_trip("-(x^2)"), "-(x^2)");
+        assert_eq!(round_trip("(x^y)^z + x^(y^z)"), "(x^y)^z + x^(y^z)");
+        assert_eq!(round_trip("2^-x"), "2^-x");
+        assert_eq!(round_trip("(n + 1)! + 3!!"), "(n + 1)! + 3!!");
+        assert_eq!(round_trip("sqrt(x) + log(2, x) + f(x, y)"), "sqrt(x) + log(2, x) + f(x, y)");
+
+        let expr = eval(&super::parse_str("x/3 - 2*y - 0.5").unwrap());
+        assert_eq!(infix(&expr), "1/3*x - 2*y - 0.5");
+        assert_eq!(infix(&eval(&super::parse_str("3*x^2 - x/2 + -1*y").unwrap())), "3*x^2 - 1/2*x - y");
+        assert_eq!(latex(&expr), "\\frac{1}{3} \\cdot x - 2 \\cdot y - 0.5");
+        assert_eq!(latex(&super::parse_str("|x| + floor(y)^2 + root(x, 5) + asin(pi*t) + e").unwrap()),
+            "\\left|x\\right| + \\left\\lfloor y\\right\\rfloor^{2} + \\sqrt[5]{x} + \\arcsin\\left(\\pi \\cdot t\\right) + e");
+        assert_eq!(latex(&super::parse_str("log(x)/(1 + speed)").unwrap()),
+            "\\frac{\\ln\\left(x\\right)}{1 + \\mathrm{speed}}");
+
+        let sexpr = |precision| render(&(Expr::float(2.0) * Expr::var("x").pow(Expr::float(1.0 / 3.0))),
+            Options{style: Style::Sexpr, precision});
+        assert_eq!(sexpr(None), "(* 2.0 (^ x 0.3333333333333333))");
+        assert_eq!(sexpr(Some(2)), "(* 2.00 (^ x 0.33))");
+        assert_eq!("latex".parse(), Ok(Style::Latex));
+        assert!("tex".parse::<Style>().is_err());
+
+        assert_eq!(tree(&super::parse_str("2*x + -y").unwrap()),
+            "Sum\n├─ Prod\n│  ├─ 2\n│  └─ x\n└─ Neg\n   └─ y\n");
+        assert_eq!(approx(&super::parse_str("sqrt(2)/4 + x/2").unwrap()).to_string(), "(+ (* 0.5 x) 0.3535533905932738)");
+    }
+
+    #[test]
+    fn test_script() {
+        use super::script::{self, Cmp, Event, Stmt};
+        use super::session::Session;
+        use Tok::*;
+
+        let toks = |x| super::lex(x).unwrap().into_iter().map(|t| t.tok).collect::<Vec<_>>();
+        assert_eq!(toks("for i in 1..=3 { a; }"), vec![
+            Iden("for"), Iden("i"), Iden("in"), Lit(Const::Int(1)),
+            DotDotEq, Lit(Const::Int(3)), LBrace, Iden("a"), Semi, RBrace,
+        ]);
+        // Comments are only skipped in scripts and rule files.
+        let comments = super::lexer::Options{comments: true, ..super::lexer::Options::default()};
+        assert_eq!(super::lexer::lex_with("a # b\nc", comments).unwrap().len(), 2);
+        assert!(super::parse_str("1 # 2").is_err());
+        assert_eq!(toks("0.5..x != 2"), vec![
+            Lit(Const::Float(0.5)), DotDot, Iden("x"), Op("!="), Lit(Const::Int(2)),
+        ]);
+
+        let stmts = script::parse("a := 1; print a, f(a, 2)\nif a <= 2 {\n  a\n} else if a > 3 { b } else {}").unwrap();
+        assert_eq!(stmts.len(), 3);
+        assert_eq!(script::parse("a # comment\nb # another").unwrap().len(), 2);
+        assert!(matches!(&stmts[1], Stmt::Print(args) if args.len() == 2 && args[1].len() == 6));
+        match &stmts[2] {
+            Stmt::If{cond, then, otherwise} => {
+                assert_eq!((cond.cmp, then.len()), (Cmp::Le, 1));
+                assert!(matches!(&otherwise[..], [Stmt::If{otherwise, ..}] if otherwise.is_empty()));
+            },
+            stmt => panic!("{:?}", stmt),
+        }
+        let error = |src| script::parse(src).unwrap_err()[0].message.clone();
+        assert_eq!(error("for i in 1..3 {\n print i"), "Unclosed '{'");
+        assert_eq!(error("if a { b }"), "Expected a comparison");
+        assert_eq!(error("a }"), "Unexpected '}'");
+        assert_eq!(error("print a,"), "Expected an expression to print");
+
+        let run = |src| {
+            let mut session = Session::default();
+            let mut events = Vec::new();
+            script::run(&mut session, src, |event| events.push(event)).map(|()| events)
+        };
+        let src = "n = 0\nfor i in 1..5 {\n  if mod(i, 2) == 1 { n = n + i }\n}\nprint n; n * 2";
+        assert_eq!(run(src), Ok(vec![
+            Event::Defined(String::from("n")), Event::Defined(String::from("n")), Event::Defined(String::from("n")),
+            Event::Print(vec![Expr::int(4)]),
+            Event::Value(Expr::int(8)),
+        ]));
+        assert_eq!(run("if x == x { print 1 }"), Ok(vec![Event::Print(vec![Expr::int(1)])]));
+        assert_eq!(run("y := x; x = 2; y"), Ok(vec![
+            Event::Defined(String::from("y")), Event::Defined(String::from("x")), Event::Value(Expr::int(2)),
+        ]));
+        let error = |src| run(src).unwrap_err()[0].message.clone();
+        assert_eq!(error("if x < 1 { 2 }"), "Cannot compare x and 1");
+        assert_eq!(error("for i in 1..x {}"), "Expected an integer range bound, found x");
+        assert_eq!(error("n = n + 1"), "Recursive definition of n: n → n");
+    }
+
+    #[test]
+    fn test_simplify() {
+        use super::format::infix;
+        use super::simplify::{simplify, simplify_with_trace};
+
+        let simplified = |s| infix(&simplify(&super::parse_str(s).unwrap()));
+        assert_eq!(simplified("2*x + 3*x"), "5*x");
+        assert_eq!(simplified("x - x + y"), "y");
+        assert_eq!(simplified("x*x^2/x"), "x^2");
+        assert_eq!(simplified("(x^2)^3*1 + 0*y"), "x^6");
+        assert_eq!(simplified("2*x*y - x*y + 1 + 2"), "x*y + 3");
+        assert_eq!(simplified("x^0 + y/y"), "2");
+        assert_eq!(simplified("0/0*0"), "undef");
+
+        let (result, steps) = simplify_with_trace(&super::parse_str("2*x + 3*x + 0").unwrap());
+        assert_eq!(infix(&result), "5*x");
+        let steps: Vec<String> = steps.iter().map(|step| step.to_string()).collect();
+        assert_eq!(steps, ["add zero: 2*x + 3*x + 0 -> 2*x + 3*x", "combine like terms: 2*x + 3*x -> 5*x"]);
+        assert_eq!(simplify_with_trace(&Expr::var("x")).1, []);
+    }
+
+    #[test]
+    fn test_rewrite() {
+        use super::format::infix;
+        use super::rewrite::{parse, Guard, Rule, Test};
+        use super::session::{Outcome, Session};
+
+        let rewrite = |rules, s| infix(&parse(rules).unwrap().apply(&eval(&super::parse_str(s).unwrap())).unwrap());
+        assert_eq!(rewrite("sin(a)^2 + cos(a)^2 -> 1", "cos(x + 1)^2 + sin(x + 1)^2"), "1");
+        assert_eq!(rewrite("sin(a)^2 + cos(a)^2 -> 1", "y + sin(x)^2 + z + cos(x)^2"), "1 + y + z");
+        assert_eq!(rewrite("sin(a)^2 + cos(a)^2 -> 1", "sin(x)^2 + cos(y)^2"), "sin(x)^2 + cos(y)^2");
+        assert_eq!(rewrite("log(a*b) -> log(a) + log(b)", "log(x*y*z)"), "log(x) + log(y) + log(z)");
+        assert_eq!(rewrite("a*x + b*x -> (a + b)*x", "2*y + 3*y"), "(2 + 3)*y");
+        assert_eq!(rewrite("x^n*x^m -> x^(n + m) if n is integer, m is integer", "a^2*b*a^3"), "a^(2 + 3)*b");
+        assert_eq!(rewrite("x^n*x^m -> x^(n + m) if n is integer, m is integer", "a^2*a^k"), "a^2*a^k");
+        assert_eq!(rewrite("# comment\nf(x, x) -> 0; f(x, y) -> 1", "f(a, a) + f(a, b)"), "0 + 1");
+
+        let rule = Rule::new(&super::parse_str("abs(x)").unwrap(), Expr::var("x"), vec![Guard{var: String::from("x"), test: Test::Positive}]);
+        assert_eq!(rule.to_string(), "abs(x) -> x if x is positive");
+        assert_eq!(rule.apply(&super::parse_str("abs(2)").unwrap()), Some(Expr::int(2)));
+        assert_eq!(rule.apply(&super::parse_str("abs(-2)").unwrap()), None);
+        assert!(Test::Even.passes(&Expr::int(4)) && !Test::Number.passes(&Expr::var("x")));
+
+        assert!(parse("a + b -> b + a").unwrap().apply(&super::parse_str("x + y").unwrap()).is_err());
+        let errors = parse("x -> \nx -> x if x is big").unwrap_err();
+        assert_eq!(errors.iter().map(|d| &d.message[..]).collect::<Vec<_>>(), ["Expected a rule", "Unknown test big"]);
+
+        let mut session = Session::default();
+        assert_eq!(session.load_rules("sqrt(x)^2 -> x"), Ok(1));
+        assert_eq!(session.run("sqrt(y + 1)^2*2"), Ok(Outcome::Value(eval(&super::parse_str("2*(y + 1)").unwrap()))));
+    }
+
+    #[test]
+    fn test_visit() {
+        use std::ops::ControlFlow;
+        use super::visit::{contains, depth, fold, free_vars, node_count, walk, walk_mut, Folder, Visitor, VisitorMut};
+
+        let expr = super::parse_str("x^2 + f(y, 3)*-z").unwrap();
+        assert_eq!(free_vars(&expr).into_iter().collect::<Vec<_>>(), ["x", "y", "z"]);
+        assert!(contains(&expr, &super::parse_str("x^2").unwrap()));
+        assert!(!contains(&expr, &Expr::var("f")));
+        assert_eq!(depth(&expr), 4);
+        assert_eq!(node_count(&expr), 10);
+        assert_eq!(depth(&Expr::int(1)), 1);
+        assert_eq!(super::subs(&expr, &[(Expr::var("x"), Expr::int(2)), (super::parse_str("-z").unwrap(), Expr::var("w"))]),
+            super::parse_str("2^2 + f(y, 3)*w").unwrap());
+
+        // The order nodes are entered and left in.
+        struct Order(Vec<String>);
+        impl Visitor for Order {
+            fn enter(&mut self, expr: &Expr) -> bool {
+                self.0.push(format!("+{}", expr));
+                !matches!(expr, Expr::Pow(..))
+            }
+            fn leave(&mut self, expr: &Expr) {
+                self.0.push(format!("-{}", expr));
+            }
+        }
+        let mut order = Order(Vec::new());
+        walk(&super::parse_str("x^2 + y").unwrap(), &mut order);
+        assert_eq!(order.0, ["+(+ (^ x 2) y)", "+(^ x 2)", "-(^ x 2)", "+y", "-y", "-(+ (^ x 2) y)"]);
+
+        struct Rename;
+        impl VisitorMut for Rename {
+            fn enter(&mut self, expr: &mut Expr) -> bool {
+                if let Expr::Var(s) = expr {
+                    s.push('1');
+                }
+                true
+            }
+        }
+        let mut renamed = super::parse_str("a*b + c").unwrap();
+        walk_mut(&mut renamed, &mut Rename);
+        assert_eq!(renamed.to_string(), "(+ (* a1 b1) c1)");
+
+        // Negates the constants outside of powers, after the children.
+        struct Negate;
+        impl Folder for Negate {
+            fn enter(&mut self, expr: Expr) -> ControlFlow<Expr, Expr> {
+                match expr {
+                    Expr::Pow(..) => ControlFlow::Break(expr),
+                    expr => ControlFlow::Continue(expr),
+                }
+            }
+            fn leave(&mut self, expr: Expr) -> Expr {
+                match expr {
+                    Expr::Const(c) => Expr::Const(super::eval::negate_const(c)),
+                    expr => expr,
+                }
+            }
+        }
+        assert_eq!(fold(super::parse_str("2*x^2 + 3").unwrap(), &mut Negate).to_string(), "(+ (* -2 (^ x 2)) -3)");
+    }
+
+    #[test]
+    fn test_dag() {
+        use std::collections::HashSet;
+        use std::rc::Rc;
+        use super::dag::{Node, Store};
+
+        let mut store = Store::new();
+        let expr = super::parse_str("(x + 1)*(x + 1) - f(x + 1, y)").unwrap();
+        let term = store.import(&expr);
+        assert_eq!(Expr::from(&term), expr);
+        assert_eq!(term.to_string(), expr.to_string());
+        let factors = match term.node() {
+            Node::Sum(v) => match v[0].node() {
+                Node::Prod(factors) => factors.clone(),
+                node => panic!("Expected a product, found {:?}", node),
+            },
+            node => panic!("Expected a sum, found {:?}", node),
+        };
+        assert_eq!(factors[0], factors[1]);
+        assert_eq!(store.import(&super::parse_str("x + 1").unwrap()), factors[0]);
+        // x, 1, x + 1, the product, y, the call, its negation and the sum.
+        assert_eq!(store.len(), 8);
+
+        let (a, b) = (store.var("x"), store.var("x"));
+        match (a.node(), b.node()) {
+            (Node::Var(a), Node::Var(b)) => assert!(Rc::ptr_eq(a, b)),
+            _ => unreachable!(),
+        }
+        assert_eq!(a.structural_hash(), b.structural_hash());
+        assert_ne!(store.constant(Const::Float(0.0)), store.constant(Const::Float(-0.0)));
+        assert_eq!(store.constant(Const::Float(f64::NAN)), store.constant(Const::Float(f64::NAN)));
+        assert_ne!(store.constant(Const::Int(1)), store.constant(Const::Float(1.0)));
+        let neg = store.make(Node::Neg(a.clone()));
+        let set: HashSet<_> = vec![a.clone(), b, neg.clone(), store.make(Node::Neg(a))].into_iter().collect();
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(&neg));
+        assert_ne!(Store::new().var("x"), store.var("x"));
+    }
+
+    #[test]
+    fn test_order() {
+        use std::cmp::Ordering;
+        use std::collections::{HashMap, HashSet};
+        use super::simplify::simplify;
+
+        let half = Const::Rat(Rat::new(1, 2));
+        let mut consts = vec![
+            Const::Undef, Const::Float(f64::NAN), Const::Inf, Const::Float(0.5), half, Const::Float(0.0),
+            Const::Float(-0.0), Const::Int(0), Const::Float(-f64::NAN), Const::NegInf, Const::Int(-3),
+        ];
+        consts.sort();
+        assert_eq!(format!("{:?}", consts), "[NegInf, Int(-3), Int(0), Float(-0.0), Float(0.0), Rat(Rat { num: 1, den: 2 }), \
+            Float(0.5), Inf, Float(NaN), Float(NaN), Undef]");
+        assert_eq!(Const::Float(f64::NAN), Const::Float(-f64::NAN));
+        assert_ne!(Const::Float(0.0), Const::Float(-0.0));
+        assert_ne!(Const::Int(1), Const::Float(1.0));
+        assert_eq!(Const::Int(1).numeric_cmp(&Const::Float(1.0)), Some(Ordering::Equal));
+        assert_eq!(Const::Float(f64::NAN).numeric_cmp(&Const::Float(f64::NAN)), None);
+        // 2^60 + 1 is not a float, and rounds to 2^60.
+        assert_eq!(Const::Int((1 << 60) + 1).numeric_cmp(&Const::Float((1u64 << 60) as f64)), Some(Ordering::Greater));
+        assert!(Const::Float(1e300) > Const::Int(i128::MAX));
+        let set: HashSet<Const> = consts.into_iter().collect();
+        assert_eq!(set.len(), 10);
+
+        // Exact near the limits of i128, where cross-multiplying overflows.
+        let big = 10i128.pow(20);
+        assert!(Const::Int(big) > Const::Rat(Rat::new(1, big / 10)));
+        assert!(Rat::new(i128::MAX, i128::MAX - 1) < Rat::new(i128::MAX - 1, i128::MAX - 2));
+        assert!(Rat::new(-i128::MAX, i128::MAX - 1) > Rat::new(-(i128::MAX - 1), i128::MAX - 2));
+        assert_eq!(Rat::new(i128::MIN, 3).cmp(&Rat::new(i128::MIN + 1, 3)), Ordering::Less);
+        // Rat against Float is exact too, so the order is transitive.
+        let (a, b, c) = (Const::Int((1 << 60) + 50), Const::Rat(Rat::new((1 << 61) + 201, 2)), Const::Float((1u64 << 60) as f64));
+        assert!(c < a && a < b && c < b);
+        assert_eq!(Const::Rat(Rat::new(1, 3)).numeric_cmp(&Const::Float(1.0 / 3.0)), Some(Ordering::Greater));
+        assert_eq!(Const::Float(-0.5).numeric_cmp(&Const::Rat(Rat::new(-1, 2))), Some(Ordering::Equal));
+
+        let parse = |s| super::parse_str(s).unwrap();
+        let mut exprs = [parse("y"), parse("x^2"), parse("-x"), parse("2"), parse("sin(x)"), parse("x"), parse("x*y"), parse("1/x")];
+        exprs.sort();
+        let exprs: Vec<String> = exprs.iter().map(super::format::infix).collect();
+        assert_eq!(exprs, ["2", "x", "-x", "y", "x^2", "sin(x)", "1/x", "x*y"]);
+        let mut memo = HashMap::new();
+        memo.insert(parse("x + 1"), 1);
+        assert_eq!(memo.get(&parse("x + 1")), Some(&1));
+
+        assert_eq!(super::format::infix(&simplify(&parse("y*x + x*y + 2*z*x"))), "2*x*y + 2*x*z");
+        assert_eq!(super::format::infix(&simplify(&parse("b*-a - a*b"))), "-2*a*b");
+    }
+
+    #[test]
+    fn test_compile() {
+        use super::compile::{compile, compile_with};
+        use super::function::Registry;
+
+        let parse = |s| super::parse_str(s).unwrap();
+        let points = [(2.0, 0.5), (0.7, 3.0), (5.5, 1.25)];
+        for src in ["x^2 + 3*x - 1/x", "sin(x)*cos(y) + sqrt(x*y)", "log(2, x) + mod(x, 3) - root(y, 3)",
+                    "x^y + y! + 2^(1/2)", "abs(x - y) + floor(x/y) + sign(y - x) - frac(x)", "(x + y)^-2*(x + y)"] {
+            let f = compile(&parse(src), &["x", "y"]).unwrap();
+            for &(x, y) in &points {
+                let at = super::subs(&parse(src), &[(Expr::var("x"), Expr::float(x)), (Expr::var("y"), Expr::float(y))]);
+                match super::eval::approx(&at) {
+                    Expr::Const(Const::Float(want)) => assert!((f.call(&[x, y]) - want).abs() < 1e-12 * want.abs().max(1.0), "{} at {:?}", src, (x, y)),
+                    e => panic!("{} at {:?} is {}", src, (x, y), e),
+                }
+            }
+        }
+
+        // sin(x) is computed once and 2*3 is folded.
+        let f = compile(&parse("sin(x)^2 + sin(x)*y + 2*3"), &["x", "y"]).unwrap();
+        assert_eq!(f.to_string().matches("sin").count(), 1);
+        assert_eq!(f.len(), 5);
+        assert_eq!(compile(&parse("x + 2*3"), &["x"]).unwrap().to_string(), "r0 = x\nr1 = 6\nr2 = add r0 r1\nreturn r2");
+        assert!(compile(&parse("sqrt(x)"), &["x"]).unwrap().call(&[-1.0]).is_nan());
+        assert_eq!(compile(&parse("x + z"), &["x"]).unwrap_err(), "Unknown variable z");
+        assert!(compile(&parse("f(x)"), &["x"]).is_err());
+
+        let xs: Vec<f64> = (0..200).map(|i| i as f64 / 10.0).collect();
+        let ys: Vec<f64> = xs.iter().map(|x| 1.0 - x).collect();
+        let values = f.call_batch(&[&xs, &ys], xs.len());
+        assert!(xs.iter().zip(&ys).zip(&values).all(|((&x, &y), &v)| v == f.call(&[x, y])));
+        assert_eq!(compile(&parse("2^10"), &[]).unwrap().call_batch(&[], 3), vec![1024.0; 3]);
+
+        // Negative integer powers need no pow.
+        let f = compile(&parse("x^-1 + x^-2"), &["x"]).unwrap();
+        assert!(!f.to_string().contains("pow r"));
+        assert!(f.to_string().contains("recip") && f.to_string().contains("powi r0 -2"));
+        assert_eq!(f.call(&[2.0]), 0.75);
+        let f = compile(&Expr::var("x").pow(-Expr::int(i128::MIN)), &["x"]).unwrap();
+        assert!(f.to_string().contains("pow r"));
+
+        // Compiled and tree evaluation agree at the infinities.
+        let f = compile(&parse("mod(x, y)"), &["x", "y"]).unwrap();
+        for &(x, y) in &[(5.0, f64::INFINITY), (-5.0, f64::INFINITY), (5.0, f64::NEG_INFINITY), (0.0, f64::NEG_INFINITY),
+                         (-2.5, f64::NEG_INFINITY), (f64::INFINITY, 3.0), (7.5, -2.0)] {
+            let src = format!("mod({}, {})", x, y);
+            let want = super::eval::to_f64(match eval(&super::parse_str(&src).unwrap()) {
+                Expr::Const(c) => c,
+                e => panic!("mod({}, {}) is {}", x, y, e),
+            });
+            let got = f.call(&[x, y]);
+            assert!(got == want || (got.is_nan() && want.is_nan()), "mod({}, {}) is {}, not {}", x, y, got, want);
+        }
+
+        let mut registry = Registry::new();
+        registry.define("g", vec![String::from("t")], parse("t^2 + 1")).unwrap();
+        assert_eq!(compile_with(&parse("2*g(x)"), &["x"], &registry).unwrap().call(&[2.0]), 10.0);
+    }
+}
@@ -0,0 +1,189 @@
+use super::expr::Expr;
+
+/// Builds an `Expr` from infix syntax written directly in Rust code, with
+/// the grammar of `parser::parse` and the precedences of `default_ops`.
+/// The macro expands to the constructors of the tree, so nothing is lexed
+/// or parsed at runtime and a malformed expression fails to compile.
+///
+/// `#name` splices in a Rust variable, and `#(expr)` any Rust expression,
+/// as a sub-expression. Anything convertible into an `Expr` can be spliced,
+/// including integers, floats, `Rat` and other `Expr`s.
+///
+/// ```
+/// use betadog::{expr, Expr};
+///
+/// let a = Expr::rat(1, 2);
+/// let n = 3;
+/// assert_eq!(expr!(#a * x^#n + sin(y)), Expr::rat(1, 2) * Expr::var("x").pow(3) + Expr::var("y").sin());
+/// assert_eq!(expr!(|x - 1| + 2!), betadog::parse_str("|x - 1| + 2!").unwrap());
+/// ```
+///
+/// ```compile_fail
+/// betadog::expr!(x + * y);
+/// ```
+///
+/// The macro reads its input a token at a time, so a long expression may
+/// need a higher `#![recursion_limit]`.
+#[macro_export]
+macro_rules! expr {
+    ($($t:tt)*) => { $crate::__expr!(@sum [] [] op; $($t)*) };
+}
+
+// The grammar is read in passes, each splitting the tokens at one level of
+// operator: terms at `+` and `-`, factors at `*` and `/`, then powers at
+// `^`. Brackets are already single token trees, so a split never looks
+// inside them.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __expr {
+    // `op` and `val` say whether the last token ends an operand, which
+    // tells a binary `+` or `-` from a unary one. As in the parser, a
+    // binary `-` negates the first operand of its term.
+    (@sum [$($terms:tt)*] [$($cur:tt)*] val; + $($rest:tt)*) => {
+        $crate::__expr!(@sum [$($terms)* [$($cur)*]] [] op; $($rest)*)
+    };
+    (@sum [$($terms:tt)*] [$($cur:tt)*] val; - $($rest:tt)*) => {
+        $crate::__expr!(@sum [$($terms)* [$($cur)*]] [-] op; $($rest)*)
+    };
+    // `|x|` is `abs(x)`.
+    (@sum $terms:tt [$($cur:tt)*] op; | $($rest:tt)*) => {
+        $crate::__expr!(@bars $terms [$($cur)*] []; $($rest)*)
+    };
+    (@sum $terms:tt [$($cur:tt)*] $state:ident; + $($rest:tt)*) => {
+        $crate::__expr!(@sum $terms [$($cur)* +] op; $($rest)*)
+    };
+    (@sum $terms:tt [$($cur:tt)*] $state:ident; - $($rest:tt)*) => {
+        $crate::__expr!(@sum $terms [$($cur)* -] op; $($rest)*)
+    };
+    (@sum $terms:tt [$($cur:tt)*] $state:ident; * $($rest:tt)*) => {
+        $crate::__expr!(@sum $terms [$($cur)* *] op; $($rest)*)
+    };
+    (@sum $terms:tt [$($cur:tt)*] $state:ident; / $($rest:tt)*) => {
+        $crate::__expr!(@sum $terms [$($cur)* /] op; $($rest)*)
+    };
+    (@sum $terms:tt [$($cur:tt)*] $state:ident; ^ $($rest:tt)*) => {
+        $crate::__expr!(@sum $terms [$($cur)* ^] op; $($rest)*)
+    };
+    (@sum $terms:tt [$($cur:tt)*] $state:ident; # $($rest:tt)*) => {
+        $crate::__expr!(@sum $terms [$($cur)* #] op; $($rest)*)
+    };
+    (@sum $terms:tt [$($cur:tt)*] $state:ident; $t:tt $($rest:tt)*) => {
+        $crate::__expr!(@sum $terms [$($cur)* $t] val; $($rest)*)
+    };
+    (@sum [$($terms:tt)*] [$($cur:tt)*] $state:ident;) => {
+        $crate::__expr!(@sum_end $($terms)* [$($cur)*])
+    };
+    (@sum_end [$($term:tt)*]) => { $crate::__expr!(@prod [] []; $($term)*) };
+    (@sum_end $([$($term:tt)*])+) => {
+        $crate::Expr::Sum(::std::vec![$(::std::boxed::Box::new($crate::__expr!(@prod [] []; $($term)*))),+])
+    };
+
+    (@bars $terms:tt [$($cur:tt)*] [$($inner:tt)*]; | $($rest:tt)*) => {
+        $crate::__expr!(@sum $terms [$($cur)* abs($($inner)*)] val; $($rest)*)
+    };
+    (@bars $terms:tt $cur:tt [$($inner:tt)*]; $t:tt $($rest:tt)*) => {
+        $crate::__expr!(@bars $terms $cur [$($inner)* $t]; $($rest)*)
+    };
+    (@bars $terms:tt $cur:tt $inner:tt;) => { compile_error!("expr!: unclosed `|`") };
+
+    // Factors after a `/` start with one. Like a binary `-`, it applies to
+    // the first operand of the factor, so `1/x^2` is `(1/x)^2`.
+    (@prod [] []; * $($rest:tt)*) => { compile_error!("expr!: expected an expression before `*`") };
+    (@prod [] []; / $($rest:tt)*) => { compile_error!("expr!: expected an expression before `/`") };
+    (@prod [$($factors:tt)*] [$($cur:tt)*]; * $($rest:tt)*) => {
+        $crate::__expr!(@prod [$($factors)* [$($cur)*]] []; $($rest)*)
+    };
+    (@prod [$($factors:tt)*] [$($cur:tt)*]; / $($rest:tt)*) => {
+        $crate::__expr!(@prod [$($factors)* [$($cur)*]] [/]; $($rest)*)
+    };
+    (@prod $factors:tt [$($cur:tt)*]; $t:tt $($rest:tt)*) => {
+        $crate::__expr!(@prod $factors [$($cur)* $t]; $($rest)*)
+    };
+    (@prod [$($factors:tt)*] [$($cur:tt)*];) => { $crate::__expr!(@prod_end $($factors)* [$($cur)*]) };
+    (@prod_end [$($factor:tt)*]) => { $crate::__expr!(@factor $($factor)*) };
+    (@prod_end $([$($factor:tt)*])+) => {
+        $crate::Expr::Prod(::std::vec![$(::std::boxed::Box::new($crate::__expr!(@factor $($factor)*))),+])
+    };
+    (@factor $($t:tt)*) => { $crate::__expr!(@pow []; $($t)*) };
+
+    // `^` is right associative.
+    (@pow [$($base:tt)*]; ^ $($rest:tt)*) => {
+        $crate::Expr::Pow(
+            ::std::boxed::Box::new($crate::__expr!(@primary $($base)*)),
+            ::std::boxed::Box::new($crate::__expr!(@pow []; $($rest)*)),
+        )
+    };
+    (@pow [$($base:tt)*]; $t:tt $($rest:tt)*) => { $crate::__expr!(@pow [$($base)* $t]; $($rest)*) };
+    (@pow [$($base:tt)*];) => { $crate::__expr!(@primary $($base)*) };
+
+    // An operand with its prefix and postfix operators. Prefix operators
+    // apply to the operand and its postfix ones, as in the parser.
+    (@primary - $($t:tt)+) => { $crate::Expr::Neg(::std::boxed::Box::new($crate::__expr!(@primary $($t)+))) };
+    (@primary / $($t:tt)+) => { $crate::Expr::Recipr(::std::boxed::Box::new($crate::__expr!(@primary $($t)+))) };
+    (@primary + $($t:tt)+) => {
+        $crate::Expr::Sum(::std::vec![::std::boxed::Box::new($crate::__expr!(@primary $($t)+))])
+    };
+    (@primary # $v:ident $($rest:tt)*) => {
+        $crate::__expr!(@postfix [$crate::Expr::from(::std::clone::Clone::clone(&$v))] $($rest)*)
+    };
+    (@primary # ($e:expr) $($rest:tt)*) => { $crate::__expr!(@postfix [$crate::Expr::from($e)] $($rest)*) };
+    (@primary inf $($rest:tt)*) => { $crate::__expr!(@postfix [$crate::Expr::Const($crate::Const::Inf)] $($rest)*) };
+    (@primary undef $($rest:tt)*) => { $crate::__expr!(@postfix [$crate::Expr::Const($crate::Const::Undef)] $($rest)*) };
+    (@primary $f:ident ($($args:tt)*) $($rest:tt)*) => {
+        $crate::__expr!(@postfix [$crate::Expr::Call(
+            $crate::Func::from_name(stringify!($f)),
+            $crate::__expr!(@args [] []; $($args)*),
+        )] $($rest)*)
+    };
+    (@primary $v:ident $($rest:tt)*) => { $crate::__expr!(@postfix [$crate::Expr::var(stringify!($v))] $($rest)*) };
+    (@primary ($($inner:tt)*) $($rest:tt)*) => { $crate::__expr!(@postfix [$crate::expr!($($inner)*)] $($rest)*) };
+    (@primary $l:literal $($rest:tt)*) => {
+        $crate::__expr!(@postfix [$crate::macros::Literal::into_expr($l)] $($rest)*)
+    };
+    (@primary $t:tt $($rest:tt)*) => { compile_error!(concat!("expr!: unexpected `", stringify!($t), "`")) };
+    (@primary) => { compile_error!("expr!: expected an expression") };
+
+    // `!!` is two `!` tokens to rustc, and the lexer reads it first.
+    (@postfix [$($e:tt)*] ! ! $($rest:tt)*) => {
+        $crate::__expr!(@postfix [$crate::Expr::call($crate::Func::DoubleFactorial, ::std::vec![$($e)*])] $($rest)*)
+    };
+    (@postfix [$($e:tt)*] ! $($rest:tt)*) => {
+        $crate::__expr!(@postfix [$crate::Expr::call($crate::Func::Factorial, ::std::vec![$($e)*])] $($rest)*)
+    };
+    (@postfix [$($e:tt)*] % $($rest:tt)*) => {
+        $crate::__expr!(@postfix [$crate::Expr::Prod(::std::vec![
+            ::std::boxed::Box::new($($e)*),
+            ::std::boxed::Box::new($crate::Expr::int(100).recipr()),
+        ])] $($rest)*)
+    };
+    (@postfix [$($e:tt)*]) => { $($e)* };
+    (@postfix [$($e:tt)*] $t:tt $($rest:tt)*) => { compile_error!(concat!("expr!: unexpected `", stringify!($t), "`")) };
+
+    // Call arguments, split at top level commas.
+    (@args [$($args:tt)*] [$($cur:tt)*]; , $($rest:tt)*) => {
+        $crate::__expr!(@args [$($args)* [$($cur)*]] []; $($rest)*)
+    };
+    (@args $args:tt [$($cur:tt)*]; $t:tt $($rest:tt)*) => { $crate::__expr!(@args $args [$($cur)* $t]; $($rest)*) };
+    (@args [] [];) => { ::std::vec::Vec::new() };
+    (@args [$([$($arg:tt)*])*] [$($cur:tt)*];) => {
+        ::std::vec![$(::std::boxed::Box::new($crate::expr!($($arg)*)),)* ::std::boxed::Box::new($crate::expr!($($cur)*))]
+    };
+}
+
+/// A literal in `expr!`. An integer literal can only be an `i128` here and
+/// a float literal only an `f64`.
+pub trait Literal {
+    fn into_expr(self) -> Expr;
+}
+
+impl Literal for i128 {
+    fn into_expr(self) -> Expr {
+        Expr::int(self)
+    }
+}
+
+impl Literal for f64 {
+    fn into_expr(self) -> Expr {
+        Expr::float(self)
+    }
+}
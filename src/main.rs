@@ -1,47 +1,200 @@
-use std::collections::{HashMap};
-use std::io;
-use std::io::Write;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::process;
 
-mod betadog;
+use betadog::{default_ops, format, Expr};
+use betadog::lexer::{self, lex_recovering_with, Tok};
+use betadog::parser::Options;
+use betadog::script::{self, Event};
+use betadog::session::Session;
 
-use betadog::lexer::{lex};
-use betadog::eval;
-use betadog::parser::{parse};
+mod repl;
+
+const USAGE: &str = "\
+Usage: betadog [OPTIONS] [FILE...]
+
+Runs the scripts given with -e, then each FILE. With neither, reads a
+script from stdin when it is piped and starts the interactive prompt when
+it is a terminal. FILE - is stdin. The result of each expression and each
+print statement is written on its own line.
+
+Options:
+  -e, --eval EXPR        evaluate EXPR
+  -f, --format FORMAT    write results as infix, sexpr, latex or json
+  -p, --precision N      write floats with N decimals
+  -r, --rules FILE       apply the rewrite rules in FILE to every result
+      --approx           evaluate numerically instead of exactly
+  -h, --help             show this message
+
+The exit status is 1 if any statement fails and 2 for bad arguments,
+unreadable files or invalid rules.";
+
+// How results are written.
+#[derive(Clone, Copy)]
+enum Output {
+    Text(format::Options),
+    Json,
+}
+
+enum Input {
+    Expr(String),
+    File(String),
+}
+
+struct Args {
+    inputs: Vec<Input>,
+    rules: Vec<String>,
+    output: Output,
+    approx: bool,
+}
 
 fn main() {
-    let ops = {
-        let mut ops = HashMap::with_capacity(5);
-        ops.insert(String::from("^"), 60);
-        ops.insert(String::from("*"), 40);
-        ops.insert(String::from("/"), 40);
-        ops.insert(String::from("+"), 20);
-        ops.insert(String::from("-"), 20);
-        ops
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        },
+        Err(message) => {
+            eprintln!("betadog: {}\n\n{}", message, USAGE);
+            process::exit(2);
+        },
     };
 
-    loop {
-        let s = {
-            let mut s = String::new();
-            print!(">>> ");
-            io::stdout().flush()
-                .expect("Failed to flush stdout");
-            io::stdin().read_line(&mut s)
-                .expect("Failed to read input");
-            s
+    let options = Options{implicit_mul: true, ..Options::default()};
+    let mut session = Session::new(default_ops(), options);
+    session.set_approx(args.approx);
+    for path in &args.rules {
+        let src = fs::read_to_string(path).unwrap_or_else(|err| {
+            eprintln!("betadog: {}: {}", path, err);
+            process::exit(2);
+        });
+        if let Err(diagnostics) = session.load_rules(&src) {
+            for diagnostic in diagnostics {
+                eprint!("{}: {}", path, diagnostic.render(&src));
+            }
+            process::exit(2);
+        }
+    }
+
+    if args.inputs.is_empty() && io::stdin().is_terminal() {
+        let format = match args.output {
+            Output::Text(format) => format,
+            Output::Json => {
+                eprintln!("betadog: The interactive prompt cannot write json\n\n{}", USAGE);
+                process::exit(2);
+            },
         };
+        return repl::run(session, format);
+    }
 
-        match lex(s) {
-            Ok(toks) => { 
-                println!("Lexer Output: {:?}", toks);
-                match parse(toks, ops.clone()) {
-                    Ok(ast) => {
-                        println!("Parser Output: {}", ast);
-                        //println!("Result: {}", eval(ast));
-                    }
-                    Err(err) => println!("{:?}", err),
-                }
+    let inputs = if args.inputs.is_empty() { vec![Input::File(String::from("-"))] } else { args.inputs };
+    let mut batch = Batch{session, output: args.output, failed: false};
+    for input in inputs {
+        let result = match input {
+            Input::Expr(src) => {
+                batch.script("-e", &src, 1);
+                Ok(())
             },
-            Err(err) => println!("{:?}", err),
+            // A file is one script, which stops at its first error.
+            Input::File(path) if path == "-" => io::read_to_string(io::stdin())
+                .map(|src| batch.script("<stdin>", &src, 1)),
+            Input::File(path) => fs::read_to_string(&path)
+                .map(|src| batch.script(&path, &src, 1))
+                .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path, err))),
         };
+        if let Err(err) = result {
+            eprintln!("betadog: {}", err);
+            process::exit(2);
+        }
+    }
+    if batch.failed {
+        process::exit(1);
+    }
+}
+
+// `Ok(None)` for `--help`.
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Option<Args>, String> {
+    let mut inputs = Vec::new();
+    let mut rules = Vec::new();
+    let mut format = format::Options::default();
+    let mut json = false;
+    let mut approx = false;
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+        match &arg[..] {
+            "-h" | "--help" => return Ok(None),
+            "-e" | "--eval" => inputs.push(Input::Expr(value(&arg)?)),
+            "-f" | "--format" => match &value(&arg)?[..] {
+                "json" => json = true,
+                style => {
+                    format.style = style.parse()?;
+                    json = false;
+                },
+            },
+            "-p" | "--precision" => {
+                let digits = value(&arg)?;
+                format.precision = Some(digits.parse().map_err(|_| format!("Invalid precision {}", digits))?);
+            },
+            "-r" | "--rules" => rules.push(value(&arg)?),
+            "--approx" => approx = true,
+            "--" => inputs.extend(args.by_ref().map(Input::File)),
+            "-" => inputs.push(Input::File(arg)),
+            option if option.starts_with('-') => return Err(format!("Unknown option {}", option)),
+            _ => inputs.push(Input::File(arg)),
+        }
+    }
+    let output = if json { Output::Json } else { Output::Text(format) };
+    Ok(Some(Args{inputs, rules, output, approx}))
+}
+
+// Runs statements without a prompt, writing one result per line to stdout
+// and errors to stderr.
+struct Batch {
+    session: Session,
+    output: Output,
+    failed: bool,
+}
+
+impl Batch {
+    // Runs `src`, which starts on line `line_no` of the input `name`.
+    fn script(&mut self, name: &str, src: &str, line_no: usize) {
+        let output = self.output;
+        let write = |expr: &Expr| write(expr, output);
+        let result = script::run(&mut self.session, src, |event| match event {
+            Event::Value(value) => println!("{}", write(&value)),
+            Event::Defined(_) => (),
+            Event::Print(values) => println!("{}", values.iter().map(write).collect::<Vec<_>>().join(" ")),
+        });
+        if let Err(diagnostics) = result {
+            self.failed = true;
+            for diagnostic in diagnostics {
+                eprint!("{}: {}", name, diagnostic.render_from(src, line_no));
+            }
+        }
+    }
+}
+
+fn write(expr: &Expr, output: Output) -> String {
+    match output {
+        Output::Text(options) => format::render(expr, options),
+        Output::Json => serde_json::to_string(expr).expect("Expressions serialise to JSON"),
+    }
+}
+
+// Whether `input` has an open bracket, block or `|`, so the next line
+// continues it.
+fn is_incomplete(input: &str) -> bool {
+    let (toks, _) = lex_recovering_with(input, lexer::Options{comments: true, ..lexer::Options::default()});
+    let mut depth = 0;
+    let mut bars = 0;
+    for t in &toks {
+        match t.tok {
+            Tok::LParen | Tok::LFloor | Tok::LCeil | Tok::LBrace => depth += 1,
+            Tok::RParen | Tok::RFloor | Tok::RCeil | Tok::RBrace => depth -= 1,
+            Tok::Bar => bars += 1,
+            _ => (),
+        }
     }
+    depth > 0 || bars % 2 == 1
 }
@@ -0,0 +1,66 @@
+use std::ops::{Add, Sub, Mul, Div, Neg};
+use super::expr::Expr;
+
+// `std::ops` for `Expr` and `&Expr`. Sums and products are flattened as they
+// are built, so `a + b + c` is a single `Sum` just like the parser makes it.
+
+fn sum(lhs: Expr, rhs: Expr) -> Expr {
+    let mut terms = Vec::new();
+    for expr in [lhs, rhs] {
+        match expr {
+            Expr::Sum(v) => terms.extend(v),
+            expr => terms.push(Box::new(expr)),
+        }
+    }
+    Expr::Sum(terms)
+}
+
+fn prod(lhs: Expr, rhs: Expr) -> Expr {
+    let mut factors = Vec::new();
+    for expr in [lhs, rhs] {
+        match expr {
+            Expr::Prod(v) => factors.extend(v),
+            expr => factors.push(Box::new(expr)),
+        }
+    }
+    Expr::Prod(factors)
+}
+
+macro_rules! impl_op {
+    ($trait:ident, $method:ident, |$lhs:ident, $rhs:ident| $body:expr) => {
+        impl<T: Into<Expr>> $trait<T> for Expr {
+            type Output = Expr;
+            fn $method(self, rhs: T) -> Expr {
+                let ($lhs, $rhs) = (self, rhs.into());
+                $body
+            }
+        }
+
+        impl<T: Into<Expr>> $trait<T> for &Expr {
+            type Output = Expr;
+            fn $method(self, rhs: T) -> Expr {
+                let ($lhs, $rhs) = (self.clone(), rhs.into());
+                $body
+            }
+        }
+    };
+}
+
+impl_op!(Add, add, |lhs, rhs| sum(lhs, rhs));
+impl_op!(Sub, sub, |lhs, rhs| sum(lhs, -rhs));
+impl_op!(Mul, mul, |lhs, rhs| prod(lhs, rhs));
+impl_op!(Div, div, |lhs, rhs| prod(lhs, rhs.recipr()));
+
+impl Neg for Expr {
+    type Output = Expr;
+    fn neg(self) -> Expr {
+        Expr::Neg(Box::new(self))
+    }
+}
+
+impl Neg for &Expr {
+    type Output = Expr;
+    fn neg(self) -> Expr {
+        -self.clone()
+    }
+}
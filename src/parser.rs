@@ -0,0 +1,649 @@
+use super::lexer::{Span, Tok, Token};
+use super::expr::{Expr, Const, Func};
+use std::collections::HashMap;
+use std::fmt;
+use std::iter::{Peekable};
+use std::rc::Rc;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    UnexpectedToken,
+    UnexpectedEnd,
+    UnknownOperator,
+    UnclosedParen,
+    UnmatchedParen,
+    ExpectedDelimiter,
+    TrailingTokens,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Error {
+    kind: ErrorKind,
+    span: Span,
+    message: String,
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn hint(&self) -> Option<&'static str> {
+        match self.kind {
+            ErrorKind::UnexpectedToken => Some("expected a number, a variable, a function call or '('"),
+            ErrorKind::UnexpectedEnd => Some("the expression is incomplete"),
+            ErrorKind::UnknownOperator => Some("the operators are + - * / ^ and the postfix ! !! %"),
+            ErrorKind::UnclosedParen => Some("add a matching ')'"),
+            ErrorKind::UnmatchedParen => Some("remove it or add a matching '('"),
+            ErrorKind::ExpectedDelimiter => Some("there may be an operator, ',' or ')' missing before this"),
+            ErrorKind::TrailingTokens => Some("there may be an operator missing before this"),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.span.start)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// How `a/bc` is read when implicit multiplication is on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ImplicitDiv {
+    /// `1/2x` is `(1/2)*x`, as if the multiplication were written out.
+    Loose,
+    /// `1/2x` is `1/(2*x)`.
+    Tight,
+}
+
+/// A callback for `Options::trace`.
+pub type Trace = Rc<dyn Fn(&Expr)>;
+
+/// Grammar extensions that are off by default.
+#[derive(Clone)]
+pub struct Options {
+    /// Reads adjacent factors as a product: `2x`, `3(x+1)`, `(a+b)(a-b)`
+    /// and `2 sin(x)`. Powers bind tighter, so `2x^2` is `2*(x^2)`. Only
+    /// builtin functions and those in `functions` can be called, so
+    /// `f(x+1)` is `f*(x+1)` unless `f` is listed.
+    pub implicit_mul: bool,
+    pub implicit_div: ImplicitDiv,
+    /// Names that are called like the builtins with `implicit_mul`, such as
+    /// user-defined functions.
+    pub functions: Vec<String>,
+    /// Called with every partial sum and product as it is built, to watch
+    /// the parser at work.
+    pub trace: Option<Trace>,
+}
+
+impl fmt::Debug for Options {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Options")
+            .field("implicit_mul", &self.implicit_mul)
+            .field("implicit_div", &self.implicit_div)
+            .field("functions", &self.functions)
+            .field("trace", &self.trace.is_some())
+            .finish()
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options{implicit_mul: false, implicit_div: ImplicitDiv::Loose, functions: Vec::new(), trace: None}
+    }
+}
+
+/// Parses `toks` into an expression. `ops` maps every binary operator to
+/// its precedence, higher binding tighter; see `default_ops`.
+///
+/// Tokens are read as they are needed, so `toks` can be a `lexer::Lexer`
+/// that stops at the first error.
+pub fn parse<'src, I: IntoIterator<Item = Token<'src>>>(toks: I, ops: HashMap<String, i8>) -> Result<Expr, Error> {
+    parse_with(toks, ops, Options::default())
+}
+
+/// Like `parse`, with the grammar extensions in `options`.
+pub fn parse_with<'src, I: IntoIterator<Item = Token<'src>>>(toks: I, ops: HashMap<String, i8>, options: Options) -> Result<Expr, Error> {
+    let mut parser = Parser::new(toks.into_iter(), 0, ops, options, false);
+    let ast = parser.parse_expr()?;
+    if !parser.is_done() {
+        return Err(parser.trailing());
+    }
+    Ok(ast)
+}
+
+/// Like `parse`, but does not stop at the first mistake. Every error is
+/// recorded, the parser resynchronises on the next operator, ',' or ')', and
+/// the parts of the input that could not be parsed become `Expr::Error`
+/// nodes in the returned tree. The errors are in the order they occur in
+/// the input.
+///
+/// `Tok::Error` tokens from `lexer::lex_recovering` are parsed as
+/// `Expr::Error` without a further error, since the lexer reported them.
+pub fn parse_recovering(toks: Vec<Token>, ops: HashMap<String, i8>) -> (Expr, Vec<Error>) {
+    parse_recovering_with(toks, ops, Options::default())
+}
+
+/// Like `parse_recovering`, with the grammar extensions in `options`.
+pub fn parse_recovering_with(toks: Vec<Token>, ops: HashMap<String, i8>, options: Options) -> (Expr, Vec<Error>) {
+    let end = toks.last().map_or(0, |t| t.span.end);
+    let mut parser = Parser::new(toks.into_iter(), end, ops, options, true);
+    let mut ast = parser.parse_expr().unwrap_or_else(|err| parser.recovered(err));
+    while !parser.is_done() {
+        let err = parser.trailing();
+        parser.record(err);
+        parser.skip_until(|tok| matches!(tok, Tok::Op(_)));
+        if !parser.is_done() {
+            ast = parser.parse_expr_rhs(ast).unwrap_or_else(|err| parser.recovered(err));
+        }
+    }
+    (ast, parser.errors)
+}
+
+struct Parser<'src, I: Iterator<Item = Token<'src>>> {
+    binary_ops: HashMap<String, i8>,
+    toks: Peekable<I>,
+    // The end of the input read so far. When recovering, the whole input
+    // is known from the start.
+    end: usize,
+    options: Options,
+    // How many `|` are open. Inside one a `|` closes it rather than starting
+    // an implicitly multiplied `|x|`.
+    bars: usize,
+    // In recovering mode errors are collected here instead of returned.
+    recover: bool,
+    errors: Vec<Error>,
+}
+
+impl<'src, I: Iterator<Item = Token<'src>>> Parser<'src, I> {
+    fn new(toks: I, end: usize, ops: HashMap<String, i8>, options: Options, recover: bool) -> Parser<'src, I> {
+        Parser{
+            binary_ops: ops,
+            toks: toks.peekable(),
+            end,
+            options,
+            bars: 0,
+            recover,
+            errors: Vec::new(),
+        }
+    }
+
+    fn is_done(&mut self) -> bool {
+        self.toks.peek().is_none()
+    }
+
+    fn peek(&mut self) -> Option<Tok<'src>> {
+        self.toks.peek().map(|t| t.tok)
+    }
+
+    fn next(&mut self) -> Option<Tok<'src>> {
+        let token = self.toks.next()?;
+        self.end = self.end.max(token.span.end);
+        Some(token.tok)
+    }
+
+    // The span of the next token, or an empty span at the end of the input.
+    fn span(&mut self) -> Span {
+        match self.toks.peek() {
+            Some(t) => t.span,
+            None => Span::new(self.end, self.end),
+        }
+    }
+
+    fn trace<F: FnOnce() -> Expr>(&self, partial: F) {
+        if let Some(trace) = &self.options.trace {
+            trace(&partial());
+        }
+    }
+
+    fn error(&mut self, kind: ErrorKind, message: String) -> Error {
+        let span = self.span();
+        Error{kind, span, message}
+    }
+
+    fn record(&mut self, err: Error) {
+        // The same token can be looked at by several rules; report it once.
+        if self.errors.last().is_none_or(|last| last.span != err.span) {
+            self.errors.push(err);
+        }
+    }
+
+    fn recovered(&mut self, err: Error) -> Expr {
+        self.record(err);
+        Expr::Error
+    }
+
+    // Returns `value` in place of the error in recovering mode.
+    fn fail<T>(&mut self, err: Error, value: T) -> Result<T, Error> {
+        if self.recover {
+            self.record(err);
+            Ok(value)
+        } else {
+            Err(err)
+        }
+    }
+
+    // Skips tokens, stepping over parenthesised groups, until `stop` matches
+    // a token outside of them or an unmatched ')' is reached.
+    fn skip_until(&mut self, stop: fn(&Tok) -> bool) {
+        let mut depth = 0;
+        while let Some(tok) = self.peek() {
+            match tok {
+                Tok::RParen if depth == 0 => return,
+                tok if depth == 0 && stop(&tok) => return,
+                Tok::LParen => depth += 1,
+                Tok::RParen => depth -= 1,
+                _ => (),
+            }
+            self.next();
+        }
+    }
+
+    fn trailing(&mut self) -> Error {
+        if let Some(Tok::RParen) = self.peek() {
+            let err = self.error(ErrorKind::UnmatchedParen, String::from("Unmatched ')'"));
+            self.next();
+            return err;
+        }
+        let start = self.span();
+        if !self.recover {
+            // Nothing is parsed after this, so read on to where the input ends.
+            while self.next().is_some() {}
+        }
+        let span = start.to(Span::new(self.end, self.end));
+        Error{
+            kind: ErrorKind::TrailingTokens,
+            span,
+            message: String::from("Unexpected tokens after parsing expression"),
+        }
+    }
+
+    fn unexpected(&mut self) -> Result<Expr, Error> {
+        let err = if self.is_done() {
+            self.error(ErrorKind::UnexpectedEnd, String::from("Unexpected end of tokens while parsing"))
+        } else {
+            self.error(ErrorKind::UnexpectedToken, String::from("Unexpected token"))
+        };
+        self.fail(err, Expr::Error)
+    }
+
+    fn prec(&mut self, op: &str) -> Result<i8, Error> {
+        match self.binary_ops.get(op) {
+            Some(p) => Ok(*p),
+            None => {
+                let err = self.error(ErrorKind::UnknownOperator, format!("Unknown operator {}", op));
+                self.fail(err, i8::MAX)
+            },
+        }
+    }
+
+    fn binary(&mut self, op: &str, span: Span, lhs: Expr, rhs: Expr) -> Result<Expr, Error> {
+        match Expr::new_binary(op, lhs, rhs) {
+            Some(expr) => Ok(expr),
+            None => self.fail(Error{
+                kind: ErrorKind::UnknownOperator,
+                span,
+                message: format!("Unknown operator {}", op),
+            }, Expr::Error),
+        }
+    }
+
+    // Lets a term like `x^2` be the first factor of a following product.
+    fn continue_product(&mut self, expr: Expr) -> Result<Expr, Error> {
+        match self.peek() {
+            Some(Tok::Op(op)) if op == "*" || op == "/" => self.parse_product_rhs(expr),
+            _ if self.implicit_next() => self.parse_product_rhs(expr),
+            _ => Ok(expr),
+        }
+    }
+
+    // Whether the next token starts a factor that is multiplied implicitly.
+    fn implicit_next(&mut self) -> bool {
+        let bars = self.bars;
+        self.options.implicit_mul && match self.peek() {
+            Some(Tok::Lit(_)) | Some(Tok::Iden(_)) | Some(Tok::LParen) | Some(Tok::Inf) | Some(Tok::Undef) |
+            Some(Tok::LFloor) | Some(Tok::LCeil) => true,
+            Some(Tok::Bar) => bars == 0,
+            _ => false,
+        }
+    }
+
+    // The right hand side of a '/'. With `ImplicitDiv::Tight` this takes in
+    // the following implicitly multiplied factors, so `1/2x` is `1/(2*x)`.
+    fn parse_divisor(&mut self) -> Result<Expr, Error> {
+        let divisor = self.parse_primary()?;
+        if self.options.implicit_div == ImplicitDiv::Loose || !self.implicit_next() {
+            return Ok(divisor);
+        }
+        let mut factors = vec![Box::new(divisor)];
+        while self.implicit_next() {
+            let factor = self.parse_primary()?;
+            let factor = match self.peek() {
+                Some(Tok::Op(op)) if op != "*" && op != "/" && self.prec(op)? > self.prec("*")? =>
+                    self.parse_bin_op_rhs(factor)?,
+                _ => factor,
+            };
+            factors.push(Box::new(factor));
+        }
+        Ok(Expr::Prod(factors))
+    }
+
+    // Called when a ')' was expected; `recovered` is returned in its place
+    // in recovering mode.
+    fn unclosed<T>(&mut self, lparen: Span, message: &str, recovered: T) -> Result<T, Error> {
+        let err = if self.is_done() {
+            Error{kind: ErrorKind::UnclosedParen, span: lparen, message: String::from("Unclosed '('")}
+        } else {
+            self.error(ErrorKind::ExpectedDelimiter, String::from(message))
+        };
+        self.fail(err, recovered)
+    }
+
+    fn parse_paren_expr(&mut self) -> Result<Expr, Error> {
+        let lparen = self.span();
+        self.next(); // eats LParen
+        let expr = self.parse_expr()?;
+        if let Some(Tok::RParen) = self.peek() {
+            self.next(); // eats RParen
+            return Ok(expr);
+        }
+        let expr = self.unclosed(lparen, "Expected ')'", expr)?;
+        self.skip_until(|_| false);
+        if let Some(Tok::RParen) = self.peek() {
+            self.next(); // eats RParen
+            return Ok(expr);
+        }
+        self.unclosed(lparen, "", expr)
+    }
+    
+    // `|x|`, `⌊x⌋` and `⌈x⌉`, which are calls of `fun`.
+    fn parse_bracket(&mut self, close: Tok, fun: Func) -> Result<Expr, Error> {
+        let open = self.span();
+        let (opening, closing) = match close {
+            Tok::RFloor => ("⌊", "⌋"),
+            Tok::RCeil => ("⌈", "⌉"),
+            _ => ("|", "|"),
+        };
+        self.next(); // Eats the opening bracket
+        if close == Tok::Bar {
+            self.bars += 1;
+        }
+        let expr = self.parse_expr();
+        if close == Tok::Bar {
+            self.bars -= 1;
+        }
+        let expr = Expr::Call(fun, vec![Box::new(expr?)]);
+        if self.peek() == Some(close) {
+            self.next(); // Eats the closing bracket
+            return Ok(expr);
+        }
+        let err = if self.is_done() {
+            Error{kind: ErrorKind::UnclosedParen, span: open, message: format!("Unclosed '{}'", opening)}
+        } else {
+            self.error(ErrorKind::ExpectedDelimiter, format!("Expected '{}'", closing))
+        };
+        self.fail(err, expr)
+    }
+
+    fn parse_args(&mut self) -> Result<Vec<Box<Expr>>, Error> {
+        let lparen = self.span();
+        self.next(); // Eats LParen
+        let mut args = Vec::new();
+        if let Some(Tok::RParen) = self.peek() {
+            self.next(); // Eats RParen
+            return Ok(args);
+        }
+        loop {
+            args.push(Box::new(self.parse_expr()?));
+            match self.peek() {
+                Some(Tok::Comma) => { self.next(); },
+                Some(Tok::RParen) => {
+                    self.next();
+                    return Ok(args);
+                },
+                None => return self.unclosed(lparen, "", args),
+                _ => {
+                    self.unclosed(lparen, "Expected ',' or ')' in argument list", ())?;
+                    self.skip_until(|tok| *tok == Tok::Comma);
+                    match self.peek() {
+                        Some(Tok::Comma) => { self.next(); },
+                        Some(Tok::RParen) => {
+                            self.next();
+                            return Ok(args);
+                        },
+                        _ => return self.unclosed(lparen, "", args),
+                    }
+                },
+            }
+        }
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, Error> {
+        let lhs = self.parse_primary()?;
+        self.parse_expr_rhs(lhs)
+    }
+
+    fn parse_expr_rhs(&mut self, lhs: Expr) -> Result<Expr, Error> {
+        let term = match self.peek() {
+            Some(Tok::Op(op)) if op == "+" || op == "-" => return self.parse_sum_rhs(lhs),
+            Some(Tok::Op(op)) if op == "*" || op == "/" => self.parse_product_rhs(lhs)?,
+            Some(Tok::Op(_)) => {
+                let expr = self.parse_bin_op_rhs(lhs)?;
+                self.continue_product(expr)?
+            },
+            _ if self.implicit_next() => self.parse_product_rhs(lhs)?,
+            _ => return Ok(lhs),
+        };
+        match self.peek() {
+            Some(Tok::Op(op)) if op == "+" || op == "-" => self.parse_sum_rhs(term),
+            _ => Ok(term),
+        }
+    }
+    
+    fn parse_sum_rhs(&mut self, lhs: Expr) -> Result<Expr, Error> {
+        let mut expr = vec![Box::new(lhs)];
+        macro_rules! f {
+            ($rhs:expr) => {
+                self.next(); // Eats Op
+                let rhs: Expr = $rhs;
+                if let Some(Tok::Op(op)) = self.peek() {
+                    match &op[..] {
+                        "+" | "-" => 
+                            expr.push(Box::new(rhs)),
+                        "*" | "/" => 
+                            expr.push(Box::new(self.parse_product_rhs(rhs)?)),
+                        op => if self.prec(op)? >= self.prec("+")? {
+                            let term = self.parse_bin_op_rhs(rhs)?;
+                            expr.push(Box::new(self.continue_product(term)?));
+                        } else {
+                            return self.parse_bin_op_rhs(Expr::Sum(expr));
+                        },
+                    }
+                } else if self.implicit_next() {
+                    expr.push(Box::new(self.parse_product_rhs(rhs)?));
+                } else {
+                    expr.push(Box::new(rhs));
+                    return Ok(Expr::Sum(expr));
+                };
+            };
+        }
+
+        loop {
+            self.trace(|| Expr::Sum(expr.clone()));
+            if let Some(Tok::Op(op)) = self.peek() {
+                match op {
+                    "+" => {
+                        f!(self.parse_primary()?);
+                    },
+                    "-" => {
+                        f!(Expr::Neg(Box::new(self.parse_primary()?)));
+                    },
+                    _ => return Ok(Expr::Sum(expr)),
+                };
+            } else {
+                return Ok(Expr::Sum(expr));
+            }
+        }
+    }
+
+    fn parse_product_rhs(&mut self, lhs: Expr) -> Result<Expr, Error> {
+        let mut expr = vec![Box::new(lhs)];
+        macro_rules! f {
+            ($rhs:expr) => {
+                let rhs: Expr = $rhs;
+                if let Some(Tok::Op(op)) = self.peek() {
+                    match &op[..] {
+                        "+" | "-" => {
+                            expr.push(Box::new(rhs));
+                            return Ok(Expr::Prod(expr));
+                        },
+                        "*" | "/" => 
+                            expr.push(Box::new(rhs)),
+                        op => if self.prec(op)? >= self.prec("*")? {
+                            expr.push(Box::new(self.parse_bin_op_rhs(rhs)?));
+                        } else {
+                            return self.parse_bin_op_rhs(Expr::Prod(expr));
+                        },
+                    }
+                } else {
+                    expr.push(Box::new(rhs));
+                    if !self.implicit_next() {
+                        return Ok(Expr::Prod(expr));
+                    }
+                };
+            };
+        }
+
+        loop {
+            self.trace(|| Expr::Prod(expr.clone()));
+            if let Some(Tok::Op(op)) = self.peek() {
+                match op {
+                    "*" => {
+                        self.next(); // Eats Op
+                        f!(self.parse_primary()?);
+                    },
+                    "/" => {
+                        self.next(); // Eats Op
+                        f!(Expr::Recipr(Box::new(self.parse_divisor()?)));
+                    },
+                    _ => return Ok(Expr::Prod(expr)),
+                };
+            } else if self.implicit_next() {
+                f!(self.parse_primary()?);
+            } else {
+                return Ok(Expr::Prod(expr));
+            }
+        }
+    }
+
+    fn parse_bin_op_rhs(&mut self, lhs: Expr) -> Result<Expr, Error> {
+        if let Some(Tok::Op(op)) = self.peek() {
+            let prec = self.prec(op)?;
+
+            let span = self.span();
+            self.next(); // Eats Op
+            let rhs = self.parse_primary()?;
+
+            let (next_op, next_prec) = if let Some(Tok::Op(next_op)) = self.peek() {
+                (next_op, self.prec(next_op)?)
+            } else {
+                return self.binary(op, span, lhs, rhs);
+            };
+
+            if let "+" | "-" | "*" | "/" = next_op {
+                return self.binary(op, span, lhs, rhs);
+            }
+
+            if prec > next_prec {
+                let lhs = self.binary(op, span, lhs, rhs)?;
+                return self.parse_bin_op_rhs(lhs);
+            } else {
+                let rhs = self.parse_bin_op_rhs(rhs)?;
+                return self.binary(op, span, lhs, rhs);
+            }
+        }
+        unreachable!();
+    }
+    
+    // A primary followed by any postfix operators, which bind tightest:
+    // `2^3!` is `2^(3!)` and `-3!` is `-(3!)`.
+    fn parse_primary(&mut self) -> Result<Expr, Error> {
+        let mut expr = self.parse_operand()?;
+        while let Some(Tok::Op(op)) = self.peek() {
+            match Expr::new_postfix(op, expr) {
+                Ok(postfix) => {
+                    self.next(); // Eats op
+                    expr = postfix;
+                },
+                Err(operand) => {
+                    expr = operand;
+                    break;
+                },
+            }
+        }
+        Ok(expr)
+    }
+
+    fn parse_operand(&mut self) -> Result<Expr, Error> {
+        if let Some(tok) = self.peek() {
+            return match tok {
+                Tok::Inf => {
+                    self.next(); // Eats inf
+                    Ok(Expr::Const(Const::Inf))
+                },
+                Tok::Undef => {
+                    self.next(); // Eats undef
+                    Ok(Expr::Const(Const::Undef))
+                }
+                Tok::Lit(c) => { 
+                    self.next(); // Eats constant
+                    Ok(Expr::Const(c)) 
+                },
+                Tok::Op(op) => {
+                    let span = self.span();
+                    self.next(); // Eats op
+                    match Expr::new_unary(op, self.parse_primary()?) {
+                        Some(v) => Ok(v),
+                        None => self.fail(Error{
+                            kind: ErrorKind::UnknownOperator,
+                            span,
+                            message: format!("Unknown unary operator {}", op),
+                        }, Expr::Error),
+                    }
+                },
+                Tok::LParen => self.parse_paren_expr(),
+                Tok::Bar => self.parse_bracket(Tok::Bar, Func::Abs),
+                Tok::LFloor => self.parse_bracket(Tok::RFloor, Func::Floor),
+                Tok::LCeil => self.parse_bracket(Tok::RCeil, Func::Ceil),
+                Tok::Error if self.recover => {
+                    self.next(); // Eats the token the lexer already reported
+                    Ok(Expr::Error)
+                },
+                Tok::Iden(s) => {
+                    self.next(); // Eats iden
+                    let is_call = !self.options.implicit_mul || Func::from_name(s).is_builtin()
+                        || self.options.functions.iter().any(|f| f == s);
+                    if let (Some(Tok::LParen), true) = (self.peek(), is_call) {
+                        Ok(Expr::Call(Func::from_name(s), self.parse_args()?))
+                    } else {
+                        Ok(Expr::Var(String::from(s)))
+                    }
+                },
+                _ => self.unexpected(),
+            }
+        }
+        self.unexpected()
+    }
+    
+}
+
@@ -0,0 +1,277 @@
+use std::ops::{Add, AddAssign, Sub, SubAssign, Mul, MulAssign, Div, DivAssign, Neg};
+use std::cmp::{Ordering};
+use std::convert::TryFrom;
+use std::fmt;
+use std::fmt::{Display};
+
+fn gcd(mut lhs: i128, mut rhs: i128) -> i128 {
+    loop {
+        match (lhs, rhs) {
+            (lhs, 0) => { return lhs; },
+            _ => {
+                let temp = rhs;
+                rhs = lhs % rhs;
+                lhs = temp;
+            },
+        };
+    }
+}
+
+fn unsigned_gcd(mut lhs: u128, mut rhs: u128) -> u128 {
+    while rhs != 0 {
+        let temp = rhs;
+        rhs = lhs % rhs;
+        lhs = temp;
+    }
+    lhs
+}
+
+fn simplify(frac: (i128, i128)) -> (i128, i128) {
+    let gcd = gcd(frac.0, frac.1);
+    let num = frac.0 / gcd;
+    let den = frac.1 / gcd;
+
+    if den < 0 && num > 0 {
+        (-num, -den)
+    } else {
+        (num, den)
+    }
+}
+
+/// An exact fraction, always kept in lowest terms.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct Rat {
+    num: i128,
+    den: i128,
+}
+
+impl Rat {
+    pub fn new(num: i128, den: i128) -> Self {
+        if den == 0 {
+            panic!("denominator == 0");
+        }
+        let (num, den) = simplify((num, den));
+        Rat{num, den}
+    }
+
+    /// Like [`Rat::new`], but `None` for a zero denominator or a fraction
+    /// whose lowest terms do not fit, such as `i128::MIN / -1`.
+    pub fn checked_new(num: i128, den: i128) -> Option<Self> {
+        if den == 0 {
+            return None;
+        }
+        let negative = (num < 0) != (den < 0);
+        let (n, d) = (num.unsigned_abs(), den.unsigned_abs());
+        let g = unsigned_gcd(n, d);
+        let num = if negative { 0i128.checked_sub_unsigned(n / g)? } else { i128::try_from(n / g).ok()? };
+        Some(Rat{num, den: i128::try_from(d / g).ok()?})
+    }
+
+    /// `self + other`, or `None` if it does not fit.
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        let num = self.num.checked_mul(other.den)?.checked_add(other.num.checked_mul(self.den)?)?;
+        Rat::checked_new(num, self.den.checked_mul(other.den)?)
+    }
+
+    /// `self - other`, or `None` if it does not fit.
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        let num = self.num.checked_mul(other.den)?.checked_sub(other.num.checked_mul(self.den)?)?;
+        Rat::checked_new(num, self.den.checked_mul(other.den)?)
+    }
+
+    /// `self * other`, or `None` if it does not fit.
+    pub fn checked_mul(self, other: Self) -> Option<Self> {
+        Rat::checked_new(self.num.checked_mul(other.num)?, self.den.checked_mul(other.den)?)
+    }
+
+    /// `self / other`, or `None` if `other` is zero or it does not fit.
+    pub fn checked_div(self, other: Self) -> Option<Self> {
+        Rat::checked_new(self.num.checked_mul(other.den)?, self.den.checked_mul(other.num)?)
+    }
+
+    /// `-self`, or `None` if it does not fit.
+    pub fn checked_neg(self) -> Option<Self> {
+        Some(Rat{num: self.num.checked_neg()?, den: self.den})
+    }
+
+    pub fn num(self) -> i128 {
+        self.num
+    }
+
+    pub fn den(self) -> i128 {
+        self.den
+    }
+}
+
+// Serialised as `{"num": "1", "den": "2"}`. Both parts are decimal strings
+// so they survive JSON readers that only have 64-bit numbers.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Rat {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut s = serializer.serialize_struct("Rat", 2)?;
+        s.serialize_field("num", &self.num.to_string())?;
+        s.serialize_field("den", &self.den.to_string())?;
+        s.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Rat {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        struct Repr {
+            #[serde(with = "serde_i128")]
+            num: i128,
+            #[serde(with = "serde_i128")]
+            den: i128,
+        }
+
+        let Repr{num, den} = Repr::deserialize(deserializer)?;
+        if den == 0 {
+            return Err(serde::de::Error::custom("denominator == 0"));
+        }
+        Rat::checked_new(num, den)
+            .ok_or_else(|| serde::de::Error::custom(format!("{}/{} overflows in lowest terms", num, den)))
+    }
+}
+
+// Integers are stored as decimal strings, see `Serialize for Rat`.
+#[cfg(feature = "serde")]
+pub mod serde_i128 {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(x: &i128, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&x.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i128, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(|_| serde::de::Error::custom(format!("invalid integer {:?}", s)))
+    }
+}
+
+impl Display for Rat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.num, self.den)
+    }
+}
+
+impl From<i128> for Rat {
+    fn from(x: i128) -> Self {
+        Rat{num: x, den: 1}
+    }
+}
+
+impl From<Rat> for f64 {
+    fn from(x: Rat) -> f64 {
+        x.num as f64 / x.den as f64
+    }
+}
+
+impl PartialOrd for Rat {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Compares `a/b` with `c/d` for positive `b` and `d`. When the products
+// overflow, the integer parts are compared and then the reciprocals of the
+// remainders, as in a continued fraction.
+fn cmp_fractions(a: u128, b: u128, c: u128, d: u128) -> Ordering {
+    if let (Some(lhs), Some(rhs)) = (a.checked_mul(d), c.checked_mul(b)) {
+        return lhs.cmp(&rhs);
+    }
+    (a / b).cmp(&(c / d)).then_with(|| match (a % b, c % d) {
+        (0, 0) => Ordering::Equal,
+        (0, _) => Ordering::Less,
+        (_, 0) => Ordering::Greater,
+        (r, s) => cmp_fractions(d, s, b, r),
+    })
+}
+
+impl Ord for Rat {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let sign = |r: &Rat| r.num.signum() * r.den.signum();
+        let (lhs, rhs) = (sign(self), sign(other));
+        if lhs != rhs || lhs == 0 {
+            return lhs.cmp(&rhs);
+        }
+        let ord = cmp_fractions(self.num.unsigned_abs(), self.den.unsigned_abs(), other.num.unsigned_abs(), other.den.unsigned_abs());
+        if lhs < 0 { ord.reverse() } else { ord }
+    }
+}
+
+impl Add for Rat {
+    type Output = Self;
+    fn add(mut self, other: Self) -> Self {
+        self += other;
+        self
+    }
+}
+
+impl AddAssign for Rat {
+    fn add_assign(&mut self, other: Self) {
+        let frac = simplify((self.num * other.den + other.num * self.den, self.den * other.den));
+        self.num = frac.0;
+        self.den = frac.1;
+    }
+}
+
+impl Sub for Rat {
+    type Output = Self;
+    fn sub(mut self, other: Self) -> Self {
+        self -= other;
+        self
+    }
+}
+
+impl SubAssign for Rat {
+    fn sub_assign(&mut self, other: Self) {
+        let frac = simplify((self.num * other.den - other.num * self.den, self.den * other.den));
+        self.num = frac.0;
+        self.den = frac.1;
+    }
+}
+
+impl Mul for Rat {
+    type Output = Self;
+    fn mul(mut self, other: Self) -> Self {
+        self *= other;
+        self
+    }
+}
+
+impl MulAssign for Rat {
+    fn mul_assign(&mut self, other: Self) {
+        let frac = simplify((self.num * other.num, self.den * other.den));
+        self.num = frac.0;
+        self.den = frac.1;
+    }
+}
+
+impl Div for Rat {
+    type Output = Self;
+    fn div(mut self, other: Self) -> Self {
+        self /= other;
+        self
+    }
+}
+
+impl DivAssign for Rat {
+    fn div_assign(&mut self, other: Self) {
+        if other.num() == 0 {
+            panic!("Attempt to divide by 0");
+        }
+        let frac = simplify((self.num * other.den, self.den * other.num));
+        self.num = frac.0;
+        self.den = frac.1;
+    }
+}
+
+impl Neg for Rat {
+    type Output = Self;
+    fn neg(self) -> Rat {
+        Rat{num: -self.num, den: self.den}
+    }
+}
\ No newline at end of file
@@ -0,0 +1,338 @@
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Context, Editor, Helper};
+
+use betadog::{format, Func};
+use betadog::format::Style;
+use betadog::function::Definition;
+use betadog::lexer::lex_recovering;
+use betadog::script::{self, Event};
+use betadog::session::Session;
+
+use super::is_incomplete;
+
+const COMMANDS: &[&str] = &[
+    ":help", ":quit", ":vars", ":del", ":clear", ":tokens", ":ast", ":tree", ":trace", ":steps",
+    ":latex", ":format", ":precision", ":exact", ":approx", ":load", ":save",
+    ":rules",
+];
+
+const HELP: &str = "\
+Enter an expression to evaluate it, or define a name with x := 3 or f(x) := x^2.
+% or ans is the previous result. x = expr binds the current value of expr.
+Statements are separated by ; and there are also print a, b,
+if a < b { ... } else { ... } and for i in 1..10 { ... }.
+
+:help               show this message
+:quit               leave (or Ctrl-D)
+:vars               list the definitions
+:del x y ...        remove definitions
+:clear              remove every definition
+:tokens             toggle printing the tokens of each line
+:ast                toggle printing the parsed expression
+:tree               toggle printing the parsed expression as a tree
+:trace              toggle printing the parser's partial sums and products
+:steps              toggle printing each simplification step
+:latex              toggle printing results as LaTeX as well
+:format STYLE       write results as sexpr, infix or latex
+:precision [N]      write floats with N decimals, or as short as exact
+:exact, :approx     evaluate exactly or numerically
+:load FILE          run a script
+:save FILE          write the definitions to a file
+:rules [FILE]       list the rewrite rules, or add those in FILE";
+
+/// Reads lines from the terminal and runs them in `session` until `:quit` or
+/// Ctrl-D, writing results in `format`.
+pub fn run(session: Session, format: format::Options) {
+    let mut repl = Repl{session, views: Views::default(), format};
+
+    let mut editor: Editor<ReplHelper, DefaultHistory> = Editor::new()
+        .expect("Failed to set up the line editor");
+    editor.set_helper(Some(ReplHelper{names: Vec::new()}));
+    let history = history_path();
+    if let Some(path) = &history {
+        // There is no history on the first run.
+        let _ = editor.load_history(path);
+    }
+
+    loop {
+        let s = match editor.readline(">>> ") {
+            Ok(s) => s,
+            // Ctrl-C drops the line being edited.
+            Err(ReadlineError::Interrupted) => continue,
+            // Ctrl-D
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                eprintln!("Failed to read input: {}", err);
+                break;
+            },
+        };
+        if s.trim().is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(s.as_str());
+
+        if !repl.line(&s) {
+            break;
+        }
+        if let Some(helper) = editor.helper_mut() {
+            helper.names = repl.session.names().into_iter().map(String::from).collect();
+        }
+    }
+
+    if let Some(path) = &history {
+        if let Err(err) = editor.save_history(path) {
+            eprintln!("Failed to save history to {}: {}", path.display(), err);
+        }
+    }
+}
+
+// `~/.betadog_history`, if there is a home directory.
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".betadog_history"))
+}
+
+// What is printed besides the result of each line.
+#[derive(Default)]
+struct Views {
+    tokens: bool,
+    ast: bool,
+    tree: bool,
+    steps: bool,
+    latex: bool,
+}
+
+struct Repl {
+    session: Session,
+    views: Views,
+    format: format::Options,
+}
+
+impl Repl {
+    // Runs a line or a `:command`, returning false on `:quit`.
+    fn line(&mut self, s: &str) -> bool {
+        match s.trim().strip_prefix(':') {
+            Some(command) => return self.command(command),
+            None => self.eval(s),
+        }
+        true
+    }
+
+    fn eval(&mut self, s: &str) {
+        if self.views.tokens {
+            let (toks, _) = lex_recovering(s);
+            let toks: Vec<String> = toks.iter().map(|t| format!("{:?}", t.tok)).collect();
+            println!("Tokens: {}", toks.join(" "));
+        }
+        if self.views.ast || self.views.tree || self.views.steps {
+            if let Ok(expr) = self.session.parse(s) {
+                if self.views.ast {
+                    println!("AST: {}", expr);
+                }
+                if self.views.tree {
+                    print!("{}", format::tree(&expr));
+                }
+                if let (true, Ok((_, steps))) = (self.views.steps, self.session.trace(&expr)) {
+                    for step in steps {
+                        println!("  {}", step);
+                    }
+                }
+            }
+        }
+        let mut events = Vec::new();
+        let result = script::run(&mut self.session, s, |event| events.push(event));
+        for event in events {
+            match event {
+                Event::Value(value) => {
+                    println!("Result: {}", format::render(&value, self.format));
+                    if self.views.latex && self.format.style != Style::Latex {
+                        println!("LaTeX: {}", format::render(&value, format::Options{style: Style::Latex, ..self.format}));
+                    }
+                },
+                Event::Defined(name) => println!("{}", self.describe(&name).unwrap()),
+                Event::Print(values) => {
+                    let values: Vec<String> = values.iter().map(|value| format::render(value, self.format)).collect();
+                    println!("{}", values.join(" "));
+                },
+            }
+        }
+        if let Err(diagnostics) = result {
+            for diagnostic in diagnostics {
+                print!("{}", diagnostic.render(s));
+            }
+        }
+    }
+
+    // The binding of `name` written in the current format.
+    fn describe(&self, name: &str) -> Option<String> {
+        if let Some(e) = self.session.value(name) {
+            return Some(format!("{} := {}", name, format::render(e, self.format)));
+        }
+        match self.session.functions().get(name)? {
+            Definition::Symbolic{params, body} =>
+                Some(format!("{}({}) := {}", name, params.join(", "), format::render(body, self.format))),
+            Definition::Native(_) => self.session.describe(name),
+        }
+    }
+
+    fn command(&mut self, command: &str) -> bool {
+        let mut words = command.split_whitespace();
+        let toggle = |view: &mut bool, name: &str| {
+            *view = !*view;
+            println!("{} {}", name, if *view { "on" } else { "off" });
+        };
+        match words.next() {
+            Some("help") => println!("{}", HELP),
+            Some("quit") => return false,
+            Some("vars") => {
+                for name in self.session.names() {
+                    println!("{}", self.describe(name).unwrap());
+                }
+            },
+            Some("del") => for name in words {
+                if !self.session.remove(name) {
+                    println!("{} is not defined", name);
+                }
+            },
+            Some("clear") => self.session.clear(),
+            Some("tokens") => toggle(&mut self.views.tokens, "Tokens"),
+            Some("ast") => toggle(&mut self.views.ast, "AST"),
+            Some("tree") => toggle(&mut self.views.tree, "Tree"),
+            Some("steps") => toggle(&mut self.views.steps, "Steps"),
+            Some("latex") => toggle(&mut self.views.latex, "LaTeX"),
+            Some("trace") => {
+                let options = self.session.options_mut();
+                options.trace = match options.trace {
+                    Some(_) => None,
+                    None => Some(Rc::new(|e| println!("Trace: {}", e))),
+                };
+                println!("Trace {}", if options.trace.is_some() { "on" } else { "off" });
+            },
+            Some("format") => match words.next().map(str::parse) {
+                Some(Ok(style)) => self.format.style = style,
+                Some(Err(message)) => println!("{}", message),
+                None => println!("Usage: :format sexpr|infix|latex"),
+            },
+            Some("precision") => match words.next().map(str::parse) {
+                Some(Ok(digits)) => self.format.precision = Some(digits),
+                Some(Err(_)) => println!("Usage: :precision [digits]"),
+                None => self.format.precision = None,
+            },
+            Some("exact") => self.session.set_approx(false),
+            Some("approx") => self.session.set_approx(true),
+            Some("load") => match command["load".len()..].trim() {
+                "" => println!("Usage: :load FILE"),
+                path => self.load(path),
+            },
+            Some("save") => match command["save".len()..].trim() {
+                "" => println!("Usage: :save FILE"),
+                path => self.save(path),
+            },
+            Some("rules") => match command["rules".len()..].trim() {
+                "" => for rule in &self.session.rules().rules {
+                    println!("{}", rule);
+                },
+                path => self.load_rules(path),
+            },
+            _ => println!("Unknown command :{}; try :help", command),
+        }
+        true
+    }
+
+    // Runs a file as a script.
+    fn load(&mut self, path: &str) {
+        match fs::read_to_string(path) {
+            Ok(text) => self.eval(&text),
+            Err(err) => println!("Failed to read {}: {}", path, err),
+        }
+    }
+
+    fn load_rules(&mut self, path: &str) {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) => return println!("Failed to read {}: {}", path, err),
+        };
+        match self.session.load_rules(&text) {
+            Ok(count) => println!("Added {} rule{}", count, if count == 1 { "" } else { "s" }),
+            Err(diagnostics) => for diagnostic in diagnostics {
+                print!("{}: {}", path, diagnostic.render(&text));
+            },
+        }
+    }
+
+    // Writes the values and symbolic functions so that `:load` restores them.
+    fn save(&self, path: &str) {
+        let mut text = String::new();
+        for name in self.session.names() {
+            if let Some(e) = self.session.value(name) {
+                text.push_str(&format!("{} := {}\n", name, format::infix(e)));
+            } else if let Some(Definition::Symbolic{params, body}) = self.session.functions().get(name) {
+                text.push_str(&format!("{}({}) := {}\n", name, params.join(", "), format::infix(body)));
+            }
+        }
+        if let Err(err) = fs::write(path, text) {
+            println!("Failed to write {}: {}", path, err);
+        }
+    }
+}
+
+// Completes builtin functions, the session's names and the commands, and
+// keeps reading lines while brackets are open.
+struct ReplHelper {
+    names: Vec<String>,
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos].char_indices().rev()
+            .find(|&(_, c)| !c.is_alphanumeric() && c != ':')
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        let word = &line[start..pos];
+        let candidates: Vec<String> = if word.starts_with(':') {
+            COMMANDS.iter().filter(|c| c.starts_with(word)).map(|c| String::from(*c)).collect()
+        } else if word.is_empty() {
+            Vec::new()
+        } else {
+            let mut names: Vec<String> = Func::BUILTIN_NAMES.iter().map(|s| String::from(*s))
+                .chain(self.names.iter().cloned())
+                .filter(|name| name.starts_with(word))
+                .collect();
+            names.sort();
+            names.dedup();
+            names
+        };
+        Ok((start, candidates))
+    }
+}
+
+impl Validator for ReplHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        let input = ctx.input();
+        if input.trim_start().starts_with(':') {
+            return Ok(ValidationResult::Valid(None));
+        }
+        if is_incomplete(input) {
+            Ok(ValidationResult::Incomplete)
+        } else {
+            Ok(ValidationResult::Valid(None))
+        }
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Helper for ReplHelper {}
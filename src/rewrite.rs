@@ -0,0 +1,452 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+
+use super::default_ops;
+use super::diagnostic::Diagnostic;
+use super::eval::eval;
+use super::expr::{Const, Expr};
+use super::format::infix;
+use super::function::{map_children, substitute};
+use super::lexer::{self, Span, Tok, Token};
+use super::parser;
+use super::simplify::flatten;
+use super::visit::contains;
+
+// How many rewrites `RuleSet::apply` makes before giving up on rules that
+// undo each other, such as `a + b -> b + a`.
+const MAX_REWRITES: usize = 10_000;
+
+// Binds the terms of a sum or the factors of a product that a rule's
+// pattern leaves over. Names from the lexer cannot contain dots.
+const REST: &str = "...";
+
+/// What a guard requires of the value of a pattern variable.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Test {
+    Integer,
+    Rational,
+    /// Any finite constant.
+    Number,
+    Positive,
+    Negative,
+    NonZero,
+    Even,
+    Odd,
+}
+
+impl Test {
+    /// The names guards are written with.
+    pub const NAMES: &'static [&'static str] = &[
+        "integer", "rational", "number", "positive", "negative", "nonzero", "even", "odd",
+    ];
+
+    fn from_name(name: &str) -> Option<Test> {
+        match name {
+            "integer" => Some(Test::Integer),
+            "rational" => Some(Test::Rational),
+            "number" => Some(Test::Number),
+            "positive" => Some(Test::Positive),
+            "negative" => Some(Test::Negative),
+            "nonzero" => Some(Test::NonZero),
+            "even" => Some(Test::Even),
+            "odd" => Some(Test::Odd),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        Test::NAMES[self as usize]
+    }
+
+    /// Whether `expr` passes. Only constants pass any test.
+    pub fn passes(self, expr: &Expr) -> bool {
+        let c = match expr {
+            Expr::Const(c) => *c,
+            _ => return false,
+        };
+        let zero = Const::Int(0);
+        match (self, c) {
+            (Test::Integer, Const::Int(_)) | (Test::Rational, Const::Int(_)) | (Test::Rational, Const::Rat(_)) => true,
+            (Test::Number, Const::Float(f)) => f.is_finite(),
+            (Test::Number, Const::Int(_)) | (Test::Number, Const::Rat(_)) => true,
+            (Test::Positive, c) => c.numeric_cmp(&zero) == Some(Ordering::Greater),
+            (Test::Negative, c) => c.numeric_cmp(&zero) == Some(Ordering::Less),
+            (Test::NonZero, c) => matches!(c.numeric_cmp(&zero), Some(Ordering::Less) | Some(Ordering::Greater)),
+            (Test::Even, Const::Int(i)) => i % 2 == 0,
+            (Test::Odd, Const::Int(i)) => i % 2 != 0,
+            _ => false,
+        }
+    }
+}
+
+/// A condition on a pattern variable, written `n is integer`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Guard {
+    pub var: String,
+    pub test: Test,
+}
+
+/// A rewrite rule `lhs -> rhs if guards`.
+///
+/// Every name in `lhs` is a pattern variable, which matches any
+/// subexpression, the same one wherever it appears. Sums and products
+/// match in any order, a variable among their terms can take several of
+/// them, and a pattern for part of a sum or product matches that part: the
+/// rule `x*x -> x^2` rewrites `2*y*y` to `2*y^2`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub lhs: Expr,
+    pub rhs: Expr,
+    pub guards: Vec<Guard>,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} -> {}", infix(&self.lhs), infix(&self.rhs))?;
+        for (i, guard) in self.guards.iter().enumerate() {
+            write!(f, "{} {} is {}", if i == 0 { " if" } else { "," }, guard.var, guard.test.name())?;
+        }
+        Ok(())
+    }
+}
+
+// The values of the pattern variables bound so far.
+type Bindings = Vec<(String, Expr)>;
+
+impl Rule {
+    /// Makes a rule, evaluating `lhs` so that it has the shape of the
+    /// evaluated expressions it is meant to match.
+    pub fn new(lhs: &Expr, rhs: Expr, guards: Vec<Guard>) -> Rule {
+        Rule{lhs: eval(lhs), rhs, guards}
+    }
+
+    /// Rewrites `expr` itself, not its subexpressions, if the rule matches.
+    pub fn apply(&self, expr: &Expr) -> Option<Expr> {
+        let mut found = None;
+        let mut accept = |b: &Bindings| {
+            if !self.guards.iter().all(|g| lookup(b, &g.var).is_some_and(|value| g.test.passes(value))) {
+                return false;
+            }
+            found = Some(b.clone());
+            true
+        };
+        let partial = match (&self.lhs, expr) {
+            (Expr::Sum(ps), Expr::Sum(es)) | (Expr::Prod(ps), Expr::Prod(es)) => {
+                let ps: Vec<&Expr> = ps.iter().map(|p| &**p).collect();
+                let es: Vec<&Expr> = es.iter().map(|e| &**e).collect();
+                let join = if let Expr::Sum(_) = expr { Expr::Sum } else { Expr::Prod };
+                ps.len() < es.len() && match_terms(&order(&ps), &es, join, true, &Vec::new(), &mut accept)
+            },
+            _ => false,
+        };
+        if !partial && !match_expr(&self.lhs, expr, &Vec::new(), &mut accept) {
+            return None;
+        }
+        let bindings = found?;
+        let (names, values): (Vec<String>, Vec<Expr>) = bindings.into_iter().unzip();
+        let result = substitute(&self.rhs, &names, &values);
+        match names.iter().position(|name| name == REST) {
+            Some(i) => match (&values[i], expr) {
+                (Expr::Sum(rest), Expr::Sum(_)) => Some(Expr::Sum(std::iter::once(Box::new(result)).chain(rest.iter().cloned()).collect())),
+                (Expr::Prod(rest), Expr::Prod(_)) => Some(Expr::Prod(std::iter::once(Box::new(result)).chain(rest.iter().cloned()).collect())),
+                _ => unreachable!("the rest is joined like the expression"),
+            },
+            None => Some(result),
+        }
+    }
+}
+
+fn lookup<'a>(bindings: &'a Bindings, name: &str) -> Option<&'a Expr> {
+    bindings.iter().find(|(n, _)| n == name).map(|(_, e)| e)
+}
+
+// Tries to match `pat` against `expr` given `bindings`, calling `k` with
+// each way it matches until `k` accepts one.
+fn match_expr(pat: &Expr, expr: &Expr, bindings: &Bindings, k: &mut dyn FnMut(&Bindings) -> bool) -> bool {
+    match (pat, expr) {
+        (Expr::Var(name), _) => match lookup(bindings, name) {
+            Some(value) => value == expr && k(bindings),
+            None => {
+                let mut bindings = bindings.clone();
+                bindings.push((name.clone(), expr.clone()));
+                k(&bindings)
+            },
+        },
+        (Expr::Sum(ps), Expr::Sum(es)) | (Expr::Prod(ps), Expr::Prod(es)) => {
+            let ps: Vec<&Expr> = ps.iter().map(|p| &**p).collect();
+            let es: Vec<&Expr> = es.iter().map(|e| &**e).collect();
+            let join = if let Expr::Sum(_) = expr { Expr::Sum } else { Expr::Prod };
+            match_terms(&order(&ps), &es, join, false, bindings, k)
+        },
+        (Expr::Pow(pb, pe), Expr::Pow(eb, ee)) => match_expr(pb, eb, bindings, &mut |b| match_expr(pe, ee, b, k)),
+        (Expr::Neg(p), Expr::Neg(e)) | (Expr::Recipr(p), Expr::Recipr(e)) => match_expr(p, e, bindings, k),
+        (Expr::Call(f, ps), Expr::Call(g, es)) if f == g && ps.len() == es.len() => {
+            let ps: Vec<&Expr> = ps.iter().map(|p| &**p).collect();
+            let es: Vec<&Expr> = es.iter().map(|e| &**e).collect();
+            match_args(&ps, &es, bindings, k)
+        },
+        _ => pat == expr && k(bindings),
+    }
+}
+
+fn match_args(ps: &[&Expr], es: &[&Expr], bindings: &Bindings, k: &mut dyn FnMut(&Bindings) -> bool) -> bool {
+    match (ps.split_first(), es.split_first()) {
+        (Some((p, ps)), Some((e, es))) => match_expr(p, e, bindings, &mut |b| match_args(ps, es, b, k)),
+        _ => k(bindings),
+    }
+}
+
+// Puts the variables last, so that they take the terms the other patterns
+// leave.
+fn order<'a>(ps: &[&'a Expr]) -> Vec<&'a Expr> {
+    let (vars, mut others): (Vec<&Expr>, Vec<&Expr>) = ps.iter().partition(|p| matches!(p, Expr::Var(_)));
+    others.extend(vars);
+    others
+}
+
+// Matches the terms of a sum or factors of a product in any order. A
+// variable can take several terms, which `join` puts back together. With
+// `rest`, terms left over are bound to `REST` instead of failing the match.
+fn match_terms(
+    ps: &[&Expr],
+    es: &[&Expr],
+    join: fn(Vec<Box<Expr>>) -> Expr,
+    rest: bool,
+    bindings: &Bindings,
+    k: &mut dyn FnMut(&Bindings) -> bool,
+) -> bool {
+    let (p, ps) = match ps.split_first() {
+        Some(split) => split,
+        None if es.is_empty() => return k(bindings),
+        None if rest => {
+            let mut bindings = bindings.clone();
+            bindings.push((String::from(REST), join(es.iter().map(|e| Box::new((*e).clone())).collect())));
+            return k(&bindings);
+        },
+        None => return false,
+    };
+    if es.len() < ps.len() + 1 {
+        return false;
+    }
+    let take = |chosen: &[usize]| -> (Expr, Vec<&Expr>) {
+        let mut taken: Vec<Box<Expr>> = chosen.iter().map(|&i| Box::new(es[i].clone())).collect();
+        let left = (0..es.len()).filter(|i| !chosen.contains(i)).map(|i| es[i]).collect();
+        let value = if taken.len() == 1 { *taken.pop().unwrap() } else { join(taken) };
+        (value, left)
+    };
+    match p {
+        // The last variable takes every term left.
+        Expr::Var(_) if ps.is_empty() && !rest => {
+            let (value, _) = take(&(0..es.len()).collect::<Vec<_>>());
+            match_expr(p, &value, bindings, k)
+        },
+        // Any nonempty group of terms, leaving one for each later pattern.
+        // Groups are only tried for short sums, to bound the search.
+        Expr::Var(_) if es.len() <= 12 => {
+            for mask in 1..(1u32 << es.len()) {
+                if (mask.count_ones() as usize) > es.len() - ps.len() {
+                    continue;
+                }
+                let chosen: Vec<usize> = (0..es.len()).filter(|i| mask & (1 << i) != 0).collect();
+                let (value, left) = take(&chosen);
+                if match_expr(p, &value, bindings, &mut |b| match_terms(ps, &left, join, rest, b, k)) {
+                    return true;
+                }
+            }
+            false
+        },
+        _ => (0..es.len()).any(|i| {
+            let (value, left) = take(&[i]);
+            match_expr(p, &value, bindings, &mut |b| match_terms(ps, &left, join, rest, b, k))
+        }),
+    }
+}
+
+/// Rules applied together, in order, until none of them matches.
+///
+/// ```
+/// let rules = betadog::rewrite::parse("sin(a)^2 + cos(a)^2 -> 1").unwrap();
+/// let expr = betadog::parse_str("y + sin(2*x)^2 + cos(2*x)^2").unwrap();
+/// assert_eq!(rules.apply(&expr).unwrap().to_string(), "(+ 1 y)");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RuleSet {
+    pub rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    pub fn new() -> RuleSet {
+        RuleSet::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn extend(&mut self, other: RuleSet) {
+        self.rules.extend(other.rules);
+    }
+
+    /// Rewrites `expr` from the leaves up, trying the rules in order at each
+    /// node and rewriting the result again, until no rule applies. Fails
+    /// if that takes too many rewrites, as with rules that undo each other.
+    pub fn apply(&self, expr: &Expr) -> Result<Expr, String> {
+        let mut budget = MAX_REWRITES;
+        self.rewrite(expr, &mut budget)
+    }
+
+    fn rewrite(&self, expr: &Expr, budget: &mut usize) -> Result<Expr, String> {
+        let mut expr = self.rewrite_children(expr, budget)?;
+        while let Some(after) = self.rules.iter().find_map(|rule| rule.apply(&expr)) {
+            if *budget == 0 {
+                return Err(format!("The rules were still rewriting after {} steps", MAX_REWRITES));
+            }
+            *budget -= 1;
+            expr = self.rewrite_children(&after, budget)?;
+        }
+        Ok(expr)
+    }
+
+    fn rewrite_children(&self, expr: &Expr, budget: &mut usize) -> Result<Expr, String> {
+        let expr = map_children(expr, |e| self.rewrite(e, budget))?;
+        // A rule may have made a sum in a sum, whose terms patterns should
+        // see together.
+        Ok(flatten(&expr).unwrap_or(expr))
+    }
+}
+
+/// Reads rules written one per line, or separated by `;`:
+///
+/// ```text
+/// # Comments start with #.
+/// sin(a)^2 + cos(a)^2 -> 1
+/// (x^a)^b -> x^(a*b) if b is integer
+/// x^n / x^m -> x^(n - m) if n is integer, m is integer
+/// ```
+///
+/// Both sides are parsed with the default operators and parser options;
+/// a guard is `name is test` with a test from `Test::NAMES`. Every
+/// problem found is reported.
+pub fn parse(src: &str) -> Result<RuleSet, Vec<Diagnostic>> {
+    parse_with(src, default_ops(), parser::Options::default())
+}
+
+/// Like `parse`, with the operators and options of `parser::parse_with`.
+pub fn parse_with(src: &str, ops: HashMap<String, i8>, options: parser::Options) -> Result<RuleSet, Vec<Diagnostic>> {
+    let (toks, errors) = lexer::lex_recovering_with(src, lexer::Options{comments: true, ..lexer::Options::default()});
+    if !errors.is_empty() {
+        return Err(errors.iter().map(Diagnostic::from).collect());
+    }
+    let mut rules = RuleSet::new();
+    let mut diagnostics = Vec::new();
+    for line in lines(src, toks) {
+        match parse_rule(line, &ops, &options) {
+            Ok(rule) => rules.rules.push(rule),
+            Err(diagnostic) => diagnostics.push(diagnostic),
+        }
+    }
+    if diagnostics.is_empty() {
+        Ok(rules)
+    } else {
+        Err(diagnostics)
+    }
+}
+
+// Splits tokens at each `;` and line break outside of brackets.
+fn lines<'src>(src: &str, toks: Vec<Token<'src>>) -> Vec<Vec<Token<'src>>> {
+    let mut lines = vec![Vec::new()];
+    let mut depth = 0;
+    let mut end = None;
+    for t in toks {
+        let newline = end.is_some_and(|end| src[end..t.span.start].contains('\n'));
+        end = Some(t.span.end);
+        match t.tok {
+            Tok::Semi if depth == 0 => {
+                lines.push(Vec::new());
+                continue;
+            },
+            _ if depth == 0 && newline => lines.push(Vec::new()),
+            _ => (),
+        }
+        match t.tok {
+            Tok::LParen | Tok::LFloor | Tok::LCeil => depth += 1,
+            Tok::RParen | Tok::RFloor | Tok::RCeil => depth -= 1,
+            _ => (),
+        }
+        lines.last_mut().unwrap().push(t);
+    }
+    lines.retain(|line| !line.is_empty());
+    lines
+}
+
+fn span(toks: &[Token]) -> Span {
+    match (toks.first(), toks.last()) {
+        (Some(first), Some(last)) => first.span.to(last.span),
+        _ => Span::new(0, 0),
+    }
+}
+
+fn error(span: Span, message: &str, hint: &str) -> Diagnostic {
+    Diagnostic{span, message: String::from(message), hint: Some(String::from(hint))}
+}
+
+const RULE_HINT: &str = "rules look like lhs -> rhs, or lhs -> rhs if n is integer";
+
+fn parse_rule(mut toks: Vec<Token>, ops: &HashMap<String, i8>, options: &parser::Options) -> Result<Rule, Diagnostic> {
+    let at_depth_0 = |toks: &[Token], want: &dyn Fn(&Tok) -> bool| {
+        let mut depth = 0;
+        toks.iter().position(|t| {
+            match t.tok {
+                Tok::LParen | Tok::LFloor | Tok::LCeil => depth += 1,
+                Tok::RParen | Tok::RFloor | Tok::RCeil => depth -= 1,
+                _ => (),
+            }
+            depth == 0 && want(&t.tok)
+        })
+    };
+    let arrow = match at_depth_0(&toks, &|tok| *tok == Tok::Op("->")) {
+        Some(i) if i > 0 && i + 1 < toks.len() => i,
+        _ => return Err(error(span(&toks), "Expected a rule", RULE_HINT)),
+    };
+    let mut rhs = toks.split_off(arrow + 1);
+    toks.pop();
+    let guards = match at_depth_0(&rhs, &|tok| *tok == Tok::Iden("if")) {
+        Some(i) => {
+            let guards = rhs.split_off(i + 1);
+            let if_token = rhs.pop().unwrap();
+            Some((if_token.span, guards))
+        },
+        None => None,
+    };
+    let parse = |toks| parser::parse_with(toks, ops.clone(), options.clone()).map_err(|err| Diagnostic::from(&err));
+    let lhs = parse(toks)?;
+    let rhs = parse(rhs)?;
+    let mut tests = Vec::new();
+    if let Some((if_span, guards)) = guards {
+        for guard in guards.split(|t| t.tok == Tok::Comma) {
+            tests.push(parse_guard(guard, &lhs).map_err(|d| Diagnostic{span: if guard.is_empty() { if_span } else { d.span }, ..d})?);
+        }
+    }
+    Ok(Rule::new(&lhs, rhs, tests))
+}
+
+fn parse_guard(toks: &[Token], pattern: &Expr) -> Result<Guard, Diagnostic> {
+    match toks {
+        [Token{tok: Tok::Iden(var), span: var_span}, Token{tok: Tok::Iden(is), ..}, Token{tok: Tok::Iden(test), span}] if *is == "is" => {
+            if !contains(pattern, &Expr::var(var)) {
+                let message = format!("{} is not a variable of the pattern", var);
+                return Err(Diagnostic{span: *var_span, message, hint: None});
+            }
+            match Test::from_name(test) {
+                Some(test) => Ok(Guard{var: String::from(*var), test}),
+                None => Err(Diagnostic{
+                    span: *span,
+                    message: format!("Unknown test {}", test),
+                    hint: Some(format!("the tests are {}", Test::NAMES.join(", "))),
+                }),
+            }
+        },
+        _ => Err(error(span(toks), "Expected a guard", "guards look like n is integer, separated by commas")),
+    }
+}
@@ -0,0 +1,394 @@
+use std::cmp::Ordering;
+
+use super::diagnostic::Diagnostic;
+use super::expr::{Const, Expr};
+use super::lexer::{self, Span, Tok, Token};
+use super::session::{Binding, Outcome, Session};
+
+/// A statement of a script. Expressions are kept as tokens and parsed when
+/// the statement runs, so that they see the functions defined before them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt<'src> {
+    /// An expression or a definition, as `Session::run` takes them.
+    Line(Vec<Token<'src>>),
+    /// `x = expr`, which binds `x` to the value of `expr` now, whereas
+    /// `x := expr` keeps following the names in `expr`. This is how a loop
+    /// updates a variable: `n = n + i`.
+    Assign{name: String, span: Span, value: Vec<Token<'src>>},
+    /// `print a, b`
+    Print(Vec<Vec<Token<'src>>>),
+    /// `if a < b { ... } else { ... }`, where `else if` is an `If` on its
+    /// own in `otherwise`.
+    If{cond: Cond<'src>, then: Vec<Stmt<'src>>, otherwise: Vec<Stmt<'src>>},
+    /// `for i in 1..10 { ... }`, or `1..=10` to include the end.
+    For{var: String, start: Vec<Token<'src>>, end: Vec<Token<'src>>, inclusive: bool, body: Vec<Stmt<'src>>},
+}
+
+/// The condition of an `if`: two expressions and a comparison.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cond<'src> {
+    pub lhs: Vec<Token<'src>>,
+    pub cmp: Cmp,
+    pub rhs: Vec<Token<'src>>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Cmp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl Cmp {
+    fn from_op(op: &str) -> Option<Cmp> {
+        match op {
+            "<" => Some(Cmp::Lt),
+            "<=" => Some(Cmp::Le),
+            ">" => Some(Cmp::Gt),
+            ">=" => Some(Cmp::Ge),
+            "==" => Some(Cmp::Eq),
+            "!=" => Some(Cmp::Ne),
+            _ => None,
+        }
+    }
+}
+
+/// What running a script produces, in order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// The value of an expression statement, which is also the new `ans`.
+    Value(Expr),
+    /// The named binding was defined or replaced.
+    Defined(String),
+    /// The values of a `print` statement.
+    Print(Vec<Expr>),
+}
+
+/// Splits a program into statements.
+///
+/// Statements are separated by `;` or by the end of a line outside of any
+/// bracket, and `#` starts a comment. Besides expressions and definitions
+/// there are
+///
+/// ```text
+/// n = n + 1
+/// print a, b
+/// if a < b { ... } else if a == b { ... } else { ... }
+/// for i in 1..10 { ... }
+/// ```
+///
+/// where `print`, `if`, `else`, `for` and `in` are keywords only in those
+/// places. The comparisons are `<`, `<=`, `>`, `>=`, `==` and `!=`, and a
+/// range `a..b` excludes `b` while `a..=b` includes it.
+pub fn parse(src: &str) -> Result<Vec<Stmt<'_>>, Vec<Diagnostic>> {
+    let (toks, errors) = lexer::lex_recovering_with(src, lexer::Options{comments: true, ..lexer::Options::default()});
+    if !errors.is_empty() {
+        return Err(errors.iter().map(Diagnostic::from).collect());
+    }
+    let mut parser = Parser{src, toks, pos: 0};
+    parser.statements(None).map_err(|d| vec![d])
+}
+
+/// Parses `src` and runs it in `session`, passing what each statement
+/// produces to `emit`. Stops at the first error.
+///
+/// ```
+/// use betadog::script::{run, Event};
+/// use betadog::session::Session;
+///
+/// let mut session = Session::default();
+/// let mut printed = Vec::new();
+/// let src = "f(x) := x^2  # square\nfor i in 1..4 { if f(i) > 3 { print i, f(i) } }";
+/// run(&mut session, src, |event| if let Event::Print(v) = event { printed.push(v[1].to_string()) }).unwrap();
+/// assert_eq!(printed, vec!["4", "9"]);
+/// ```
+pub fn run<F: FnMut(Event)>(session: &mut Session, src: &str, mut emit: F) -> Result<(), Vec<Diagnostic>> {
+    exec(session, &parse(src)?, &mut emit)
+}
+
+/// Runs statements from `parse` in `session`.
+pub fn exec<F: FnMut(Event)>(session: &mut Session, stmts: &[Stmt], emit: &mut F) -> Result<(), Vec<Diagnostic>> {
+    for stmt in stmts {
+        match stmt {
+            Stmt::Line(toks) => match session.run_tokens(toks.clone())? {
+                Outcome::Value(value) => emit(Event::Value(value)),
+                Outcome::Defined(name) => emit(Event::Defined(name)),
+            },
+            Stmt::Assign{name, span, value} => {
+                let value = session.eval_tokens(value.clone())?;
+                session.define(name, Binding::Value(value)).map_err(|message| error(*span, message))?;
+                emit(Event::Defined(name.clone()));
+            },
+            Stmt::Print(args) => {
+                let values = args.iter().map(|toks| session.eval_tokens(toks.clone())).collect::<Result<_, _>>()?;
+                emit(Event::Print(values));
+            },
+            Stmt::If{cond, then, otherwise} => if test(session, cond)? {
+                exec(session, then, emit)?;
+            } else {
+                exec(session, otherwise, emit)?;
+            },
+            Stmt::For{var, start, end, inclusive, body} => {
+                let (first, last) = (integer(session, start)?, integer(session, end)?);
+                let mut i = first;
+                while i < last || (*inclusive && i == last) {
+                    session.define(var, Binding::Value(Expr::int(i))).map_err(|message| error(span(start), message))?;
+                    exec(session, body, emit)?;
+                    i += 1;
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+fn error(span: Span, message: String) -> Vec<Diagnostic> {
+    vec![Diagnostic{span, message, hint: None}]
+}
+
+fn span(toks: &[Token]) -> Span {
+    match (toks.first(), toks.last()) {
+        (Some(first), Some(last)) => first.span.to(last.span),
+        _ => Span::new(0, 0),
+    }
+}
+
+fn integer(session: &Session, toks: &[Token]) -> Result<i128, Vec<Diagnostic>> {
+    match session.eval_tokens(toks.to_vec())? {
+        Expr::Const(Const::Int(i)) => Ok(i),
+        value => Err(error(span(toks), format!("Expected an integer range bound, found {}", value))),
+    }
+}
+
+// Constants compare by value. Other expressions can only be tested for
+// being the same after evaluation, with `==` and `!=`.
+fn test(session: &Session, cond: &Cond) -> Result<bool, Vec<Diagnostic>> {
+    let lhs = session.eval_tokens(cond.lhs.clone())?;
+    let rhs = session.eval_tokens(cond.rhs.clone())?;
+    let ordering = match (&lhs, &rhs) {
+        (Expr::Const(a), Expr::Const(b)) => a.numeric_cmp(b),
+        _ if lhs == rhs => Some(Ordering::Equal),
+        _ => None,
+    };
+    let result = match (cond.cmp, ordering) {
+        (Cmp::Eq, ordering) => ordering == Some(Ordering::Equal),
+        (Cmp::Ne, ordering) => ordering != Some(Ordering::Equal),
+        (Cmp::Lt, Some(ordering)) => ordering == Ordering::Less,
+        (Cmp::Le, Some(ordering)) => ordering != Ordering::Greater,
+        (Cmp::Gt, Some(ordering)) => ordering == Ordering::Greater,
+        (Cmp::Ge, Some(ordering)) => ordering != Ordering::Less,
+        (_, None) => {
+            let span = span(&cond.lhs).to(span(&cond.rhs));
+            return Err(error(span, format!("Cannot compare {} and {}", lhs, rhs)));
+        },
+    };
+    Ok(result)
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    toks: Vec<Token<'a>>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<Tok<'a>> {
+        self.toks.get(self.pos).map(|t| t.tok)
+    }
+
+    fn keyword(&self, name: &str) -> bool {
+        matches!(self.peek(), Some(Tok::Iden(s)) if s == name)
+    }
+
+    // The span of the next token, or an empty one at the end of the input.
+    fn here(&self) -> Span {
+        match self.toks.get(self.pos) {
+            Some(t) => t.span,
+            None => Span::new(self.src.len(), self.src.len()),
+        }
+    }
+
+    fn error<T>(&self, message: &str, hint: Option<&str>) -> Result<T, Diagnostic> {
+        Err(Diagnostic{span: self.here(), message: String::from(message), hint: hint.map(String::from)})
+    }
+
+    // Whether a line ends between the previous token and the next one.
+    fn at_newline(&self) -> bool {
+        match (self.pos.checked_sub(1).and_then(|i| self.toks.get(i)), self.toks.get(self.pos)) {
+            (Some(prev), Some(next)) => self.src[prev.span.end..next.span.start].contains('\n'),
+            _ => false,
+        }
+    }
+
+    // Statements up to the end of the input, or up to and including the
+    // `}` that closes the block opened at `open`.
+    fn statements(&mut self, open: Option<Span>) -> Result<Vec<Stmt<'a>>, Diagnostic> {
+        let mut stmts = Vec::new();
+        loop {
+            while let Some(Tok::Semi) = self.peek() {
+                self.pos += 1;
+            }
+            match (self.peek(), open) {
+                (None, None) => return Ok(stmts),
+                (None, Some(open)) => return Err(Diagnostic{
+                    span: open,
+                    message: String::from("Unclosed '{'"),
+                    hint: Some(String::from("add a matching '}'")),
+                }),
+                (Some(Tok::RBrace), Some(_)) => {
+                    self.pos += 1;
+                    return Ok(stmts);
+                },
+                (Some(Tok::RBrace), None) => return self.error("Unexpected '}'", None),
+                _ => (),
+            }
+            stmts.push(self.statement()?);
+            match self.peek() {
+                None | Some(Tok::Semi) | Some(Tok::RBrace) => (),
+                _ if self.at_newline() => (),
+                _ => return self.error("Expected ';' or a new line after the statement", None),
+            }
+        }
+    }
+
+    fn statement(&mut self) -> Result<Stmt<'a>, Diagnostic> {
+        if self.keyword("print") {
+            self.pos += 1;
+            let toks = self.line()?;
+            if toks.is_empty() {
+                return Ok(Stmt::Print(Vec::new()));
+            }
+            let mut args = vec![Vec::new()];
+            let mut depth = 0;
+            for t in toks {
+                match t.tok {
+                    Tok::Comma if depth == 0 => {
+                        args.push(Vec::new());
+                        continue;
+                    },
+                    Tok::LParen | Tok::LFloor | Tok::LCeil => depth += 1,
+                    Tok::RParen | Tok::RFloor | Tok::RCeil => depth -= 1,
+                    _ => (),
+                }
+                args.last_mut().unwrap().push(t);
+            }
+            if args.iter().any(Vec::is_empty) {
+                return self.error("Expected an expression to print", Some("print takes expressions separated by commas"));
+            }
+            Ok(Stmt::Print(args))
+        } else if self.keyword("if") {
+            self.pos += 1;
+            let toks = self.until(|tok| *tok == Tok::LBrace, "Expected '{' after the condition")?;
+            let cond = self.condition(toks)?;
+            let then = self.block()?;
+            let mut otherwise = Vec::new();
+            if self.keyword("else") {
+                self.pos += 1;
+                otherwise = if self.keyword("if") { vec![self.statement()?] } else { self.block()? };
+            }
+            Ok(Stmt::If{cond, then, otherwise})
+        } else if self.keyword("for") {
+            self.pos += 1;
+            let var = match self.peek() {
+                Some(Tok::Iden(s)) => String::from(s),
+                _ => return self.error("Expected a variable after 'for'", Some("loops look like for i in 1..10 { ... }")),
+            };
+            self.pos += 1;
+            if !self.keyword("in") {
+                return self.error("Expected 'in'", Some("loops look like for i in 1..10 { ... }"));
+            }
+            self.pos += 1;
+            let start = self.until(|tok| matches!(tok, Tok::DotDot | Tok::DotDotEq), "Expected a range like 1..10")?;
+            let inclusive = self.peek() == Some(Tok::DotDotEq);
+            self.pos += 1;
+            let end = self.until(|tok| *tok == Tok::LBrace, "Expected '{' after the range")?;
+            let body = self.block()?;
+            Ok(Stmt::For{var, start, end, inclusive, body})
+        } else if let (Some(Tok::Iden(name)), Some(Tok::Op(op))) = (self.peek(), self.toks.get(self.pos + 1).map(|t| t.tok)) {
+            if op != "=" {
+                return Ok(Stmt::Line(self.line()?));
+            }
+            let (name, span) = (String::from(name), self.here());
+            self.pos += 2;
+            let value = self.line()?;
+            if value.is_empty() {
+                return self.error("Expected an expression after '='", None);
+            }
+            Ok(Stmt::Assign{name, span, value})
+        } else {
+            Ok(Stmt::Line(self.line()?))
+        }
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt<'a>>, Diagnostic> {
+        if self.peek() != Some(Tok::LBrace) {
+            return self.error("Expected '{'", None);
+        }
+        let open = self.here();
+        self.pos += 1;
+        self.statements(Some(open))
+    }
+
+    // The tokens up to the end of the statement: a `;`, a `}` or a new line
+    // outside of brackets.
+    fn line(&mut self) -> Result<Vec<Token<'a>>, Diagnostic> {
+        let start = self.pos;
+        let mut depth = 0;
+        while let Some(tok) = self.peek() {
+            match tok {
+                Tok::Semi | Tok::RBrace if depth == 0 => break,
+                _ if depth == 0 && self.pos > start && self.at_newline() => break,
+                Tok::LBrace | Tok::DotDot | Tok::DotDotEq => return self.error("Unexpected token", None),
+                Tok::LParen | Tok::LFloor | Tok::LCeil => depth += 1,
+                Tok::RParen | Tok::RFloor | Tok::RCeil => depth -= 1,
+                _ => (),
+            }
+            self.pos += 1;
+        }
+        Ok(self.toks[start..self.pos].to_vec())
+    }
+
+    // The tokens up to a token outside of brackets for which `stop` is
+    // true, which is not consumed. Fails with `message` at the end of the
+    // statement or if there are no tokens before it.
+    fn until<P: Fn(&Tok) -> bool>(&mut self, stop: P, message: &str) -> Result<Vec<Token<'a>>, Diagnostic> {
+        let start = self.pos;
+        let mut depth = 0;
+        loop {
+            match self.peek() {
+                Some(tok) if depth == 0 && stop(&tok) => break,
+                None | Some(Tok::Semi) | Some(Tok::LBrace) | Some(Tok::RBrace) => return self.error(message, None),
+                Some(Tok::LParen) | Some(Tok::LFloor) | Some(Tok::LCeil) => depth += 1,
+                Some(Tok::RParen) | Some(Tok::RFloor) | Some(Tok::RCeil) => depth -= 1,
+                _ => (),
+            }
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return self.error("Expected an expression", None);
+        }
+        Ok(self.toks[start..self.pos].to_vec())
+    }
+
+    fn condition(&self, mut toks: Vec<Token<'a>>) -> Result<Cond<'a>, Diagnostic> {
+        let cmp = |t: &Token| match &t.tok {
+            Tok::Op(op) => Cmp::from_op(op),
+            _ => None,
+        };
+        let positions: Vec<usize> = (0..toks.len()).filter(|&i| cmp(&toks[i]).is_some()).collect();
+        let i = match positions[..] {
+            [i] if i > 0 && i + 1 < toks.len() => i,
+            _ => return Err(Diagnostic{
+                span: span(&toks),
+                message: String::from("Expected a comparison"),
+                hint: Some(String::from("conditions look like a < b, using < <= > >= == or !=")),
+            }),
+        };
+        let rhs = toks.split_off(i + 1);
+        let op = toks.pop().unwrap();
+        Ok(Cond{lhs: toks, cmp: cmp(&op).unwrap(), rhs})
+    }
+}
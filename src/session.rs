@@ -0,0 +1,427 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use super::diagnostic::Diagnostic;
+use super::eval::approx_with;
+use super::expr::{Expr, Func};
+use super::function::{map_children, substitute, Definition, Registry};
+use super::lexer::{self, Span, Tok, Token};
+use super::parser;
+use super::rewrite::{self, RuleSet};
+use super::simplify::{simplify_with, trace_with, Step};
+use super::visit::children;
+
+/// A definition made with `Session::define`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Binding {
+    /// `x := 3`
+    Value(Expr),
+    /// `f(x, y) := x*y + 1`
+    Function{params: Vec<String>, body: Expr},
+}
+
+/// The result of `Session::run`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// An expression was evaluated; it is also the new `ans`.
+    Value(Expr),
+    /// The named binding was defined or replaced.
+    Defined(String),
+}
+
+/// The state kept between the lines of a REPL: names defined with `:=`, the
+/// user functions, and the previous result, which is written `ans` or `%`.
+///
+/// Definitions are stored as written and substituted when a line is
+/// evaluated, so `y := x + 1` follows later changes to `x`. A definition
+/// that would refer back to itself, directly or through other bindings, is
+/// rejected. Native functions can be added through `functions_mut`, and
+/// rewrite rules applied to every result through `load_rules`.
+///
+/// ```
+/// use betadog::session::{Outcome, Session};
+///
+/// let mut session = Session::default();
+/// session.run("f(x) := x^2 + 1").unwrap();
+/// session.run("a := 3").unwrap();
+/// assert_eq!(session.run("f(a) * 2"), Ok(Outcome::Value(betadog::Expr::int(20))));
+/// assert_eq!(session.run("% + 1"), Ok(Outcome::Value(betadog::Expr::int(21))));
+/// assert!(session.run("a := f(a)").is_err());
+/// ```
+pub struct Session {
+    values: BTreeMap<String, Expr>,
+    functions: Registry,
+    ans: Option<Expr>,
+    ops: HashMap<String, i8>,
+    options: parser::Options,
+    approx: bool,
+    rules: RuleSet,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session::new(super::default_ops(), parser::Options::default())
+    }
+}
+
+impl Session {
+    /// A session that parses lines with the binary operators `ops` and the
+    /// grammar extensions in `options`.
+    pub fn new(ops: HashMap<String, i8>, options: parser::Options) -> Session {
+        Session{values: BTreeMap::new(), functions: Registry::new(), ans: None, ops, options, approx: false, rules: RuleSet::new()}
+    }
+
+    /// The grammar extensions lines are parsed with.
+    pub fn options_mut(&mut self) -> &mut parser::Options {
+        &mut self.options
+    }
+
+    /// Whether `run` evaluates numerically with `eval::approx` rather than
+    /// exactly.
+    pub fn approx(&self) -> bool {
+        self.approx
+    }
+
+    pub fn set_approx(&mut self, approx: bool) {
+        self.approx = approx;
+    }
+
+    /// The result of the last evaluated line.
+    pub fn ans(&self) -> Option<&Expr> {
+        self.ans.as_ref()
+    }
+
+    pub fn value(&self, name: &str) -> Option<&Expr> {
+        self.values.get(name)
+    }
+
+    pub fn functions(&self) -> &Registry {
+        &self.functions
+    }
+
+    pub fn functions_mut(&mut self) -> &mut Registry {
+        &mut self.functions
+    }
+
+    pub fn rules(&self) -> &RuleSet {
+        &self.rules
+    }
+
+    pub fn rules_mut(&mut self) -> &mut RuleSet {
+        &mut self.rules
+    }
+
+    /// Adds the rules in `src`, written as `rewrite::parse` reads them and
+    /// parsed like lines of the session, returning how many there were.
+    pub fn load_rules(&mut self, src: &str) -> Result<usize, Vec<Diagnostic>> {
+        let rules = rewrite::parse_with(src, self.ops.clone(), self.options.clone())?;
+        let count = rules.rules.len();
+        self.rules.extend(rules);
+        Ok(count)
+    }
+
+    /// The names of every value and function, in order.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.values.keys().map(|name| &name[..]).chain(self.functions.names()).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Removes the value or function `name`, returning whether it existed.
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.values.remove(name).is_some() || self.functions.remove(name).is_some()
+    }
+
+    /// Removes every value and symbolic function and forgets `ans`. Native
+    /// functions are kept.
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.functions.clear_symbolic();
+        self.ans = None;
+    }
+
+    /// The binding of `name` written as a definition, e.g. `f(x) := (+ x 1)`.
+    pub fn describe(&self, name: &str) -> Option<String> {
+        if let Some(e) = self.values.get(name) {
+            return Some(format!("{} := {}", name, e));
+        }
+        match self.functions.get(name)? {
+            Definition::Symbolic{params, body} => Some(format!("{}({}) := {}", name, params.join(", "), body)),
+            Definition::Native(f) => Some(format!("{} is a native function of {} argument{}",
+                name, f.arity(), if f.arity() == 1 { "" } else { "s" })),
+        }
+    }
+
+    /// Binds `name`, replacing any previous value or function. Fails with a
+    /// message if `name` is reserved or the definition is recursive.
+    pub fn define(&mut self, name: &str, binding: Binding) -> Result<(), String> {
+        if name == "ans" {
+            return Err(String::from("ans is the previous result and cannot be defined"));
+        }
+        if Func::from_name(name).is_builtin() {
+            return Err(format!("{} is a builtin function and cannot be defined", name));
+        }
+        let path = match &binding {
+            Binding::Value(e) => self.path_to(e, &[], name, &mut HashSet::new()),
+            Binding::Function{params, body} => self.path_to(body, params, name, &mut HashSet::new()),
+        };
+        if let Some(path) = path {
+            return Err(format!("Recursive definition of {}: {} → {}", name, name, path.join(" → ")));
+        }
+        self.remove(name);
+        match binding {
+            Binding::Value(e) => {
+                self.values.insert(String::from(name), e);
+                Ok(())
+            },
+            Binding::Function{params, body} => self.functions.define(name, params, body),
+        }
+    }
+
+    /// Substitutes every bound name in `expr` and expands the symbolic
+    /// functions. Fails with a message if a function is called with the
+    /// wrong number of arguments.
+    pub fn resolve(&self, expr: &Expr) -> Result<Expr, String> {
+        match expr {
+            Expr::Var(s) if s == "ans" => Ok(self.ans.clone().unwrap_or_else(|| expr.clone())),
+            Expr::Var(s) => match self.values.get(s) {
+                Some(e) => self.resolve(e),
+                None => Ok(expr.clone()),
+            },
+            Expr::Call(Func::Func(name), args) => {
+                let args = args.iter().map(|e| self.resolve(e)).collect::<Result<Vec<_>, _>>()?;
+                if self.values.contains_key(name) {
+                    return Err(format!("{} is not a function", name));
+                }
+                match self.functions.get(name) {
+                    Some(def) if def.arity() != args.len() => Err(format!("{} takes {} argument{} but {} were given",
+                        name, def.arity(), if def.arity() == 1 { "" } else { "s" }, args.len())),
+                    Some(def) => match def.expand(&args) {
+                        Some(body) => self.resolve(&body),
+                        None => Ok(Expr::call(Func::Func(name.clone()), args)),
+                    },
+                    None => Ok(Expr::call(Func::Func(name.clone()), args)),
+                }
+            },
+            e => map_children(e, |e| self.resolve(e)),
+        }
+    }
+
+    /// Evaluates a line, or defines a name if it has the form `x := ...` or
+    /// `f(x, ...) := ...`. A `%` where an operand is expected, as in `% * 2`,
+    /// is the previous result; after an operand it is still a percentage.
+    pub fn run(&mut self, src: &str) -> Result<Outcome, Vec<Diagnostic>> {
+        let (toks, lex_errors) = lexer::lex_recovering(src);
+        let diagnostics = lex_errors.iter().map(Diagnostic::from).collect();
+        self.run_parsed(self.parse_tokens(toks, diagnostics)?)
+    }
+
+    /// Like `run` for a line that is already lexed, such as a statement of
+    /// a `script`. Lexer errors must be reported by the caller.
+    pub fn run_tokens(&mut self, toks: Vec<Token>) -> Result<Outcome, Vec<Diagnostic>> {
+        self.run_parsed(self.parse_tokens(toks, Vec::new())?)
+    }
+
+    /// Parses and evaluates an expression that is already lexed, like
+    /// `run_tokens` but without defining anything or changing `ans`.
+    pub fn eval_tokens(&self, toks: Vec<Token>) -> Result<Expr, Vec<Diagnostic>> {
+        let line = self.parse_tokens(toks, Vec::new())?;
+        if let Some(target) = line.target {
+            let message = String::from("Expected an expression, not a definition");
+            return Err(vec![Diagnostic{span: target.span, message, hint: None}]);
+        }
+        self.evaluate(&line.expr).map_err(|message| vec![Diagnostic{span: line.span, message, hint: None}])
+    }
+
+    fn run_parsed(&mut self, line: Line) -> Result<Outcome, Vec<Diagnostic>> {
+        let error = |message| vec![Diagnostic{span: line.span, message, hint: None}];
+        match line.target {
+            Some(Target{name, params, span}) => {
+                // `ans` is fixed when the definition is made.
+                let body = match &self.ans {
+                    Some(ans) => substitute(&line.expr, &[String::from("ans")], std::slice::from_ref(ans)),
+                    None => line.expr,
+                };
+                let binding = match params {
+                    Some(params) => Binding::Function{params, body},
+                    None => Binding::Value(body),
+                };
+                self.define(&name, binding).map_err(|message| vec![Diagnostic{span, message, hint: None}])?;
+                Ok(Outcome::Defined(name))
+            },
+            None => {
+                let value = self.evaluate(&line.expr).map_err(error)?;
+                self.ans = Some(value.clone());
+                Ok(Outcome::Value(value))
+            },
+        }
+    }
+
+    /// Resolves the names in `expr` and simplifies it, exactly or after
+    /// `eval::approx` as set by `set_approx`, then applies the rules and
+    /// simplifies what they make. Unlike `run` this leaves `ans` alone.
+    pub fn evaluate(&self, expr: &Expr) -> Result<Expr, String> {
+        let value = simplify_with(&self.prepare(expr)?, &self.functions);
+        if self.rules.is_empty() {
+            return Ok(value);
+        }
+        Ok(simplify_with(&self.rules.apply(&value)?, &self.functions))
+    }
+
+    /// Like `evaluate`, also returning the steps `simplify::trace_with` took.
+    /// The rules count as one step.
+    pub fn trace(&self, expr: &Expr) -> Result<(Expr, Vec<Step>), String> {
+        let (value, mut steps) = trace_with(&self.prepare(expr)?, &self.functions);
+        let rewritten = self.rules.apply(&value)?;
+        if rewritten == value {
+            return Ok((value, steps));
+        }
+        steps.push(Step{rule: "apply rules", before: value, after: rewritten.clone()});
+        let (value, more) = trace_with(&rewritten, &self.functions);
+        steps.extend(more);
+        Ok((value, steps))
+    }
+
+    // `expr` with its names resolved, as floats in approximate mode.
+    fn prepare(&self, expr: &Expr) -> Result<Expr, String> {
+        let value = self.resolve(expr)?;
+        Ok(if self.approx { approx_with(&value, &self.functions) } else { value })
+    }
+
+    /// Parses a line as `run` would without evaluating it. For a definition
+    /// this is the expression after `:=`.
+    pub fn parse(&self, src: &str) -> Result<Expr, Vec<Diagnostic>> {
+        let (toks, lex_errors) = lexer::lex_recovering(src);
+        let diagnostics = lex_errors.iter().map(Diagnostic::from).collect();
+        self.parse_tokens(toks, diagnostics).map(|line| line.expr)
+    }
+
+    fn parse_tokens(&self, mut toks: Vec<Token>, mut diagnostics: Vec<Diagnostic>) -> Result<Line, Vec<Diagnostic>> {
+        let span = match (toks.first(), toks.last()) {
+            (Some(first), Some(last)) => first.span.to(last.span),
+            _ => Span::new(0, 0),
+        };
+        previous_result(&mut toks);
+
+        let mut options = self.options.clone();
+        options.functions.extend(self.functions.names().map(String::from));
+
+        let assign = toks.iter().position(|t| t.tok == Tok::Op(":="));
+        let (target, toks) = match assign {
+            Some(i) => {
+                let rhs = toks.split_off(i + 1);
+                let op = toks.pop().unwrap();
+                if rhs.is_empty() {
+                    let span = Span::new(op.span.end, op.span.end);
+                    let message = String::from("Expected an expression after ':='");
+                    diagnostics.push(Diagnostic{span, message, hint: None});
+                    return Err(diagnostics);
+                }
+                let target = match definition_target(&toks) {
+                    Ok(target) => Some(target),
+                    Err(message) => {
+                        let span = toks.first().map_or(op.span, |t| t.span.to(op.span));
+                        let hint = Some(String::from("definitions look like x := 3 or f(x, y) := x*y"));
+                        diagnostics.push(Diagnostic{span, message, hint});
+                        None
+                    },
+                };
+                if let Some(Target{name, params: Some(_), ..}) = &target {
+                    options.functions.push(name.clone());
+                }
+                (target, rhs)
+            },
+            None => (None, toks),
+        };
+
+        let (expr, parse_errors) = parser::parse_recovering_with(toks, self.ops.clone(), options);
+        diagnostics.extend(parse_errors.iter().map(Diagnostic::from));
+        if !diagnostics.is_empty() {
+            diagnostics.sort_by_key(|d| d.span.start);
+            return Err(diagnostics);
+        }
+        Ok(Line{target, expr, span})
+    }
+
+    // The chain of names through which `expr` refers to `target`, if any.
+    // `params` are the parameters of the function `expr` is the body of.
+    fn path_to(&self, expr: &Expr, params: &[String], target: &str, visited: &mut HashSet<String>) -> Option<Vec<String>> {
+        let mut names = Vec::new();
+        free_names(expr, params, &mut names);
+        for name in names {
+            if name == target {
+                return Some(vec![name]);
+            }
+            if !visited.insert(name.clone()) {
+                continue;
+            }
+            let path = match (self.values.get(&name), self.functions.get(&name)) {
+                (Some(e), _) => self.path_to(e, &[], target, visited),
+                (_, Some(Definition::Symbolic{params, body})) => self.path_to(body, params, target, visited),
+                _ => None,
+            };
+            if let Some(mut path) = path {
+                path.insert(0, name);
+                return Some(path);
+            }
+        }
+        None
+    }
+}
+
+// A parsed line: an expression, or a definition of `target`.
+struct Line {
+    target: Option<Target>,
+    expr: Expr,
+    span: Span,
+}
+
+// The left hand side of `:=`.
+struct Target {
+    name: String,
+    params: Option<Vec<String>>,
+    span: Span,
+}
+
+fn definition_target(toks: &[Token]) -> Result<Target, String> {
+    let expected = || String::from("Expected a name or a function like f(x) before ':='");
+    let (name, span, params) = match toks {
+        [Token{tok: Tok::Iden(name), span}] => return Ok(Target{name: String::from(*name), params: None, span: *span}),
+        [Token{tok: Tok::Iden(name), span}, Token{tok: Tok::LParen, ..}, params @ .., Token{tok: Tok::RParen, ..}] =>
+            (name, span, params),
+        _ => return Err(expected()),
+    };
+    let mut names: Vec<String> = Vec::new();
+    for (i, t) in params.iter().enumerate() {
+        match (&t.tok, i % 2) {
+            (Tok::Iden(p), 0) if names.iter().any(|n| n == p) => return Err(format!("Parameter {} appears twice", p)),
+            (Tok::Iden(p), 0) => names.push(String::from(*p)),
+            (Tok::Comma, 1) if i + 1 < params.len() => (),
+            _ => return Err(expected()),
+        }
+    }
+    Ok(Target{name: String::from(*name), params: Some(names), span: *span})
+}
+
+fn previous_result(toks: &mut [Token]) {
+    for i in 0..toks.len() {
+        let operand_expected = match i.checked_sub(1).map(|j| &toks[j].tok) {
+            None => true,
+            Some(Tok::Op(op)) => !matches!(&op[..], "!" | "!!" | "%"),
+            Some(Tok::LParen) | Some(Tok::Comma) | Some(Tok::LFloor) | Some(Tok::LCeil) => true,
+            _ => false,
+        };
+        if operand_expected && toks[i].tok == Tok::Op("%") {
+            toks[i].tok = Tok::Iden("ans");
+        }
+    }
+}
+
+// The variables other than `params` and the user functions in `expr`.
+fn free_names(expr: &Expr, params: &[String], names: &mut Vec<String>) {
+    match expr {
+        Expr::Var(s) if !params.contains(s) && !names.contains(s) => names.push(s.clone()),
+        Expr::Call(Func::Func(s), _) if !names.contains(s) => names.push(s.clone()),
+        _ => (),
+    }
+    children(expr).into_iter().for_each(|e| free_names(e, params, names));
+}
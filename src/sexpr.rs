@@ -0,0 +1,224 @@
+use std::fmt;
+use super::expr::{Const, Expr, Func};
+use super::rat;
+
+// Reads back the s-expression syntax produced by `Display for Expr`, e.g.
+// `(+ a (* 2 b))`, `(/ 1 x)` for reciprocals and `(Sin x)` for calls.
+
+#[derive(Debug, PartialEq)]
+pub struct Error {
+    position: usize,
+    message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for Error {}
+
+struct Reader<'a> {
+    src: &'a str,
+    index: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn error<T>(&self, message: &str) -> Result<T, Error> {
+        Err(Error{
+            message: String::from(message),
+            position: self.index,
+        })
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.src[self.index..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.index += c.len_utf8();
+        }
+    }
+
+    fn is_done(&mut self) -> bool {
+        self.skip_whitespace();
+        self.peek().is_none()
+    }
+
+    fn read_expr(&mut self) -> Result<Expr, Error> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('(') => self.read_list(),
+            Some(')') => self.error("Unexpected ')'"),
+            Some(_) => {
+                let start = self.index;
+                let s = self.atom();
+                match read_atom(s) {
+                    Some(expr) => Ok(expr),
+                    None => Err(Error{
+                        message: format!("Invalid atom {}", s),
+                        position: start,
+                    }),
+                }
+            },
+            None => self.error("Unexpected end of input"),
+        }
+    }
+
+    fn read_list(&mut self) -> Result<Expr, Error> {
+        self.index += 1; // Eats (
+        self.skip_whitespace();
+        let start = self.index;
+        let head = self.atom();
+        if head.is_empty() {
+            return self.error("Expected an operator or function name");
+        }
+
+        let mut args = Vec::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some(')') => {
+                    self.index += 1; // Eats )
+                    break;
+                },
+                Some(_) => args.push(Box::new(self.read_expr()?)),
+                None => return self.error("Expected ')'"),
+            }
+        }
+
+        let arity = |n: usize| if args.len() == n {
+            Ok(())
+        } else {
+            Err(Error{
+                message: format!("{} expects {} arguments, found {}", head, n, args.len()),
+                position: start,
+            })
+        };
+
+        match head {
+            "+" => Ok(Expr::Sum(args)),
+            "*" => Ok(Expr::Prod(args)),
+            "^" => {
+                arity(2)?;
+                let rhs = args.pop().unwrap();
+                let lhs = args.pop().unwrap();
+                Ok(Expr::Pow(lhs, rhs))
+            },
+            "-" => {
+                arity(1)?;
+                Ok(Expr::Neg(args.pop().unwrap()))
+            },
+            "/" => {
+                arity(2)?;
+                if *args[0] != Expr::Const(Const::Int(1)) {
+                    return Err(Error{
+                        message: String::from("/ must have the form (/ 1 x)"),
+                        position: start,
+                    });
+                }
+                Ok(Expr::Recipr(args.pop().unwrap()))
+            },
+            s if is_name(s) => Ok(Expr::Call(func(s), args)),
+            s => Err(Error{
+                message: format!("Unknown operator {}", s),
+                position: start,
+            }),
+        }
+    }
+
+    fn atom(&mut self) -> &'a str {
+        let start = self.index;
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || c == '(' || c == ')' {
+                break;
+            }
+            self.index += c.len_utf8();
+        }
+        &self.src[start..self.index]
+    }
+}
+
+fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) => c.is_alphabetic() && chars.all(|c| c.is_alphanumeric()),
+        None => false,
+    }
+}
+
+fn func(name: &str) -> Func {
+    match name {
+        "Sin" => Func::Sin,
+        "Cos" => Func::Cos,
+        "Tan" => Func::Tan,
+        "ASin" => Func::ASin,
+        "ACos" => Func::ACos,
+        "ATan" => Func::ATan,
+        "Log" => Func::Log,
+        "Sqrt" => Func::Sqrt,
+        "Cbrt" => Func::Cbrt,
+        "Root" => Func::Root,
+        "Factorial" => Func::Factorial,
+        "DoubleFactorial" => Func::DoubleFactorial,
+        "Gamma" => Func::Gamma,
+        "Abs" => Func::Abs,
+        "Floor" => Func::Floor,
+        "Ceil" => Func::Ceil,
+        "Round" => Func::Round,
+        "Sign" => Func::Sign,
+        "Frac" => Func::Frac,
+        "Mod" => Func::Mod,
+        "Diff" => Func::Diff,
+        s => Func::Func(String::from(s)),
+    }
+}
+
+fn read_atom(s: &str) -> Option<Expr> {
+    match s {
+        "inf" => return Some(Expr::Const(Const::Inf)),
+        "-inf" => return Some(Expr::Const(Const::NegInf)),
+        "undef" => return Some(Expr::Const(Const::Undef)),
+        "NaN" => return Some(Expr::Const(Const::Float(f64::NAN))),
+        "?" => return Some(Expr::Error),
+        s if is_name(s) => return Some(Expr::Var(String::from(s))),
+        _ => (),
+    }
+
+    if let Ok(i) = s.parse::<i128>() {
+        return Some(Expr::Const(Const::Int(i)));
+    }
+
+    if let Some(slash) = s.find('/') {
+        let num = s[..slash].parse::<i128>().ok()?;
+        let den = s[slash + 1..].parse::<i128>().ok()?;
+        // `None` for a zero denominator or lowest terms that overflow.
+        return rat::Rat::checked_new(num, den).map(|r| Expr::Const(Const::Rat(r)));
+    }
+
+    // Rust's float parser also accepts spellings like "infinity", which
+    // are not something `Display` ever produces.
+    if s.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.') {
+        if let Ok(f) = s.parse::<f64>() {
+            if f.is_finite() {
+                return Some(Expr::Const(Const::Float(f)));
+            }
+        }
+    }
+    None
+}
+
+/// Reads an expression in the syntax printed by `Display for Expr`.
+pub fn read(s: &str) -> Result<Expr, Error> {
+    let mut reader = Reader{src: s, index: 0};
+    let expr = reader.read_expr()?;
+    if !reader.is_done() {
+        return reader.error("Unexpected input after expression");
+    }
+    Ok(expr)
+}
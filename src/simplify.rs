@@ -0,0 +1,446 @@
+use std::convert::Infallible;
+use std::fmt;
+
+use super::eval::{add_const, eval_with, mul_const, negate_const};
+use super::expr::{Const, Expr};
+use super::format::infix;
+use super::function::{map_children, Registry};
+
+/// One rewrite made by `simplify_with_trace`: the rule, and the
+/// subexpression before and after it was applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub rule: &'static str,
+    pub before: Expr,
+    pub after: Expr,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} -> {}", self.rule, infix(&self.before), infix(&self.after))
+    }
+}
+
+type Rule = fn(&Expr) -> Option<Expr>;
+
+// Tried in order at each node once its children are simplified. Every rule
+// makes the expression smaller or folds constants, so rewriting stops.
+const RULES: &[(&str, Rule)] = &[
+    ("flatten", flatten),
+    ("double negation", double_negation),
+    ("negate constant", negate_constant),
+    ("add zero", add_zero),
+    ("multiply by zero", multiply_by_zero),
+    ("multiply by one", multiply_by_one),
+    ("add constants", add_constants),
+    ("multiply constants", multiply_constants),
+    ("combine like terms", like_terms),
+    ("combine powers", combine_powers),
+    ("power of a power", power_of_power),
+    ("power of one", power_of_one),
+    ("power of zero", power_of_zero),
+    ("reorder terms", reorder),
+];
+
+/// Evaluates `expr` like `eval` and also collects like terms and powers of
+/// the same base, so `2*x + 3*x` is `5*x` and `x*x^2` is `x^3`. Factors and
+/// terms are sorted in the canonical order of `Expr`, with the constant
+/// term last.
+pub fn simplify(expr: &Expr) -> Expr {
+    simplify_with(expr, &Registry::new())
+}
+
+/// Like `simplify`, with the user functions in `functions` as `eval_with`
+/// uses them.
+pub fn simplify_with(expr: &Expr, functions: &Registry) -> Expr {
+    Simplifier{functions, steps: None}.simplify(expr)
+}
+
+/// Like `simplify`, also returning every rewrite in the order it was made.
+///
+/// ```
+/// let expr = betadog::parse_str("2*x + 3*x").unwrap();
+/// let (result, steps) = betadog::simplify::simplify_with_trace(&expr);
+/// assert_eq!(result.to_string(), "(* 5 x)");
+/// assert_eq!(steps[0].to_string(), "combine like terms: 2*x + 3*x -> 5*x");
+/// ```
+pub fn simplify_with_trace(expr: &Expr) -> (Expr, Vec<Step>) {
+    trace_with(expr, &Registry::new())
+}
+
+/// Like `simplify_with_trace`, with the user functions in `functions`.
+pub fn trace_with(expr: &Expr, functions: &Registry) -> (Expr, Vec<Step>) {
+    let mut simplifier = Simplifier{functions, steps: Some(Vec::new())};
+    let result = simplifier.simplify(expr);
+    (result, simplifier.steps.unwrap_or_default())
+}
+
+struct Simplifier<'a> {
+    functions: &'a Registry,
+    // `None` when no trace is wanted.
+    steps: Option<Vec<Step>>,
+}
+
+impl Simplifier<'_> {
+    fn simplify(&mut self, expr: &Expr) -> Expr {
+        let expr = match map_children(expr, |e| Ok::<_, Infallible>(self.simplify(e))) {
+            Ok(e) => e,
+            Err(never) => match never {},
+        };
+        match self.rewrite(&expr) {
+            // The rewritten parts may simplify further.
+            Some((rule, after)) => {
+                if let Some(steps) = &mut self.steps {
+                    steps.push(Step{rule, before: expr, after: after.clone()});
+                }
+                self.simplify(&after)
+            },
+            None => expr,
+        }
+    }
+
+    fn rewrite(&self, expr: &Expr) -> Option<(&'static str, Expr)> {
+        for (name, rule) in RULES {
+            if let Some(after) = rule(expr) {
+                return Some((name, after));
+            }
+        }
+        // Whatever `eval` does beyond the rules, such as computing
+        // functions of constants.
+        let after = eval_with(expr, self.functions);
+        if after != *expr {
+            return Some(("evaluate", after));
+        }
+        None
+    }
+}
+
+pub(crate) fn flatten(expr: &Expr) -> Option<Expr> {
+    let v = match expr {
+        Expr::Sum(v) | Expr::Prod(v) => v,
+        _ => return None,
+    };
+    // The terms of a sum in a sum, or the factors of a product in a product.
+    let nested = |e: &Expr| match (expr, e) {
+        (Expr::Sum(_), Expr::Sum(inner)) | (Expr::Prod(_), Expr::Prod(inner)) => Some(inner.clone()),
+        _ => None,
+    };
+    match &v[..] {
+        [] => return Some(Expr::int(if let Expr::Sum(_) = expr { 0 } else { 1 })),
+        [e] => return Some((**e).clone()),
+        _ => (),
+    }
+    if !v.iter().any(|e| nested(e).is_some()) {
+        return None;
+    }
+    let flat = v.iter().flat_map(|e| nested(e).unwrap_or_else(|| vec![e.clone()])).collect();
+    Some(if let Expr::Sum(_) = expr { Expr::Sum(flat) } else { Expr::Prod(flat) })
+}
+
+// A sum or product of `v`, or its only element, so that rules do not leave
+// work for `flatten`.
+fn sum(mut v: Vec<Box<Expr>>) -> Expr {
+    match v.len() {
+        0 => Expr::int(0),
+        1 => *v.pop().unwrap(),
+        _ => Expr::Sum(v),
+    }
+}
+
+fn product(mut v: Vec<Box<Expr>>) -> Expr {
+    match v.len() {
+        0 => Expr::int(1),
+        1 => *v.pop().unwrap(),
+        _ => Expr::Prod(v),
+    }
+}
+
+fn double_negation(expr: &Expr) -> Option<Expr> {
+    match expr {
+        Expr::Neg(e) => match &**e {
+            Expr::Neg(e) => Some((**e).clone()),
+            _ => None,
+        },
+        Expr::Recipr(e) => match &**e {
+            Expr::Recipr(e) => Some((**e).clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn negate_constant(expr: &Expr) -> Option<Expr> {
+    match expr {
+        Expr::Neg(e) => match **e {
+            Expr::Const(c) => Some(Expr::Const(negate_const(c))),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn without(v: &[Box<Expr>], unwanted: Const) -> Option<Vec<Box<Expr>>> {
+    let kept: Vec<Box<Expr>> = v.iter().filter(|e| ***e != Expr::Const(unwanted)).cloned().collect();
+    if kept.len() == v.len() {
+        None
+    } else {
+        Some(kept)
+    }
+}
+
+fn add_zero(expr: &Expr) -> Option<Expr> {
+    match expr {
+        Expr::Sum(v) => without(v, Const::Int(0)).map(sum),
+        _ => None,
+    }
+}
+
+fn multiply_by_one(expr: &Expr) -> Option<Expr> {
+    match expr {
+        Expr::Prod(v) => without(v, Const::Int(1)).map(product),
+        _ => None,
+    }
+}
+
+// Only when no other factor is infinite or undefined, which `eval` settles.
+fn multiply_by_zero(expr: &Expr) -> Option<Expr> {
+    let v = match expr {
+        Expr::Prod(v) => v,
+        _ => return None,
+    };
+    let zero = v.iter().any(|e| **e == Expr::int(0));
+    let safe = v.iter().all(|e| !matches!(**e, Expr::Const(Const::Inf) | Expr::Const(Const::NegInf) | Expr::Const(Const::Undef)));
+    if zero && safe {
+        Some(Expr::int(0))
+    } else {
+        None
+    }
+}
+
+// Folds the constants of a sum or product into one, which goes last in a
+// sum and first in a product as `eval` puts them.
+fn fold(v: &[Box<Expr>], identity: Const, op: fn(Const, Const) -> Const) -> Option<(Const, Vec<Box<Expr>>)> {
+    let consts: Vec<Const> = v.iter().filter_map(|e| match **e {
+        Expr::Const(c) => Some(c),
+        _ => None,
+    }).collect();
+    if consts.len() < 2 {
+        return None;
+    }
+    let acc = consts.into_iter().fold(identity, op);
+    let rest = v.iter().filter(|e| !matches!(***e, Expr::Const(_))).cloned().collect();
+    Some((acc, rest))
+}
+
+fn add_constants(expr: &Expr) -> Option<Expr> {
+    match expr {
+        Expr::Sum(v) => fold(v, Const::Int(0), add_const).map(|(acc, mut rest)| {
+            if acc != Const::Int(0) {
+                rest.push(Box::new(Expr::Const(acc)));
+            }
+            sum(rest)
+        }),
+        _ => None,
+    }
+}
+
+fn multiply_constants(expr: &Expr) -> Option<Expr> {
+    match expr {
+        Expr::Prod(v) => fold(v, Const::Int(1), mul_const).map(|(acc, mut rest)| {
+            if acc != Const::Int(1) {
+                rest.insert(0, Box::new(Expr::Const(acc)));
+            }
+            product(rest)
+        }),
+        _ => None,
+    }
+}
+
+// A term as a coefficient times the rest: `3*x*y` is 3 and `x*y`, `-x` and
+// `(-x)*y` are -1 and `x` or `x*y`. Constants have no rest.
+fn split_term(expr: &Expr) -> Option<(Const, Expr)> {
+    match expr {
+        Expr::Const(_) => None,
+        Expr::Neg(e) => split_term(e).map(|(c, rest)| (negate_const(c), rest)),
+        Expr::Prod(v) => {
+            let mut coefficient = Const::Int(1);
+            let mut rest = Vec::new();
+            for mut e in v {
+                while let Expr::Neg(inner) = &**e {
+                    coefficient = negate_const(coefficient);
+                    e = inner;
+                }
+                match **e {
+                    Expr::Const(c) => coefficient = mul_const(coefficient, c),
+                    _ => rest.push(e.clone()),
+                }
+            }
+            // In the canonical order, so that `x*y` and `y*x` are alike.
+            rest.sort();
+            match rest.len() {
+                0 => None,
+                1 => Some((coefficient, *rest.pop().unwrap())),
+                _ => Some((coefficient, Expr::Prod(rest))),
+            }
+        },
+        e => Some((Const::Int(1), e.clone())),
+    }
+}
+
+// `coefficient * rest`, without a coefficient of 1.
+fn scale(coefficient: Const, rest: Expr) -> Option<Expr> {
+    match coefficient {
+        Const::Int(0) => None,
+        Const::Int(1) => Some(rest),
+        Const::Int(-1) => Some(Expr::Neg(Box::new(rest))),
+        c => {
+            let mut factors = vec![Box::new(Expr::Const(c))];
+            match rest {
+                Expr::Prod(v) => factors.extend(v),
+                rest => factors.push(Box::new(rest)),
+            }
+            Some(Expr::Prod(factors))
+        },
+    }
+}
+
+fn like_terms(expr: &Expr) -> Option<Expr> {
+    let v = match expr {
+        Expr::Sum(v) => v,
+        _ => return None,
+    };
+    // Each term is either kept as it is or added to the group of the first
+    // term with the same rest.
+    let mut groups: Vec<(Const, Expr, usize)> = Vec::new();
+    let mut order = Vec::new();
+    for e in v {
+        match split_term(e) {
+            Some((c, rest)) => match groups.iter_mut().position(|(_, r, _)| *r == rest) {
+                Some(i) => {
+                    groups[i].0 = add_const(groups[i].0, c);
+                    groups[i].2 += 1;
+                },
+                None => {
+                    order.push(Err(groups.len()));
+                    groups.push((c, rest, 1));
+                },
+            },
+            None => order.push(Ok(e.clone())),
+        }
+    }
+    if groups.iter().all(|(_, _, n)| *n == 1) {
+        return None;
+    }
+    let mut groups: Vec<Option<(Const, Expr, usize)>> = groups.into_iter().map(Some).collect();
+    let terms = order.into_iter().filter_map(|term| match term {
+        Ok(e) => Some(e),
+        Err(i) => {
+            let (c, rest, n) = groups[i].take().unwrap();
+            if n == 1 {
+                // Leave a term that was not combined as it was written.
+                return v.iter().find(|e| split_term(e).is_some_and(|(_, r)| r == rest)).cloned();
+            }
+            scale(c, rest).map(Box::new)
+        },
+    }).collect();
+    Some(sum(terms))
+}
+
+// A factor as a base and an exponent: `x^2` is `x` and 2, `1/x` is `x` and
+// -1. Constants are left to `multiply constants`.
+fn split_factor(expr: &Expr) -> Option<(Expr, Expr)> {
+    match expr {
+        Expr::Const(_) => None,
+        Expr::Pow(base, exp) => Some(((**base).clone(), (**exp).clone())),
+        Expr::Recipr(e) => Some(((**e).clone(), Expr::int(-1))),
+        e => Some((e.clone(), Expr::int(1))),
+    }
+}
+
+// A factor of a product with the exponents of the later factors with the
+// same base, or with none for a constant.
+struct Group {
+    base: Option<Expr>,
+    exps: Vec<Box<Expr>>,
+    original: Box<Expr>,
+}
+
+fn combine_powers(expr: &Expr) -> Option<Expr> {
+    let v = match expr {
+        Expr::Prod(v) => v,
+        _ => return None,
+    };
+    let mut groups: Vec<Group> = Vec::new();
+    let mut combined = false;
+    for e in v {
+        match split_factor(e) {
+            Some((base, exp)) => match groups.iter_mut().find(|g| g.base.as_ref() == Some(&base)) {
+                Some(group) => {
+                    group.exps.push(Box::new(exp));
+                    combined = true;
+                },
+                None => groups.push(Group{base: Some(base), exps: vec![Box::new(exp)], original: e.clone()}),
+            },
+            None => groups.push(Group{base: None, exps: Vec::new(), original: e.clone()}),
+        }
+    }
+    if !combined {
+        return None;
+    }
+    Some(product(groups.into_iter().map(|group| match group.base {
+        Some(base) if group.exps.len() > 1 => Box::new(Expr::Pow(Box::new(base), Box::new(Expr::Sum(group.exps)))),
+        _ => group.original,
+    }).collect()))
+}
+
+// `(x^a)^n = x^(a*n)` for an integer `n`.
+fn power_of_power(expr: &Expr) -> Option<Expr> {
+    match expr {
+        Expr::Pow(base, n) if matches!(**n, Expr::Const(Const::Int(_))) => match &**base {
+            Expr::Pow(x, a) => Some(Expr::Pow(x.clone(), Box::new(Expr::Prod(vec![a.clone(), n.clone()])))),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn power_of_one(expr: &Expr) -> Option<Expr> {
+    match expr {
+        Expr::Pow(base, exp) if **exp == Expr::int(1) => Some((**base).clone()),
+        _ => None,
+    }
+}
+
+fn power_of_zero(expr: &Expr) -> Option<Expr> {
+    match expr {
+        Expr::Pow(base, exp) if **exp == Expr::int(0) && **base != Expr::Const(Const::Undef) => Some(Expr::int(1)),
+        _ => None,
+    }
+}
+
+// Sorts factors in the canonical order of `Expr`, and terms by what they
+// multiply their coefficient with, keeping constants last.
+fn reorder(expr: &Expr) -> Option<Expr> {
+    let (mut sorted, v) = match expr {
+        Expr::Sum(v) => {
+            let mut sorted = v.clone();
+            sorted.sort_by_cached_key(|e| match split_term(e) {
+                Some((c, rest)) => (false, Some(rest), Some(c)),
+                None => (true, None, None),
+            });
+            (sorted, v)
+        },
+        Expr::Prod(v) => (v.clone(), v),
+        _ => return None,
+    };
+    if let Expr::Prod(_) = expr {
+        sorted.sort();
+    }
+    if sorted == *v {
+        None
+    } else if let Expr::Sum(_) = expr {
+        Some(Expr::Sum(sorted))
+    } else {
+        Some(Expr::Prod(sorted))
+    }
+}
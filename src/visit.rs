@@ -0,0 +1,181 @@
+use std::collections::BTreeSet;
+use std::ops::ControlFlow;
+
+use super::expr::Expr;
+
+/// Looks at every node of a tree, through `walk`.
+///
+/// ```
+/// use betadog::visit::{walk, Visitor};
+///
+/// // Counts the powers outside of function calls.
+/// struct Powers(usize);
+///
+/// impl Visitor for Powers {
+///     fn enter(&mut self, expr: &betadog::Expr) -> bool {
+///         if let betadog::Expr::Pow(..) = expr {
+///             self.0 += 1;
+///         }
+///         !matches!(expr, betadog::Expr::Call(..))
+///     }
+/// }
+///
+/// let mut powers = Powers(0);
+/// walk(&betadog::parse_str("x^2 + sin(y^2)*(z^3)^4").unwrap(), &mut powers);
+/// assert_eq!(powers.0, 3);
+/// ```
+pub trait Visitor {
+    /// Called on a node before its children, which are skipped if this
+    /// returns false.
+    fn enter(&mut self, _expr: &Expr) -> bool {
+        true
+    }
+
+    /// Called on a node after its children.
+    fn leave(&mut self, _expr: &Expr) {}
+}
+
+/// Like `Visitor`, changing the tree in place through `walk_mut`.
+pub trait VisitorMut {
+    /// Called on a node before its children, which are skipped if this
+    /// returns false. Changes are seen by the children visited next.
+    fn enter(&mut self, _expr: &mut Expr) -> bool {
+        true
+    }
+
+    /// Called on a node after its children.
+    fn leave(&mut self, _expr: &mut Expr) {}
+}
+
+/// Rebuilds a tree it takes by value, through `fold`.
+pub trait Folder {
+    /// Called on a node before its children: `Continue` goes on to fold
+    /// the children of the node returned, `Break` uses it as it is.
+    fn enter(&mut self, expr: Expr) -> ControlFlow<Expr, Expr> {
+        ControlFlow::Continue(expr)
+    }
+
+    /// Called on a node whose children are folded.
+    fn leave(&mut self, expr: Expr) -> Expr {
+        expr
+    }
+}
+
+/// The subexpressions directly below `expr`, in order.
+pub fn children(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::Sum(v) | Expr::Prod(v) | Expr::Call(_, v) => v.iter().map(|e| &**e).collect(),
+        Expr::Pow(lhs, rhs) => vec![lhs, rhs],
+        Expr::Neg(e) | Expr::Recipr(e) => vec![e],
+        Expr::Const(_) | Expr::Var(_) | Expr::Error => Vec::new(),
+    }
+}
+
+pub fn children_mut(expr: &mut Expr) -> Vec<&mut Expr> {
+    match expr {
+        Expr::Sum(v) | Expr::Prod(v) | Expr::Call(_, v) => v.iter_mut().map(|e| &mut **e).collect(),
+        Expr::Pow(lhs, rhs) => vec![lhs, rhs],
+        Expr::Neg(e) | Expr::Recipr(e) => vec![e],
+        Expr::Const(_) | Expr::Var(_) | Expr::Error => Vec::new(),
+    }
+}
+
+/// Calls `visitor` on every node of `expr`, parents before and after their
+/// children.
+pub fn walk<V: Visitor + ?Sized>(expr: &Expr, visitor: &mut V) {
+    if visitor.enter(expr) {
+        for e in children(expr) {
+            walk(e, visitor);
+        }
+    }
+    visitor.leave(expr);
+}
+
+pub fn walk_mut<V: VisitorMut + ?Sized>(expr: &mut Expr, visitor: &mut V) {
+    if visitor.enter(expr) {
+        for e in children_mut(expr) {
+            walk_mut(e, visitor);
+        }
+    }
+    visitor.leave(expr);
+}
+
+/// Rebuilds `expr` with `folder`, moving the nodes it keeps.
+pub fn fold<F: Folder + ?Sized>(expr: Expr, folder: &mut F) -> Expr {
+    let expr = match folder.enter(expr) {
+        ControlFlow::Continue(expr) => expr,
+        ControlFlow::Break(expr) => return expr,
+    };
+    let mut each = |v: Vec<Box<Expr>>| v.into_iter().map(|e| Box::new(fold(*e, folder))).collect();
+    let expr = match expr {
+        Expr::Sum(v) => Expr::Sum(each(v)),
+        Expr::Prod(v) => Expr::Prod(each(v)),
+        Expr::Call(fun, v) => Expr::Call(fun, each(v)),
+        Expr::Pow(lhs, rhs) => Expr::Pow(Box::new(fold(*lhs, folder)), Box::new(fold(*rhs, folder))),
+        Expr::Neg(e) => Expr::Neg(Box::new(fold(*e, folder))),
+        Expr::Recipr(e) => Expr::Recipr(Box::new(fold(*e, folder))),
+        e => e,
+    };
+    folder.leave(expr)
+}
+
+// Replaces whole subexpressions, outermost first.
+struct Subs<'a>(&'a [(Expr, Expr)]);
+
+impl Folder for Subs<'_> {
+    fn enter(&mut self, expr: Expr) -> ControlFlow<Expr, Expr> {
+        match self.0.iter().find(|(from, _)| *from == expr) {
+            Some((_, to)) => ControlFlow::Break(to.clone()),
+            None => ControlFlow::Continue(expr),
+        }
+    }
+}
+
+/// Replaces every subexpression of `expr` that is equal to the first
+/// expression of a pair with the second, all at once: the replacements are
+/// not searched again, so `x` and `y` can be swapped.
+///
+/// ```
+/// use betadog::{parse_str, visit::subs, Expr};
+///
+/// let expr = parse_str("sin(x + 1)*y + x").unwrap();
+/// let pairs = [(parse_str("x + 1").unwrap(), Expr::var("t")), (Expr::var("x"), Expr::var("y")), (Expr::var("y"), Expr::var("x"))];
+/// assert_eq!(subs(&expr, &pairs), parse_str("sin(t)*x + y").unwrap());
+/// ```
+pub fn subs(expr: &Expr, pairs: &[(Expr, Expr)]) -> Expr {
+    fold(expr.clone(), &mut Subs(pairs))
+}
+
+/// The names of the variables in `expr`, without function names.
+pub fn free_vars(expr: &Expr) -> BTreeSet<String> {
+    struct Vars(BTreeSet<String>);
+
+    impl Visitor for Vars {
+        fn enter(&mut self, expr: &Expr) -> bool {
+            if let Expr::Var(s) = expr {
+                self.0.insert(s.clone());
+            }
+            true
+        }
+    }
+
+    let mut vars = Vars(BTreeSet::new());
+    walk(expr, &mut vars);
+    vars.0
+}
+
+/// Whether `part` is `expr` or one of its subexpressions.
+pub fn contains(expr: &Expr, part: &Expr) -> bool {
+    expr == part || children(expr).into_iter().any(|e| contains(e, part))
+}
+
+/// The number of nodes on the longest path from `expr` to a leaf, which
+/// is 1 for a leaf.
+pub fn depth(expr: &Expr) -> usize {
+    1 + children(expr).into_iter().map(depth).max().unwrap_or(0)
+}
+
+/// The number of nodes in `expr`.
+pub fn node_count(expr: &Expr) -> usize {
+    1 + children(expr).into_iter().map(node_count).sum::<usize>()
+}